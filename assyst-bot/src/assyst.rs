@@ -2,7 +2,8 @@ use crate::{
     badtranslator::BadTranslator,
     caching::{
         local_caching::{Ratelimits, Replies, Reply},
-        persistent_caching::init_guild_caching,
+        persistent_caching::{cache_member, init_guild_caching},
+        wsi_cache::WsiCache,
     },
     command::{
         command::{
@@ -15,22 +16,23 @@ use crate::{
     },
     logger::{self, log_command_use},
     metrics::GlobalMetrics,
-    rest::{patreon::Patron, wsi::wsi_listen, HealthcheckResult},
-    util::{get_current_millis, get_guild_owner, is_guild_manager, regexes, Uptime},
+    rest::{patreon::Patron, wsi_pool::WsiPool, HealthcheckResult},
+    util::{
+        get_current_millis, get_guild_channel, get_guild_owner, is_guild_manager, regexes, Uptime,
+    },
 };
 
 use anyhow::bail;
 use assyst_common::{
     config::Config,
     consts::BOT_ID,
-    persistent_cache::{CacheRequestData, CacheResponseInner},
+    persistent_cache::{event_containers::MemberSend, CacheRequestData, CacheResponseInner},
     util::GuildId,
 };
 use assyst_database::Database;
 use async_recursion::async_recursion;
 use regex::Captures;
 use reqwest::Client as ReqwestClient;
-use shared::{fifo::FifoSend, job::JobResult};
 use std::{
     borrow::{Borrow, Cow},
     collections::{HashMap, HashSet},
@@ -129,8 +131,9 @@ pub struct Assyst {
     pub healthcheck_result: Mutex<(Instant, Vec<HealthcheckResult>)>,
     pub command_usage_diff: Mutex<Vec<(String, Vec<(usize, Instant)>)>>,
     pub web_download_urls: Mutex<Vec<String>>,
+    pub wsi: Arc<WsiPool>,
+    pub wsi_cache: WsiCache,
     cache_tx: UnboundedSender<(Sender<CacheResponseInner>, CacheRequestData)>,
-    wsi_tx: UnboundedSender<(Sender<JobResult>, FifoSend, usize)>,
 }
 impl Assyst {
     /// Create a new Assyst instance from a token. This method does NOT
@@ -155,9 +158,8 @@ impl Assyst {
             .map(Arc::new)
             .unwrap();
 
-        let config_clone = config.clone();
-        let (wsi_tx, wsi_rx) =
-            tokio::sync::mpsc::unbounded_channel::<(Sender<JobResult>, FifoSend, usize)>();
+        let wsi = WsiPool::new(config.wsi_backends());
+        let wsi_cache = WsiCache::new(&config.wsi_cache);
 
         let (cache_tx, cache_rx) =
             tokio::sync::mpsc::unbounded_channel::<(Sender<CacheResponseInner>, CacheRequestData)>(
@@ -180,8 +182,9 @@ impl Assyst {
             healthcheck_result: Mutex::new((Instant::now(), vec![])),
             command_usage_diff: Mutex::new(vec![]),
             cache_tx,
-            wsi_tx,
             web_download_urls: Mutex::new(vec![]),
+            wsi,
+            wsi_cache,
         };
         if assyst.config.disable_bad_translator {
             assyst.badtranslator.disable().await
@@ -191,9 +194,7 @@ impl Assyst {
             init_guild_caching(cache_rx).await;
         });
 
-        tokio::spawn(async move {
-            wsi_listen(wsi_rx, &config_clone.url.wsi.to_string()).await;
-        });
+        assyst.wsi.start();
 
         assyst.registry.register_commands();
         assyst
@@ -236,10 +237,6 @@ impl Assyst {
         Ok(())
     }
 
-    pub fn send_to_wsi(&self, sender: Sender<JobResult>, job: FifoSend, premium_level: usize) {
-        self.wsi_tx.send((sender, job, premium_level)).unwrap();
-    }
-
     pub fn send_to_cache(&self, sender: Sender<CacheResponseInner>, job: CacheRequestData) {
         self.cache_tx.send((sender, job)).unwrap();
     }
@@ -384,6 +381,20 @@ impl Assyst {
 
         let command_instance = self.registry.commands.get(command.calling_name).unwrap();
 
+        // messages carry the author's member, which is the only way members are updated
+        // without the privileged members intent. only commands read them, so other messages
+        // aren't worth a round trip to the cache
+        if let Some(member) = &message.member {
+            let member = MemberSend {
+                guild_id,
+                user_id: message.author.id.get(),
+                username: message.author.name.clone(),
+                nickname: member.nick.clone(),
+                roles: member.roles.iter().map(|r| r.get()).collect(),
+            };
+            tokio::spawn(cache_member(self.clone(), member));
+        }
+
         // checking if the command is disabled
         let is_guild_disabled = self
             .database
@@ -391,7 +402,7 @@ impl Assyst {
             .await;
 
         if is_guild_disabled {
-            let owner = get_guild_owner(&self.http, GuildId::new(guild_id)).await?;
+            let owner = get_guild_owner(self, GuildId::new(guild_id)).await?;
 
             if owner != message.author.id && !self.user_is_admin(context.author_id().get()) {
                 return Ok(());
@@ -399,10 +410,10 @@ impl Assyst {
         };
 
         if command_instance.nsfw {
-            let channel = self.http.channel(message.channel_id).await?.model().await?;
+            let channel = get_guild_channel(self, message.channel_id.get()).await?;
 
-            if let Some(_) = channel.guild_id {
-                if !channel.nsfw.unwrap_or(false) {
+            if let Some(channel) = channel {
+                if !channel.nsfw {
                     context
                         .reply_err("This command is limited to NSFW text channels only.")
                         .await?;
@@ -534,13 +545,9 @@ impl Assyst {
                 }
             }
             CommandAvailability::GuildOwner => {
-                let is_manager = is_guild_manager(
-                    &self.http,
-                    context.message.guild_id.unwrap(),
-                    context.message.author.id,
-                )
-                .await
-                .map_err(|_| CommandParseError::permission_validator_failed())?;
+                let is_manager = is_guild_manager(context, context.message.guild_id.unwrap())
+                    .await
+                    .map_err(|_| CommandParseError::permission_validator_failed())?;
 
                 let is_bot_admin = self.user_is_admin(context.author_id().get());
 
@@ -2,7 +2,7 @@ use assyst_common::consts::MESSAGE_EDIT_HANDLE_LIMIT;
 use assyst_common::util::{GuildId, MessageId};
 use std::sync::Arc;
 use std::{collections::HashMap, u64, usize};
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use twilight_model::channel::Message;
 use util::get_current_millis;
 
@@ -103,6 +103,8 @@ impl Replies {
 
 pub struct Reply {
     pub invocation_deleted: bool,
+    /// Notifies running jobs of this command when the invocation is deleted
+    deleted: watch::Sender<bool>,
     invocation: Arc<Message>,
     pub reply: Option<Arc<Message>>,
     expire: u64,
@@ -116,6 +118,7 @@ impl Reply {
             expire: get_current_millis() + MESSAGE_EDIT_HANDLE_LIMIT as u64,
             in_use: false,
             invocation_deleted: false,
+            deleted: watch::Sender::new(false),
         }
     }
     pub fn has_expired(&self) -> bool {
@@ -130,6 +133,10 @@ impl Reply {
     }
     pub fn set_invocation_deleted(&mut self) -> &mut Self {
         self.invocation_deleted = true;
+        self.deleted.send_replace(true);
         self
     }
+    pub fn watch_deleted(&self) -> watch::Receiver<bool> {
+        self.deleted.subscribe()
+    }
 }
@@ -1,2 +1,3 @@
 pub mod local_caching;
 pub mod persistent_caching;
+pub mod wsi_cache;
@@ -1,20 +1,31 @@
 use std::{
     collections::HashMap,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU64},
+        Arc,
+    },
     time::Duration,
 };
 
 use assyst_common::{
     consts::CACHE_PIPE,
+    handshake::{client_handshake, Handshake},
     ok_or_break,
     persistent_cache::{
-        guild_cache::TopGuilds, CacheError, CacheRequest, CacheRequestData, CacheResponse,
-        CacheResponseData, CacheResponseInner,
+        capabilities,
+        entity_cache::{CachedChannel, CachedGuild, CachedMember, CachedRole},
+        event_containers::MemberSend,
+        guild_cache::{ReadyGuildChanges, TopGuilds},
+        CacheError, CacheRequest, CacheRequestData, CacheResponse, CacheResponseData,
+        CacheResponseInner, FrameId, PROTOCOL_VERSION,
     },
     some_or_break, unwrap_enum_variant,
 };
 use bincode::{deserialize, serialize};
-use serenity::all::{GuildCreateEvent, GuildDeleteEvent, ReadyEvent};
+use serenity::all::{
+    ChannelDeleteEvent, GuildChannel, GuildCreateEvent, GuildDeleteEvent, GuildRoleDeleteEvent,
+    GuildUpdateEvent, ReadyEvent, Role,
+};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::UnixStream,
@@ -30,6 +41,8 @@ use twilight_model::gateway::payload::incoming::{GuildCreate, GuildDelete, Ready
 use crate::assyst::Assyst;
 
 static CONNECTED: AtomicBool = AtomicBool::new(false);
+/// Capabilities supported by both the bot and the connected cache server
+static CAPABILITIES: AtomicU64 = AtomicU64::new(0);
 
 pub async fn init_guild_caching(
     reciever: UnboundedReceiver<(Sender<CacheResponseInner>, CacheRequestData)>,
@@ -37,7 +50,7 @@ pub async fn init_guild_caching(
     let reciever = Arc::new(Mutex::new(reciever));
 
     loop {
-        let stream = match UnixStream::connect(CACHE_PIPE).await {
+        let mut stream = match UnixStream::connect(CACHE_PIPE).await {
             Ok(stream) => stream,
             Err(_) => {
                 sleep(Duration::from_secs(10)).await;
@@ -45,6 +58,18 @@ pub async fn init_guild_caching(
             }
         };
 
+        let handshake = Handshake::new(PROTOCOL_VERSION, capabilities::ALL);
+        match client_handshake(&mut stream, handshake).await {
+            Ok(capabilities) => {
+                CAPABILITIES.store(capabilities, std::sync::atomic::Ordering::Relaxed);
+            }
+            Err(e) => {
+                eprintln!("Failed to connect to the cache server: {}", e);
+                sleep(Duration::from_secs(10)).await;
+                continue;
+            }
+        }
+
         CONNECTED.store(true, std::sync::atomic::Ordering::Relaxed);
 
         let jobs = Arc::new(Mutex::new(
@@ -60,12 +85,17 @@ pub async fn init_guild_caching(
                 let len = ok_or_break!(r.read_u32().await);
                 let mut buf = vec![0; len as usize];
                 ok_or_break!(r.read_exact(&mut buf).await);
-                let deserialized = deserialize::<CacheResponse>(&buf).unwrap();
-                let id = deserialized.id();
+                let (id, response) = match deserialize::<CacheResponse>(&buf) {
+                    Ok(response) => (response.id(), response.data()),
+                    Err(_) => match deserialize::<FrameId>(&buf) {
+                        Ok(FrameId { id }) => (id, Err(CacheError::InvalidResponse)),
+                        Err(_) => break,
+                    },
+                };
                 let tx = jobs_clone.lock().await.remove(&id);
                 if let Some(tx) = tx {
-                    tx.send(deserialized.data())
-                        .expect("failed to send cache job result to sender");
+                    // the job may have timed out already
+                    let _ = tx.send(response);
                 }
             }
         });
@@ -109,6 +139,11 @@ pub async fn run_cache_job(assyst: Arc<Assyst>, job: CacheRequestData) -> CacheR
         return Err(CacheError::CacheServerDown);
     }
 
+    let required = job.required_capabilities();
+    if CAPABILITIES.load(std::sync::atomic::Ordering::Relaxed) & required != required {
+        return Err(CacheError::UnsupportedRequest);
+    }
+
     const TIME_LIMIT: Duration = Duration::from_secs(10);
 
     let (tx, rx) = oneshot::channel::<CacheResponseInner>();
@@ -127,15 +162,18 @@ pub async fn get_top_guilds(assyst: Arc<Assyst>) -> anyhow::Result<TopGuilds> {
     Ok(unwrap_enum_variant!(response, CacheResponseData::TopGuilds))
 }
 
-pub async fn get_new_guilds_from_ready(
+/// Returns how many guilds the READY added and removed
+///
+/// Guilds are only removed if the cache server restored them from a snapshot and the READY no longer contains them.
+pub async fn get_guild_changes_from_ready(
     assyst: Arc<Assyst>,
     event: ReadyEvent,
-) -> anyhow::Result<usize> {
+) -> anyhow::Result<ReadyGuildChanges> {
     let request = CacheRequestData::SendReadyEvent(event.into());
     let response = run_cache_job(assyst, request).await?;
     Ok(unwrap_enum_variant!(
         response,
-        CacheResponseData::TotalNewGuilds
+        CacheResponseData::ReadyGuilds
     ))
 }
 
@@ -171,3 +209,104 @@ pub async fn get_guild_count(assyst: Arc<Assyst>) -> anyhow::Result<usize> {
         CacheResponseData::TotalGuilds
     ))
 }
+
+pub async fn handle_guild_update_event(
+    assyst: Arc<Assyst>,
+    event: GuildUpdateEvent,
+) -> anyhow::Result<()> {
+    let request = CacheRequestData::SendGuildUpdate(event.into());
+    run_cache_job(assyst, request).await?;
+    Ok(())
+}
+
+pub async fn handle_channel_update(
+    assyst: Arc<Assyst>,
+    channel: GuildChannel,
+) -> anyhow::Result<()> {
+    let request = CacheRequestData::SendChannelUpdate(channel.into());
+    run_cache_job(assyst, request).await?;
+    Ok(())
+}
+
+pub async fn handle_channel_delete_event(
+    assyst: Arc<Assyst>,
+    event: ChannelDeleteEvent,
+) -> anyhow::Result<()> {
+    let request = CacheRequestData::SendChannelDelete(event.into());
+    run_cache_job(assyst, request).await?;
+    Ok(())
+}
+
+pub async fn handle_role_update(assyst: Arc<Assyst>, role: Role) -> anyhow::Result<()> {
+    let request = CacheRequestData::SendRoleUpdate(role.into());
+    run_cache_job(assyst, request).await?;
+    Ok(())
+}
+
+pub async fn handle_role_delete_event(
+    assyst: Arc<Assyst>,
+    event: GuildRoleDeleteEvent,
+) -> anyhow::Result<()> {
+    let request = CacheRequestData::SendRoleDelete(event.into());
+    run_cache_job(assyst, request).await?;
+    Ok(())
+}
+
+/// Stores a guild member that was fetched from the API, so it can be served from the cache next time
+pub async fn cache_member(assyst: Arc<Assyst>, member: MemberSend) -> anyhow::Result<()> {
+    let request = CacheRequestData::SendMember(member);
+    run_cache_job(assyst, request).await?;
+    Ok(())
+}
+
+pub async fn get_cached_guild(
+    assyst: Arc<Assyst>,
+    guild_id: u64,
+) -> anyhow::Result<Option<CachedGuild>> {
+    let request = CacheRequestData::GetGuild(guild_id);
+    let response = run_cache_job(assyst, request).await?;
+    Ok(unwrap_enum_variant!(response, CacheResponseData::Guild))
+}
+
+pub async fn get_cached_channel(
+    assyst: Arc<Assyst>,
+    channel_id: u64,
+) -> anyhow::Result<Option<CachedChannel>> {
+    let request = CacheRequestData::GetChannel(channel_id);
+    let response = run_cache_job(assyst, request).await?;
+    Ok(unwrap_enum_variant!(response, CacheResponseData::Channel))
+}
+
+pub async fn get_cached_guild_roles(
+    assyst: Arc<Assyst>,
+    guild_id: u64,
+) -> anyhow::Result<Option<Vec<CachedRole>>> {
+    let request = CacheRequestData::GetGuildRoles(guild_id);
+    let response = run_cache_job(assyst, request).await?;
+    Ok(unwrap_enum_variant!(
+        response,
+        CacheResponseData::GuildRoles
+    ))
+}
+
+pub async fn get_cached_guild_owner(
+    assyst: Arc<Assyst>,
+    guild_id: u64,
+) -> anyhow::Result<Option<u64>> {
+    let request = CacheRequestData::GetGuildOwner(guild_id);
+    let response = run_cache_job(assyst, request).await?;
+    Ok(unwrap_enum_variant!(
+        response,
+        CacheResponseData::GuildOwner
+    ))
+}
+
+pub async fn get_cached_member(
+    assyst: Arc<Assyst>,
+    guild_id: u64,
+    user_id: u64,
+) -> anyhow::Result<Option<CachedMember>> {
+    let request = CacheRequestData::GetMember { guild_id, user_id };
+    let response = run_cache_job(assyst, request).await?;
+    Ok(unwrap_enum_variant!(response, CacheResponseData::Member))
+}
@@ -0,0 +1,347 @@
+use assyst_common::config;
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// SHA-256 hash of everything that determines the result of a job
+pub type CacheKey = [u8; 32];
+
+/// Hashes a serialized job, which contains its input, operation and arguments, along with the premium tier it runs
+/// with, since WSI applies different limits to every tier
+pub fn cache_key(job: &[u8], tier: usize) -> CacheKey {
+    let mut hasher = Sha256::new();
+    hasher.update(tier.to_le_bytes());
+    hasher.update(job);
+    hasher.finalize().into()
+}
+
+fn to_hex(key: &CacheKey) -> String {
+    key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(name: &str) -> Option<CacheKey> {
+    if name.len() != 64 {
+        return None;
+    }
+
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(name.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Sizes of cached results in order of use, so that the least recently used ones can be evicted to stay within a budget
+struct Lru {
+    budget: usize,
+    size: usize,
+    tick: u64,
+    entries: HashMap<CacheKey, (u64, usize)>,
+    order: BTreeMap<u64, CacheKey>,
+}
+impl Lru {
+    fn new(budget: usize) -> Self {
+        Lru {
+            budget,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn fits(&self, size: usize) -> bool {
+        size <= self.budget
+    }
+
+    /// Marks a result as used, returning whether it exists
+    fn touch(&mut self, key: &CacheKey) -> bool {
+        let Some((tick, _)) = self.entries.get_mut(key) else {
+            return false;
+        };
+
+        self.order.remove(tick);
+        self.tick += 1;
+        *tick = self.tick;
+        self.order.insert(self.tick, *key);
+        true
+    }
+
+    /// Adds a result that fits in the budget, returning the results that were evicted to make room for it
+    fn insert(&mut self, key: CacheKey, size: usize) -> Vec<CacheKey> {
+        self.remove(&key);
+
+        let mut evicted = Vec::new();
+        while self.size + size > self.budget {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            let (_, oldest_size) = self.entries.remove(&oldest).unwrap();
+            self.size -= oldest_size;
+            evicted.push(oldest);
+        }
+
+        self.tick += 1;
+        self.entries.insert(key, (self.tick, size));
+        self.order.insert(self.tick, key);
+        self.size += size;
+
+        evicted
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some((tick, size)) = self.entries.remove(key) {
+            self.order.remove(&tick);
+            self.size -= size;
+        }
+    }
+}
+
+struct Memory {
+    lru: Lru,
+    results: HashMap<CacheKey, Bytes>,
+}
+
+/// Where a cached result was found
+pub enum CacheTier {
+    Memory,
+    Disk,
+}
+impl CacheTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheTier::Memory => "memory",
+            CacheTier::Disk => "disk",
+        }
+    }
+}
+
+/// Results of WSI jobs, keyed by a hash of the job
+///
+/// Recently used results are kept in memory. Once they're evicted from memory they're moved to disk,
+/// where they're kept across restarts until they're evicted from there as well.
+pub struct WsiCache {
+    memory: Mutex<Memory>,
+    disk: Mutex<Lru>,
+    disk_path: PathBuf,
+}
+impl WsiCache {
+    pub fn new(config: &config::WsiCache) -> Self {
+        let disk_path = PathBuf::from(&*config.disk_path);
+        let mut disk = Lru::new(config.disk_bytes);
+
+        if config.disk_bytes > 0 {
+            if let Err(e) = std::fs::create_dir_all(&disk_path) {
+                eprintln!("Failed to create WSI cache directory: {:?}", e);
+            }
+
+            let mut files = std::fs::read_dir(&disk_path)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter_map(|entry| {
+                    let path = entry.path();
+                    // results that were still being written when the bot stopped
+                    if path.extension() == Some("tmp".as_ref()) {
+                        let _ = std::fs::remove_file(path);
+                        return None;
+                    }
+
+                    let key = from_hex(entry.file_name().to_str()?)?;
+                    let metadata = entry.metadata().ok()?;
+                    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    Some((modified, key, metadata.len() as usize))
+                })
+                .collect::<Vec<_>>();
+            // oldest first, so that the most recently written results are the last to be evicted
+            files.sort_unstable();
+
+            for (_, key, size) in files {
+                let evicted = if disk.fits(size) {
+                    disk.insert(key, size)
+                } else {
+                    vec![key]
+                };
+
+                for key in evicted {
+                    let _ = std::fs::remove_file(disk_path.join(to_hex(&key)));
+                }
+            }
+        }
+
+        WsiCache {
+            memory: Mutex::new(Memory {
+                lru: Lru::new(config.memory_bytes),
+                results: HashMap::new(),
+            }),
+            disk: Mutex::new(disk),
+            disk_path,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.memory.lock().unwrap().lru.budget > 0 || self.disk.lock().unwrap().budget > 0
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.disk_path.join(to_hex(key))
+    }
+
+    pub async fn get(&self, key: &CacheKey) -> Option<(Bytes, CacheTier)> {
+        {
+            let mut memory = self.memory.lock().unwrap();
+            if memory.lru.touch(key) {
+                return Some((memory.results[key].clone(), CacheTier::Memory));
+            }
+        }
+
+        if !self.disk.lock().unwrap().touch(key) {
+            return None;
+        }
+
+        let result = match tokio::fs::read(self.path(key)).await {
+            Ok(result) => Bytes::from(result),
+            Err(_) => {
+                self.disk.lock().unwrap().remove(key);
+                return None;
+            }
+        };
+
+        self.insert(*key, result.clone()).await;
+        Some((result, CacheTier::Disk))
+    }
+
+    /// Caches a result in memory, moving the results that are evicted to make room for it to disk
+    pub async fn insert(&self, key: CacheKey, result: Bytes) {
+        let evicted = {
+            let mut memory = self.memory.lock().unwrap();
+
+            if memory.lru.fits(result.len()) {
+                let evicted = memory.lru.insert(key, result.len());
+                memory.results.insert(key, result);
+
+                evicted
+                    .into_iter()
+                    .map(|key| {
+                        let result = memory.results.remove(&key).unwrap();
+                        (key, result)
+                    })
+                    .collect::<Vec<_>>()
+            } else {
+                vec![(key, result)]
+            }
+        };
+
+        for (key, result) in evicted {
+            self.insert_disk(key, result).await;
+        }
+    }
+
+    async fn insert_disk(&self, key: CacheKey, result: Bytes) {
+        {
+            let mut disk = self.disk.lock().unwrap();
+
+            // results that were read from disk are still stored there
+            if !disk.fits(result.len()) || disk.touch(&key) {
+                return;
+            }
+        }
+
+        // write to a temporary file first, so that the result is never read while partially written
+        let path = self.path(&key);
+        let temp_path = path.with_extension("tmp");
+        let written = match tokio::fs::write(&temp_path, &result).await {
+            Ok(_) => tokio::fs::rename(&temp_path, &path).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = written {
+            eprintln!("Failed to write WSI result to the cache: {:?}", e);
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return;
+        }
+
+        // only tracked once the file exists, so that a failed read can't untrack a file that is still being written
+        let evicted = self.disk.lock().unwrap().insert(key, result.len());
+
+        for key in evicted {
+            let _ = tokio::fs::remove_file(self.path(&key)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: u8) -> CacheKey {
+        [n; 32]
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut lru = Lru::new(30);
+
+        assert!(lru.insert(key(1), 10).is_empty());
+        assert!(lru.insert(key(2), 10).is_empty());
+        assert!(lru.insert(key(3), 10).is_empty());
+
+        // 1 is now more recently used than 2
+        assert!(lru.touch(&key(1)));
+        assert_eq!(lru.insert(key(4), 10), vec![key(2)]);
+        assert_eq!(lru.insert(key(5), 20), vec![key(3), key(1)]);
+
+        assert!(!lru.touch(&key(2)));
+        assert!(lru.touch(&key(4)));
+        assert_eq!(lru.size, 30);
+    }
+
+    #[test]
+    fn replaces_and_removes_entries() {
+        let mut lru = Lru::new(30);
+
+        lru.insert(key(1), 10);
+        lru.insert(key(1), 25);
+        assert_eq!(lru.size, 25);
+        assert_eq!(lru.entries.len(), 1);
+        assert_eq!(lru.order.len(), 1);
+
+        lru.remove(&key(1));
+        lru.remove(&key(1));
+        assert_eq!(lru.size, 0);
+        assert!(lru.order.is_empty());
+        assert!(!lru.touch(&key(1)));
+    }
+
+    #[test]
+    fn only_fits_within_budget() {
+        let lru = Lru::new(30);
+        assert!(lru.fits(30));
+        assert!(!lru.fits(31));
+        assert!(!Lru::new(0).fits(1));
+    }
+
+    #[test]
+    fn removes_partial_writes_on_startup() {
+        let dir = std::env::temp_dir().join(format!("wsi-cache-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let temp_path = dir.join(to_hex(&key(1))).with_extension("tmp");
+        std::fs::write(&temp_path, [0; 10]).unwrap();
+        std::fs::write(dir.join(to_hex(&key(2))), [0; 10]).unwrap();
+
+        let cache = WsiCache::new(&config::WsiCache {
+            memory_bytes: 0,
+            disk_bytes: 100,
+            disk_path: dir.to_str().unwrap().into(),
+        });
+
+        assert!(!temp_path.exists());
+        assert!(cache.disk.lock().unwrap().touch(&key(2)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -11,7 +11,7 @@ use crate::{
         bt::{bad_translate, TranslateResult, Translation},
         wombo::{WomboResponse, WomboResponseResult, WomboStyle},
     },
-    util::{codeblock, ensure_guild_manager, normalize_emojis},
+    util::{codeblock, ensure_guild_manager, get_guild_roles, normalize_emojis},
 };
 use anyhow::{bail, Context as _};
 use assyst_common::{
@@ -269,13 +269,7 @@ pub async fn run_color_command(
                     .reply_with_text("Successfully added color role")
                     .await?;
             } else {
-                let guild_roles = context
-                    .assyst
-                    .http
-                    .roles(GuildId::new(guild_id))
-                    .await?
-                    .models()
-                    .await?;
+                let guild_roles = get_guild_roles(&context.assyst, GuildId::new(guild_id)).await?;
 
                 let mut roles = Vec::new();
 
@@ -303,7 +297,7 @@ pub async fn run_color_command(
                         .any(|(name, _)| role.name.eq(name));
 
                     if is_color_role {
-                        roles.push((role.name, role.id.get() as i64));
+                        roles.push((role.name, role.id as i64));
                     }
                 }
 
@@ -360,14 +354,20 @@ pub async fn run_color_command(
 
             let user_id = context.message.author.id;
 
-            let user_roles = context
-                .assyst
-                .http
-                .guild_member(GuildId::new(guild_id), user_id)
-                .await?
-                .model()
-                .await?
-                .roles;
+            // the invoking member is part of the message, so it usually doesn't need to be fetched
+            let user_roles = match &context.message.member {
+                Some(member) => member.roles.clone(),
+                None => {
+                    context
+                        .assyst
+                        .http
+                        .guild_member(GuildId::new(guild_id), user_id)
+                        .await?
+                        .model()
+                        .await?
+                        .roles
+                }
+            };
 
             let mut roles_without_colors = user_roles
                 .iter()
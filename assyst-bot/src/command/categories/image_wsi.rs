@@ -14,16 +14,20 @@ use crate::{
     rest,
     util::{codeblock, get_buffer_filetype},
 };
-use anyhow::{anyhow, Context as _};
+use anyhow::{anyhow, bail, ensure, Context as _};
 use assyst_common::consts;
 use bytes::Bytes;
 use lazy_static::lazy_static;
 use shared::query_params::ResizeMethod;
 use std::time::Duration;
 use std::{borrow::Cow, sync::Arc};
+use tokio::time::timeout;
 
 const CATEGORY_NAME: &str = "image (wsi)";
 
+/// Maximum time an entire pipe may take, across all of its steps
+const PIPE_TIME_LIMIT: Duration = Duration::from_secs(60 * 5);
+
 lazy_static! {
     pub static ref _3D_ROTATE_COMMAND: Command = CommandBuilder::new("3drotate")
         .alias("3d")
@@ -312,6 +316,18 @@ lazy_static! {
         .cooldown(Duration::from_secs(4))
         .category(CATEGORY_NAME)
         .build();
+    pub static ref PIPE_COMMAND: Command = CommandBuilder::new("pipe")
+        .alias("chain")
+        .arg(Argument::ImageBuffer)
+        .arg(Argument::StringRemaining)
+        .public()
+        .description("run an image through multiple operations, feeding the output of each into the next")
+        .example(format!("{} magik | caption \"hi\" | gif_speed 2", consts::Y21))
+        .example(format!("{} flip | blur 5 | jpeg", consts::Y21))
+        .usage("[image] [operation <args>] | [operation <args>] | ...")
+        .cooldown(Duration::from_secs(10))
+        .category(CATEGORY_NAME)
+        .build();
     pub static ref PRINTER_COMMAND: Command = CommandBuilder::new("printer")
         .arg(Argument::ImageBuffer)
         .public()
@@ -1376,6 +1392,341 @@ pub async fn run_pixelate_command(
     Ok(())
 }
 
+/// A single operation of a pipe, along with the arguments passed to it
+struct PipeStep {
+    name: String,
+    args: Vec<String>,
+}
+
+/// Splits the input of the pipe command into its steps.
+/// Arguments can be wrapped in double quotes to include spaces or pipes.
+fn parse_pipe_steps(input: &str) -> anyhow::Result<Vec<PipeStep>> {
+    let mut steps = vec![];
+    let mut words: Vec<String> = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+
+    let mut end_step = |words: &mut Vec<String>| -> anyhow::Result<()> {
+        let mut parts = std::mem::take(words).into_iter();
+        let name = parts
+            .next()
+            .with_context(|| format!("Step {} of the pipe is empty", steps.len() + 1))?;
+
+        steps.push(PipeStep {
+            name: name.to_ascii_lowercase(),
+            args: parts.collect(),
+        });
+        Ok(())
+    };
+
+    for c in input.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            '|' if !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+                end_step(&mut words)?;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    ensure!(!quoted, "The pipe contains an unterminated quote");
+
+    if in_word {
+        words.push(word);
+    }
+    end_step(&mut words)?;
+
+    Ok(steps)
+}
+
+/// Operations that can be used as a step of a pipe, by the name of their command
+const PIPE_OPERATIONS: &[&str] = &[
+    "ahshit",
+    "aprilfools",
+    "blur",
+    "caption",
+    "deepfry",
+    "fisheye",
+    "flip",
+    "flop",
+    "frameshift",
+    "ghost",
+    "gifloop",
+    "gifmagik",
+    "gifscramble",
+    "gifspeed",
+    "globe",
+    "grayscale",
+    "invert",
+    "jpeg",
+    "magik",
+    "neon",
+    "paint",
+    "pixelate",
+    "printer",
+    "rainbow",
+    "reverse",
+    "rotate",
+    "setloop",
+    "speechbubble",
+    "spin",
+    "spread",
+    "swirl",
+    "uncaption",
+    "wall",
+    "wave",
+    "wormhole",
+    "zoom",
+    "zoomblur",
+];
+
+/// A pipe step resolved to the command it runs, with its arguments checked against that command
+struct ResolvedPipeStep {
+    command: &'static Command,
+    args: Vec<ParsedArgument>,
+}
+
+/// Resolves a step to its command using the same names and aliases as invoking it directly,
+/// then parses its arguments the way the command would, filling in the same defaults.
+/// Underscores in the name are ignored, so `gif_speed` resolves to `gifspeed`.
+fn resolve_pipe_step(context: &Context, step: &PipeStep) -> anyhow::Result<ResolvedPipeStep> {
+    let registry = &context.assyst.registry;
+    let command = registry
+        .get_command_from_name_or_alias(&step.name)
+        .or_else(|| registry.get_command_from_name_or_alias(&step.name.replace('_', "")))
+        .filter(|command| PIPE_OPERATIONS.contains(&command.name))
+        .with_context(|| format!("`{}` is not an operation that can be piped", step.name))?;
+
+    let mut given = step.args.iter();
+    let mut args = vec![];
+
+    // the first argument of every pipeable command is the image, which the pipe supplies
+    for arg in command.args.iter().skip(1) {
+        let (inner, default) = match arg {
+            Argument::Optional(inner) => (&**inner, Some(ParsedArgument::Nothing)),
+            Argument::OptionalWithDefault(inner, default) => {
+                (&**inner, Some(ParsedArgument::Text((*default).to_owned())))
+            }
+            other => (other, None),
+        };
+
+        let value = match inner {
+            Argument::StringRemaining => {
+                let text = given.by_ref().cloned().collect::<Vec<_>>().join(" ");
+                (!text.is_empty()).then_some(ParsedArgument::Text(text))
+            }
+            _ => given
+                .next()
+                .map(|value| parse_pipe_argument(&step.name, inner, value))
+                .transpose()?,
+        };
+
+        match value.or(default) {
+            Some(value) => args.push(value),
+            None => bail!(
+                "`{}` is missing an argument (usage: `{}`)",
+                step.name,
+                command.metadata.usage
+            ),
+        }
+    }
+
+    ensure!(
+        given.next().is_none(),
+        "`{}` was given too many arguments (usage: `{}`)",
+        step.name,
+        command.metadata.usage
+    );
+
+    Ok(ResolvedPipeStep { command, args })
+}
+
+/// Parses a single argument of a pipe step as the given argument type
+fn parse_pipe_argument(step: &str, arg: &Argument, value: &str) -> anyhow::Result<ParsedArgument> {
+    match arg {
+        Argument::Integer => {
+            ensure!(
+                value.parse::<i64>().is_ok(),
+                "`{}` expects a whole number, but got `{}`",
+                step,
+                value
+            );
+        }
+        Argument::Decimal => {
+            ensure!(
+                value.parse::<f64>().is_ok(),
+                "`{}` expects a number, but got `{}`",
+                step,
+                value
+            );
+        }
+        Argument::Choice(choices) => {
+            let choice = choices.iter().find(|&&x| x == value).with_context(|| {
+                format!(
+                    "`{}` expects one of {}, but got `{}`",
+                    step,
+                    choices.join(", "),
+                    value
+                )
+            })?;
+            return Ok(ParsedArgument::Choice(choice));
+        }
+        Argument::String => {}
+        _ => bail!(
+            "`{}` takes an argument that can't be passed in a pipe",
+            step
+        ),
+    }
+
+    Ok(ParsedArgument::Text(value.to_owned()))
+}
+
+/// Runs a single resolved step of a pipe through its WSI operation
+async fn run_pipe_step(
+    context: &Context,
+    step: &ResolvedPipeStep,
+    image: Bytes,
+) -> anyhow::Result<Bytes> {
+    let assyst = context.assyst.clone();
+    let user_id = context.author_id();
+    let args = &step.args;
+    let name = step.command.name;
+
+    // arguments were validated against the command when the step was resolved
+    let result = match name {
+        "ahshit" => wsi::ahshit(assyst, image, user_id).await,
+        "aprilfools" => wsi::aprilfools(assyst, image, user_id).await,
+        "blur" => wsi::blur(assyst, image, user_id, args[0].as_text()).await,
+        "caption" => wsi::caption(assyst, image, user_id, args[0].as_text()).await,
+        "deepfry" => wsi::deepfry(assyst, image, user_id).await,
+        "fisheye" => wsi::fisheye(assyst, image, user_id).await,
+        "flip" => wsi::flip(assyst, image, user_id).await,
+        "flop" => wsi::flop(assyst, image, user_id).await,
+        "frameshift" => wsi::frame_shift(assyst, image, user_id).await,
+        "ghost" => wsi::ghost(assyst, image, user_id, args[0].as_text()).await,
+        "gifloop" => wsi::gif_loop(assyst, image, user_id).await,
+        "gifmagik" => wsi::gif_magik(assyst, image, user_id).await,
+        "gifscramble" => wsi::gif_scramble(assyst, image, user_id).await,
+        "gifspeed" => wsi::gif_speed(assyst, image, user_id, args[0].maybe_text()).await,
+        "globe" => wsi::globe(assyst, image, user_id).await,
+        "grayscale" => wsi::grayscale(assyst, image, user_id).await,
+        "invert" => wsi::invert(assyst, image, user_id).await,
+        "jpeg" => wsi::jpeg(assyst, image, user_id).await,
+        "magik" => wsi::magik(assyst, image, user_id).await,
+        "neon" => {
+            let radius = args[0].as_text().parse::<i64>()?.clamp(1, 20) as usize;
+            wsi::neon(assyst, image, user_id, radius).await
+        }
+        "paint" => wsi::paint(assyst, image, user_id).await,
+        "pixelate" => {
+            let downscaled_height = args[0]
+                .maybe_text()
+                .map(|x| x.parse::<usize>())
+                .transpose()
+                .with_context(|| format!("`{}` expects a positive number of pixels", name))?;
+            wsi::pixelate(assyst, image, user_id, downscaled_height).await
+        }
+        "printer" => wsi::printer(assyst, image, user_id).await,
+        "rainbow" => wsi::rainbow(assyst, image, user_id).await,
+        "reverse" => wsi::reverse(assyst, image, user_id).await,
+        "rotate" => wsi::rotate(assyst, image, user_id, args[0].as_text()).await,
+        "setloop" => {
+            let looping = args[0].as_choice() == "on";
+            wsi::set_loop(assyst, image, user_id, looping).await
+        }
+        "speechbubble" => wsi::speechbubble(assyst, image, user_id).await,
+        "spin" => wsi::spin(assyst, image, user_id).await,
+        "spread" => wsi::spread(assyst, image, user_id).await,
+        "swirl" => wsi::swirl(assyst, image, user_id).await,
+        "uncaption" => {
+            let lines = args[0].maybe_text().map(String::from);
+            wsi::uncaption(assyst, image, user_id, lines).await
+        }
+        "wall" => wsi::wall(assyst, image, user_id).await,
+        "wave" => wsi::wave(assyst, image, user_id).await,
+        "wormhole" => wsi::wormhole(assyst, image, user_id).await,
+        "zoom" => wsi::zoom(assyst, image, user_id).await,
+        "zoomblur" => {
+            let factor = args[0].as_text().parse::<f64>()?;
+            wsi::zoom_blur(assyst, image, user_id, factor).await
+        }
+        other => bail!("`{}` is not an operation that can be piped", other),
+    };
+
+    Ok(result?)
+}
+
+pub async fn run_pipe_command(
+    context: Arc<Context>,
+    args: Vec<ParsedArgument>,
+    _flags: ParsedFlags,
+) -> CommandResult {
+    let image = args[0].as_bytes();
+    let steps = parse_pipe_steps(args[1].as_text())?;
+    ensure!(
+        steps.len() <= consts::MAX_CHAIN_LENGTH,
+        "A pipe can have at most {} steps",
+        consts::MAX_CHAIN_LENGTH
+    );
+
+    // resolve every step up front so a typo in the last step doesn't waste the ones before it
+    let resolved = steps
+        .iter()
+        .enumerate()
+        .map(|(index, step)| {
+            resolve_pipe_step(&context, step).map_err(|e| anyhow!("Step {}: {}", index + 1, e))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    context.reply_with_text("processing...").await?;
+
+    let chain = async {
+        let mut current = image;
+        for (index, (step, resolved)) in steps.iter().zip(&resolved).enumerate() {
+            current = run_pipe_step(&context, resolved, current)
+                .await
+                .map_err(|e| anyhow!("Step {} (`{}`) failed: {}", index + 1, step.name, e))?;
+
+            ensure!(
+                current.len() <= consts::WORKING_FILESIZE_LIMIT_BYTES,
+                "The output of step {} (`{}`) exceeded the maximum file size limit of {}MB",
+                index + 1,
+                step.name,
+                consts::WORKING_FILESIZE_LIMIT_BYTES / 1000 / 1000
+            );
+        }
+        Ok::<_, anyhow::Error>(current)
+    };
+
+    let result = timeout(PIPE_TIME_LIMIT, chain).await.map_err(|_| {
+        anyhow!(
+            "The pipe took longer than {} minutes to finish",
+            PIPE_TIME_LIMIT.as_secs() / 60
+        )
+    })??;
+
+    let format = get_buffer_filetype(&result).unwrap_or_else(|| "png");
+    context.reply_with_image(format, result).await?;
+    Ok(())
+}
+
 pub async fn run_printer_command(
     context: Arc<Context>,
     args: Vec<ParsedArgument>,
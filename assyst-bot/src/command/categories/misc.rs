@@ -20,7 +20,7 @@ use crate::{
     util::{
         bytes_to_readable, codeblock, ensure_same_guild, exec_sync, extract_page_title,
         format_discord_timestamp, format_time, generate_list, generate_table, get_buffer_filetype,
-        get_memory_usage, parse_codeblock,
+        get_guild_roles, get_memory_usage, is_same_guild, parse_codeblock,
     },
 };
 use crate::{
@@ -606,12 +606,36 @@ pub async fn run_wsi_stats_command(
     _: Vec<ParsedArgument>,
     _flags: ParsedFlags,
 ) -> CommandResult {
-    let response = wsi::stats(context.assyst.clone()).await?;
+    let mut output = String::new();
 
-    let output = format!(
-        "**Current Requests:** {}\n**Total Workers:** {}",
-        response.current_requests, response.total_workers
-    );
+    for (index, backend) in context.assyst.wsi.stats().into_iter().enumerate() {
+        let status = if !backend.connected {
+            "disconnected"
+        } else if backend.ejected {
+            "ejected"
+        } else {
+            "healthy"
+        };
+
+        let workers = match wsi::stats(context.assyst.clone(), index).await {
+            Ok(stats) => format!(
+                "**Current Requests:** {}\n**Total Workers:** {}",
+                stats.current_requests, stats.total_workers
+            ),
+            Err(e) => format!("**Error:** {}", e),
+        };
+
+        output += &format!(
+            "**Backend {}** (tier {}+, {})\n{}\n**In Flight:** {}\n**Completed:** {}\n**Failed:** {}\n\n",
+            index + 1,
+            backend.min_tier,
+            status,
+            workers,
+            backend.in_flight,
+            backend.completed,
+            backend.failed
+        );
+    }
 
     context.reply_with_text(output).await?;
     Ok(())
@@ -1294,36 +1318,13 @@ pub async fn run_blacklist_command(
             // validate that the provided id exists for the provided type
             match r#type {
                 "channel" => {
-                    let channels = context
-                        .assyst
-                        .http
-                        .guild_channels(Id::new(guild_id))
-                        .await?
-                        .model()
-                        .await?;
-                    if !channels
-                        .iter()
-                        .map(|x| x.id.get())
-                        .collect::<Vec<u64>>()
-                        .contains(&id)
-                    {
+                    if !is_same_guild(&context.assyst, id, guild_id).await.unwrap_or(false) {
                         bail!("No channel exists with this ID in this guild");
                     }
                 }
                 "role" => {
-                    let roles = context
-                        .assyst
-                        .http
-                        .roles(Id::new(guild_id))
-                        .await?
-                        .model()
-                        .await?;
-                    if !roles
-                        .iter()
-                        .map(|x| x.id.get())
-                        .collect::<Vec<u64>>()
-                        .contains(&id)
-                    {
+                    let roles = get_guild_roles(&context.assyst, Id::new(guild_id)).await?;
+                    if !roles.iter().any(|x| x.id == id) {
                         bail!("No role exists with this ID in this guild");
                     }
                 }
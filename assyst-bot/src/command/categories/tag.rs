@@ -1,23 +1,35 @@
-use std::{convert::TryInto, future::IntoFuture, sync::Arc, time::Duration};
+use std::{convert::TryInto, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, bail, ensure, Context as _};
 use assyst_common::{
     consts,
-    eval::FakeEvalImageResponse,
-    util::{mention_to_id, UserId},
+    filetype::Type,
+    persistent_cache::{entity_cache::CachedMember, event_containers::MemberSend},
+    util::{mention_to_id, MessageId, UserId},
 };
 use assyst_database::Tag;
 use assyst_tag as tag;
+use bytes::Bytes;
 use lazy_static::lazy_static;
 use std::fmt::Write;
-use tag::ParseResult;
+use tag::{MessageOptions, ParseResult, ReplyMode, Request, Response, Session, Step};
+use twilight_model::{
+    channel::message::embed::{Embed, EmbedField, EmbedImage},
+    id::Id,
+    user::User,
+};
 
 use crate::{
+    assyst::Assyst,
+    caching::persistent_caching::{
+        cache_member, get_cached_channel, get_cached_guild, get_cached_member,
+    },
     command::{
         command::{
             Argument, Command, CommandAvailability, CommandBuilder, ParsedArgument, ParsedFlags,
         },
         context::Context,
+        messagebuilder::MessageBuilder,
         parse::image_lookups::previous_message_attachment,
         registry::CommandResult,
     },
@@ -28,6 +40,8 @@ use crate::{
 
 const CATEGORY_NAME: &str = "misc";
 const DEFAULT_LIST_COUNT: i64 = 10;
+const SUGGESTION_COUNT: i64 = 3;
+const GLOBAL_TAG_PREFIX: &str = "@global/";
 const DESCRIPTION: &str = r#"
 -t <name>                    :: Look up a tag by its name and respond with its contents
 -t create <name> <content>   :: Create a tag with the given name and content
@@ -35,6 +49,24 @@ const DESCRIPTION: &str = r#"
 -t edit <name> <content>     :: Edit a tag by its name and new content
 -t list [<page, default=0>]  :: List tags created in this guild
 -t info <name>               :: Get information about a tag
+-t search <query>            :: Search tags by name and content
+-t top                       :: List the most used tags in this guild
+-t publish <name>            :: Publish a tag, so it can be used in any guild as @global/<name>
+-t unpublish <name>          :: Remove a tag from the global namespace
+-t debug <name> [args]       :: Run a tag and attach a trace of every subtag it invoked
+-t alias <alias> <name>      :: Create an alias for an existing tag
+-t transfer <name> <@user>   :: Transfer ownership of a tag to another user
+-t history <name>            :: List previous revisions of a tag
+-t revert <name> <revision>  :: Revert a tag to a previous revision
+
+Tags can respond with an embed using {embed:title|description}, {embedfield:name|value|inline},
+{embedcolor:#hex} and {embedimage:url}. {reply} replies to the invocation and pings you,
+{reply:message id} replies to another message without pinging its author,
+{nomention} sends the response without replying.
+
+Server information is available through {guildname}, {guildid}, {membercount} and {channelname:id}.
+{username:id}, {nickname:id} and {roles:id} describe a member (the invoker if no ID is given),
+{hasrole:id|role} checks if they have a role, given by ID or name.
 
 Tag documentation: https://jacher.io/tags
 "#;
@@ -49,7 +81,7 @@ lazy_static! {
         .arg(Argument::String)
         .arg(Argument::Optional(Box::new(Argument::String)))
         .arg(Argument::Optional(Box::new(Argument::StringRemaining)))
-        .usage("[create|delete|edit|list|info|raw|<tag name>] [<tag name>] [<tag content>]")
+        .usage("[create|delete|edit|list|info|search|top|raw|alias|transfer|history|revert|publish|unpublish|debug|<tag name>] [<tag name>] [<tag content>]")
         .example("create test hello, this is a tag")
         .example("delete test")
         .example("edit test new content")
@@ -57,11 +89,68 @@ lazy_static! {
         .example("list 2")
         .example("list <@571661221854707713> 3")
         .example("info test")
+        .example("search hello")
+        .example("top")
+        .example("alias t test")
+        .example("transfer test <@571661221854707713>")
+        .example("history test")
+        .example("revert test 2")
+        .example("publish test")
+        .example("@global/test")
+        .example("debug test hello")
         .example("test")
         .example("raw test")
         .build();
 }
 
+/// Checks the tag source for problems and formats them as a warning for the tag author
+///
+/// Returns an empty string if no problems were found
+fn format_diagnostics(content: &str) -> String {
+    let diagnostics = tag::validate(content);
+
+    if diagnostics.is_empty() {
+        return String::new();
+    }
+
+    /// Most diagnostics that are listed, so that the reply stays within the message length limit
+    const MAX_LISTED_DIAGNOSTICS: usize = 5;
+    /// Maximum number of characters of a single listed diagnostic
+    const MAX_DIAGNOSTIC_LENGTH: usize = 150;
+
+    let count = diagnostics.len() as u64;
+    let mut list = diagnostics
+        .iter()
+        .take(MAX_LISTED_DIAGNOSTICS)
+        .map(|d| {
+            let d = d.to_string();
+            if d.chars().count() > MAX_DIAGNOSTIC_LENGTH {
+                format!(
+                    "{}...",
+                    d.chars().take(MAX_DIAGNOSTIC_LENGTH).collect::<String>()
+                )
+            } else {
+                d
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if diagnostics.len() > MAX_LISTED_DIAGNOSTICS {
+        list += &format!(
+            "\n...and {} more",
+            diagnostics.len() - MAX_LISTED_DIAGNOSTICS
+        );
+    }
+
+    format!(
+        "\n:warning: Found {} potential {} in this tag:\n{}",
+        count,
+        util::pluralize("problem", "s", count),
+        util::codeblock(&list, "")
+    )
+}
+
 async fn run_create_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -> CommandResult {
     let author = context.message.author.id.get();
     let guild_id = context.message.guild_id.unwrap().get();
@@ -86,7 +175,11 @@ async fn run_create_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>)
     ensure!(success, "Tag already exists in this guild.");
 
     context
-        .reply_with_text(format!("Successfully created tag `{}`.", name))
+        .reply_with_text(format!(
+            "Successfully created tag `{}`.{}",
+            name,
+            format_diagnostics(content)
+        ))
         .await?;
 
     Ok(())
@@ -100,14 +193,11 @@ async fn run_delete_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>)
         .and_then(|t| t.maybe_text())
         .context("No tag name provided.")?;
 
-    let success = if is_guild_manager(
-        context.assyst.http.as_ref(),
-        context.message.guild_id.unwrap(),
-        context.author_id(),
-    )
-    .await
-    .unwrap_or(false)
-    {
+    let is_manager = is_guild_manager(&context, context.message.guild_id.unwrap())
+        .await
+        .unwrap_or(false);
+
+    let success = if is_manager {
         context
             .assyst
             .database
@@ -121,6 +211,18 @@ async fn run_delete_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>)
             .await?
     };
 
+    // the name might refer to an alias instead
+    let success = success
+        || context
+            .assyst
+            .database
+            .remove_tag_alias(
+                (!is_manager).then(|| author as i64),
+                guild_id.try_into()?,
+                name,
+            )
+            .await?;
+
     ensure!(
         success,
         "Failed to delete tag. Does it exist, and do you own it?"
@@ -157,7 +259,11 @@ async fn run_edit_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -
     );
 
     context
-        .reply_with_text(format!("Successfully edited tag `{}`.", name))
+        .reply_with_text(format!(
+            "Successfully edited tag `{}`.{}",
+            name,
+            format_diagnostics(data)
+        ))
         .await?;
 
     Ok(())
@@ -280,32 +386,281 @@ async fn run_info_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -
         .flatten()
         .context("No tag name provided.")?;
 
-    let tag = context
+    let tag = get_tag_or_suggest(&context, name).await?;
+
+    let usage = context
         .assyst
         .database
-        .get_tag(guild_id.try_into()?, name)
-        .await?
-        .context("No tag found.")?;
+        .get_tag_usage(guild_id.try_into()?, &tag.name)
+        .await?;
 
     let fmt = util::format_discord_timestamp(tag.created_at as u64);
-    let message = format!(
+    let mut message = format!(
         "🗒️ **Tag information: **{}\n\nAuthor: <@{}>\nCreated: {}",
         tag.name, tag.author, fmt
     );
 
+    match usage {
+        Some(usage) => write!(
+            message,
+            "\nUses: {}\nLast used: {}",
+            usage.uses,
+            util::format_discord_timestamp(usage.last_used as u64)
+        )?,
+        None => write!(message, "\nUses: 0")?,
+    };
+
     context.reply_with_text(message).await?;
 
     Ok(())
 }
 
 async fn run_raw_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -> CommandResult {
-    let guild_id = context.message.guild_id.unwrap().get();
     let name = args
         .get(1)
         .map(|t| t.maybe_text())
         .flatten()
         .context("No tag name provided.")?;
 
+    let tag = get_tag_or_suggest(&context, name).await?;
+
+    let raw = util::codeblock(&tag.data, "");
+
+    context.reply_with_text(raw).await?;
+    Ok(())
+}
+
+/// Fetches a tag by its name, or a published tag if the name starts with [`GLOBAL_TAG_PREFIX`]
+async fn find_tag(assyst: &Assyst, guild_id: i64, name: &str) -> anyhow::Result<Option<Tag>> {
+    let tag = match name.strip_prefix(GLOBAL_TAG_PREFIX) {
+        Some(name) => assyst.database.get_published_tag(name).await?,
+        None => assyst.database.get_tag(guild_id, name).await?,
+    };
+
+    Ok(tag)
+}
+
+/// Fetches a tag by its name, suggesting similarly named tags if it doesn't exist
+async fn get_tag_or_suggest(context: &Context, name: &str) -> anyhow::Result<Tag> {
+    let guild_id = context.message.guild_id.unwrap().get() as i64;
+
+    if let Some(tag) = find_tag(&context.assyst, guild_id, name).await? {
+        return Ok(tag);
+    }
+
+    if name.starts_with(GLOBAL_TAG_PREFIX) {
+        bail!("No published tag found.");
+    }
+
+    // suggestions need pg_trgm, which may not be installed
+    let similar = context
+        .assyst
+        .database
+        .get_similar_tag_names(guild_id, name, SUGGESTION_COUNT)
+        .await
+        .unwrap_or_default();
+
+    if similar.is_empty() {
+        bail!("No tag found.");
+    } else {
+        bail!("No tag found. Did you mean: {}?", similar.join(", "));
+    }
+}
+
+async fn run_publish_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -> CommandResult {
+    let author = context.message.author.id.get();
+    let guild_id = context.message.guild_id.unwrap().get();
+    let name = args
+        .get(1)
+        .and_then(|t| t.maybe_text())
+        .context("No tag name provided.")?;
+
+    let success = context
+        .assyst
+        .database
+        .publish_tag(author.try_into()?, guild_id.try_into()?, name)
+        .await?;
+
+    ensure!(
+        success,
+        "Failed to publish tag. Do you own it, and is the name not already taken globally?"
+    );
+
+    context
+        .reply_with_text(format!(
+            "Successfully published tag `{0}`. It can now be used anywhere with `{1}t {2}{0}`.",
+            name, context.prefix, GLOBAL_TAG_PREFIX
+        ))
+        .await?;
+
+    Ok(())
+}
+
+async fn run_unpublish_subcommand(
+    context: Arc<Context>,
+    args: Vec<ParsedArgument>,
+) -> CommandResult {
+    let author = context.message.author.id.get();
+    let name = args
+        .get(1)
+        .and_then(|t| t.maybe_text())
+        .context("No tag name provided.")?;
+    let name = name.strip_prefix(GLOBAL_TAG_PREFIX).unwrap_or(name);
+
+    // bot admins can unpublish any tag, for moderation purposes
+    let author = if context.assyst.user_is_admin(author) {
+        None
+    } else {
+        Some(author.try_into()?)
+    };
+
+    let success = context.assyst.database.unpublish_tag(author, name).await?;
+
+    ensure!(
+        success,
+        "Failed to unpublish tag. Is it published, and do you own it?"
+    );
+
+    context
+        .reply_with_text(format!("Successfully unpublished tag `{}`.", name))
+        .await?;
+
+    Ok(())
+}
+
+async fn run_search_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -> CommandResult {
+    let guild_id = context.message.guild_id.unwrap().get();
+    let query = args
+        .iter()
+        .skip(1)
+        .flat_map(|a| a.maybe_text())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    ensure!(!query.is_empty(), "No search query provided.");
+
+    let tags = context
+        .assyst
+        .database
+        .search_tags(guild_id.try_into()?, &query, DEFAULT_LIST_COUNT)
+        .await?;
+
+    if tags.is_empty() {
+        context.reply_err("No tags found for this query").await?;
+        return Ok(());
+    }
+
+    let mut message = format!(
+        "🗒️ **Tags matching: **{}\nView a tag by running `{}t <name>`\n\n",
+        query, context.prefix
+    );
+
+    for (index, tag) in tags.into_iter().enumerate() {
+        write!(message, "{}. {} (<@{}>)\n", index + 1, tag.name, tag.author)?;
+    }
+
+    context.reply_with_text(message).await?;
+
+    Ok(())
+}
+
+async fn run_top_subcommand(context: Arc<Context>, _args: Vec<ParsedArgument>) -> CommandResult {
+    let guild_id = context.message.guild_id.unwrap().get();
+
+    let tags = context
+        .assyst
+        .database
+        .get_top_tags(guild_id.try_into()?, DEFAULT_LIST_COUNT)
+        .await?;
+
+    if tags.is_empty() {
+        context
+            .reply_err("No tags have been used in this server yet")
+            .await?;
+        return Ok(());
+    }
+
+    let mut message = String::from("🗒️ **Most used tags in this server**\n\n");
+
+    for (index, tag) in tags.into_iter().enumerate() {
+        write!(
+            message,
+            "{}. {} ({} {}, last used {})\n",
+            index + 1,
+            tag.name,
+            tag.uses,
+            util::pluralize("use", "s", tag.uses as u64),
+            util::format_discord_timestamp(tag.last_used as u64)
+        )?;
+    }
+
+    context.reply_with_text(message).await?;
+
+    Ok(())
+}
+
+/// Returns whether the command author may manage a tag, i.e. whether they own it or are a guild manager
+async fn can_manage_tag(context: &Context, tag: &Tag) -> bool {
+    tag.author == context.author_id().get() as i64
+        || is_guild_manager(context, context.message.guild_id.unwrap())
+            .await
+            .unwrap_or(false)
+}
+
+async fn run_alias_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -> CommandResult {
+    let author = context.message.author.id.get();
+    let guild_id = context.message.guild_id.unwrap().get();
+    let name = args
+        .get(1)
+        .and_then(|t| t.maybe_text())
+        .context("No alias name provided.")?;
+
+    ensure!(
+        name.len() < 20,
+        "Alias name must be less than 20 characters"
+    );
+
+    let target = args
+        .get(2)
+        .and_then(|t| t.maybe_text())
+        .context("No tag name provided.")?;
+
+    let success = context
+        .assyst
+        .database
+        .add_tag_alias(author.try_into()?, guild_id.try_into()?, name, target)
+        .await?;
+
+    ensure!(
+        success,
+        "Failed to create alias. Does the tag exist, and is the name not already taken?"
+    );
+
+    context
+        .reply_with_text(format!(
+            "Successfully created alias `{}` for tag `{}`.",
+            name, target
+        ))
+        .await?;
+
+    Ok(())
+}
+
+async fn run_transfer_subcommand(
+    context: Arc<Context>,
+    args: Vec<ParsedArgument>,
+) -> CommandResult {
+    let guild_id = context.message.guild_id.unwrap().get();
+    let name = args
+        .get(1)
+        .and_then(|t| t.maybe_text())
+        .context("No tag name provided.")?;
+    let new_author = args
+        .get(2)
+        .and_then(|t| t.maybe_text())
+        .and_then(mention_to_id)
+        .context("No user provided.")?;
+
     let tag = context
         .assyst
         .database
@@ -313,20 +668,88 @@ async fn run_raw_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) ->
         .await?
         .context("No tag found.")?;
 
-    let raw = util::codeblock(&tag.data, "");
+    ensure!(
+        can_manage_tag(&context, &tag).await,
+        "You can only transfer tags that you own."
+    );
+
+    context
+        .assyst
+        .database
+        .transfer_tag(guild_id.try_into()?, &tag.name, new_author.try_into()?)
+        .await?;
+
+    context
+        .reply_with_text(format!(
+            "Successfully transferred tag `{}` to <@{}>.",
+            tag.name, new_author
+        ))
+        .await?;
 
-    context.reply_with_text(raw).await?;
     Ok(())
 }
 
-async fn run_tag_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -> CommandResult {
+async fn run_history_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -> CommandResult {
     let guild_id = context.message.guild_id.unwrap().get();
     let name = args
-        .get(0)
-        .map(|t| t.as_text())
+        .get(1)
+        .and_then(|t| t.maybe_text())
         .context("No tag name provided.")?;
 
-    println!("Running tag {} in {:?}", name, context.message.guild_id);
+    let tag = context
+        .assyst
+        .database
+        .get_tag(guild_id.try_into()?, name)
+        .await?
+        .context("No tag found.")?;
+
+    let history = context
+        .assyst
+        .database
+        .get_tag_history(guild_id.try_into()?, &tag.name)
+        .await?;
+
+    if history.is_empty() {
+        context
+            .reply_err("This tag has not been edited yet")
+            .await?;
+        return Ok(());
+    }
+
+    let mut message = format!(
+        "🗒️ **History of tag: **{0}\nRevert to a revision by running `{1}t revert {0} <revision>`\n\n",
+        tag.name, context.prefix
+    );
+
+    for revision in history.iter().take(DEFAULT_LIST_COUNT as usize) {
+        write!(
+            message,
+            "Revision {}: {} bytes, replaced {}\n",
+            revision.revision,
+            revision.data.len(),
+            util::format_discord_timestamp(revision.replaced_at as u64)
+        )?;
+    }
+
+    write!(message, "\n{} revisions in total", history.len())?;
+
+    context.reply_with_text(message).await?;
+
+    Ok(())
+}
+
+async fn run_revert_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -> CommandResult {
+    let guild_id = context.message.guild_id.unwrap().get();
+    let name = args
+        .get(1)
+        .and_then(|t| t.maybe_text())
+        .context("No tag name provided.")?;
+    let revision = args
+        .get(2)
+        .and_then(|t| t.maybe_text())
+        .context("No revision provided.")?
+        .parse::<i32>()
+        .context("Revision must be a number.")?;
 
     let tag = context
         .assyst
@@ -335,38 +758,217 @@ async fn run_tag_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) ->
         .await?
         .context("No tag found.")?;
 
-    let ccx = context.clone();
-    let output = tokio::task::spawn_blocking(move || {
-        let args = args
-            .iter()
-            .skip(1)
-            .flat_map(|a| a.maybe_text())
-            .collect::<Vec<_>>();
-
-        let args = if args.len() > 1 {
-            let arg2 = args[1];
-            let raw_replaced = arg2.replace("\n", " \n");
-            let mut arg2split = raw_replaced
+    ensure!(
+        can_manage_tag(&context, &tag).await,
+        "You can only revert tags that you own."
+    );
+
+    let revision = context
+        .assyst
+        .database
+        .get_tag_revision(guild_id.try_into()?, &tag.name, revision)
+        .await?
+        .context("No such revision found.")?;
+
+    context
+        .assyst
+        .database
+        .edit_tag_force(guild_id.try_into()?, &tag.name, &revision.data)
+        .await?;
+
+    context
+        .reply_with_text(format!(
+            "Successfully reverted tag `{}` to revision {}.",
+            tag.name, revision.revision
+        ))
+        .await?;
+
+    Ok(())
+}
+
+/// Returns the name that variables of `tag` are scoped to, when it's invoked as `name`
+fn scoped_tag_name(name: &str, tag: &Tag) -> String {
+    // published tags get their own variable scope, separate from a local tag with the same name
+    if name.starts_with(GLOBAL_TAG_PREFIX) {
+        name.to_owned()
+    } else {
+        tag.name.clone()
+    }
+}
+
+/// Splits the raw command arguments following the tag name into the arguments passed to the tag
+fn split_tag_args(args: &[ParsedArgument]) -> Vec<String> {
+    args.iter()
+        .flat_map(|a| a.maybe_text())
+        .flat_map(|a| {
+            a.replace("\n", " \n")
                 .split(' ')
-                .map(|x| String::from(x))
-                .collect::<Vec<String>>();
-            let mut new_args = vec![args[0].to_string()];
-            new_args.append(&mut arg2split);
-            new_args
-        } else {
-            args.iter().map(|x| String::from(*x)).collect::<Vec<_>>()
-        };
-
-        let tokio = tokio::runtime::Handle::current();
-        let b = args.iter().map(|s| s as &str).collect::<Vec<_>>();
-
-        tag::parse(&tag.data, &b, TagContext { ccx, tokio })
-    })
-    .await?
-    .context("Tag execution failed");
+                .map(String::from)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+async fn run_debug_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -> CommandResult {
+    let name = args
+        .get(1)
+        .and_then(|t| t.maybe_text())
+        .context("No tag name provided.")?
+        .to_owned();
+
+    let tag = get_tag_or_suggest(&context, &name).await?;
+    let tag_name = scoped_tag_name(&name, &tag);
+
+    let session = Session::new(tag.data, split_tag_args(args.get(2..).unwrap_or_default()))
+        .with_tag_name(tag_name)
+        .with_trace();
+    let (session, result) = run_session(&context, session).await?;
+    let trace = session.trace();
+
+    let mut report = String::new();
+    for entry in trace {
+        writeln!(report, "{}", entry)?;
+    }
+
+    let summary = match &result {
+        Ok(ParseResult { output, .. }) => {
+            writeln!(report, "\nOutput: {:?}", output)?;
+            format!(
+                "Tag `{}` ran successfully, invoking {} subtags.",
+                name,
+                trace.len()
+            )
+        }
+        Err(e) => {
+            writeln!(report, "\nError: {:?}", e)?;
+            format!("Tag `{}` failed: {:#}", name, e)
+        }
+    };
+
+    context
+        .reply(
+            MessageBuilder::new()
+                .content(summary.into_boxed_str())
+                .attachment("trace.txt".into(), report.into_bytes()),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Builds the response of a tag that used structured message output, such as embeds
+fn tag_message_builder(
+    output: String,
+    attachment: Option<(Bytes, Type)>,
+    message: MessageOptions,
+) -> anyhow::Result<MessageBuilder> {
+    let mut builder = MessageBuilder::new();
+
+    if let Some(embed) = message.embed {
+        builder = builder.embed(Embed {
+            author: None,
+            color: embed.color,
+            description: embed.description,
+            fields: embed
+                .fields
+                .into_iter()
+                .map(|f| EmbedField {
+                    inline: f.inline,
+                    name: f.name,
+                    value: f.value,
+                })
+                .collect(),
+            footer: None,
+            image: embed.image.map(|url| EmbedImage {
+                height: None,
+                proxy_url: None,
+                url,
+                width: None,
+            }),
+            kind: "rich".to_owned(),
+            provider: None,
+            thumbnail: None,
+            timestamp: None,
+            title: embed.title,
+            url: None,
+            video: None,
+        });
+    } else if output.trim_start().is_empty() && attachment.is_none() {
+        builder = builder.content("[Empty Response]".into());
+    }
+
+    if !output.trim_start().is_empty() {
+        builder = builder.content(output.into_boxed_str());
+    }
+
+    if let Some((buffer, ty)) = attachment {
+        ensure!(
+            buffer.len() <= consts::WORKING_FILESIZE_LIMIT_BYTES,
+            "The tag output file exceeded the maximum file size limit of {}MB",
+            consts::WORKING_FILESIZE_LIMIT_BYTES / 1000 / 1000
+        );
+
+        builder = builder.attachment(
+            format!("attachment.{}", ty.as_str()).into_boxed_str(),
+            buffer.to_vec(),
+        );
+    }
+
+    match message.reply {
+        ReplyMode::Silent => {}
+        ReplyMode::Reply(message_id) => {
+            builder.reply_to = message_id.and_then(MessageId::new_checked);
+            // the author of any other message in the channel didn't ask to be pinged
+            builder.mention_replied_user = builder.reply_to.is_none();
+        }
+        ReplyMode::Disabled => builder.should_reply = false,
+    }
+
+    Ok(builder)
+}
+
+async fn run_tag_subcommand(context: Arc<Context>, args: Vec<ParsedArgument>) -> CommandResult {
+    let name = args
+        .get(0)
+        .map(|t| t.as_text())
+        .context("No tag name provided.")?;
+
+    println!("Running tag {} in {:?}", name, context.message.guild_id);
+
+    let tag = get_tag_or_suggest(&context, name).await?;
+
+    // a failure to count the use shouldn't stop the tag from running
+    let assyst = context.assyst.clone();
+    let (guild_id, tag_name) = (tag.guild_id, tag.name.clone());
+    tokio::spawn(async move {
+        if let Err(e) = assyst
+            .database
+            .increment_tag_uses(guild_id, &tag_name)
+            .await
+        {
+            eprintln!("Failed to increment uses of tag {}: {:?}", tag_name, e);
+        }
+    });
+
+    let tag_name = scoped_tag_name(name, &tag);
+
+    let session = Session::new(tag.data, split_tag_args(&args[1..])).with_tag_name(tag_name);
+    let (_, output) = run_session(&context, session).await?;
+    let output = output.context("Tag execution failed");
 
     match output {
-        Ok(ParseResult { attachment, output }) => {
+        Ok(ParseResult {
+            attachment,
+            output,
+            message,
+        }) if message != MessageOptions::default() => {
+            context
+                .reply(tag_message_builder(output, attachment, message)?)
+                .await?;
+        }
+        Ok(ParseResult {
+            attachment, output, ..
+        }) => {
             if let Some((buffer, ty)) = attachment {
                 let output = (!output.is_empty()).then(|| output);
 
@@ -399,114 +1001,309 @@ pub async fn run_tag_command(
         "edit" => run_edit_subcommand(context, args).await,
         "list" => run_list_subcommand(context, args).await,
         "info" => run_info_subcommand(context, args).await,
+        "search" => run_search_subcommand(context, args).await,
+        "top" => run_top_subcommand(context, args).await,
         "raw" => run_raw_subcommand(context, args).await,
+        "alias" => run_alias_subcommand(context, args).await,
+        "transfer" => run_transfer_subcommand(context, args).await,
+        "history" => run_history_subcommand(context, args).await,
+        "revert" => run_revert_subcommand(context, args).await,
+        "publish" => run_publish_subcommand(context, args).await,
+        "debug" => run_debug_subcommand(context, args).await,
+        "unpublish" => run_unpublish_subcommand(context, args).await,
         _ => run_tag_subcommand(context, args).await,
     }
 }
 
-struct TagContext {
-    tokio: tokio::runtime::Handle,
-    ccx: Arc<Context>,
+/// Where a tag is being run, which is all the parser needs to know without doing any I/O
+#[derive(Clone, Copy)]
+struct TagEnvironment {
+    channel_id: u64,
+    guild_id: Option<u64>,
+    user_id: u64,
 }
 
-impl tag::Context for TagContext {
-    fn execute_javascript(
-        &self,
-        code: &str,
-        args: Vec<String>,
-    ) -> anyhow::Result<FakeEvalImageResponse> {
-        let response = self.tokio.block_on(fake_eval(
-            &self.ccx.assyst,
-            code,
-            true,
-            Some(&self.ccx.message),
-            args,
-        ))?;
-
-        Ok(response)
+impl TagEnvironment {
+    fn new(ccx: &Context) -> Self {
+        Self {
+            channel_id: ccx.message.channel_id.get(),
+            guild_id: ccx.message.guild_id.map(|g| g.get()),
+            user_id: ccx.message.author.id.get(),
+        }
     }
+}
 
-    fn get_last_attachment(&self) -> anyhow::Result<String> {
-        let http = &self.ccx.assyst.http;
-        let message = &*self.ccx.message;
-        let previous = self
-            .tokio
-            .block_on(previous_message_attachment(http, message))
-            .context("Failed to extract last attachment")?;
+impl tag::Environment for TagEnvironment {
+    fn channel_id(&self) -> anyhow::Result<u64> {
+        Ok(self.channel_id)
+    }
 
-        Ok(previous.into_owned())
+    fn guild_id(&self) -> anyhow::Result<u64> {
+        self.guild_id.context("Missing Guild ID")
     }
 
-    fn get_avatar(&self, user_id: Option<u64>) -> anyhow::Result<String> {
-        let user_id = user_id.unwrap_or(self.ccx.message.author.id.get());
+    fn user_id(&self) -> anyhow::Result<u64> {
+        Ok(self.user_id)
+    }
+}
 
-        let user = self
-            .tokio
-            .block_on(self.ccx.http().user(UserId::new(user_id)).into_future())?;
+/// Runs a tag session to completion, fulfilling its requests asynchronously
+///
+/// Parsing itself happens on the blocking thread pool, but no thread is held while waiting for I/O.
+async fn run_session(
+    ccx: &Arc<Context>,
+    mut session: Session,
+) -> anyhow::Result<(Session, anyhow::Result<ParseResult>)> {
+    let env = TagEnvironment::new(ccx);
+
+    loop {
+        let (returned, step) = tokio::task::spawn_blocking(move || {
+            let step = session.step(&env);
+            (session, step)
+        })
+        .await?;
+        session = returned;
 
-        if user.status().get() == 404 {
-            return Err(anyhow!("User not found"));
+        match step {
+            Step::Pending(request) => {
+                let response = handle_request(ccx, request).await;
+                session.resume(response);
+            }
+            Step::Done(result) => return Ok((session, result)),
         }
+    }
+}
 
-        let user = self.tokio.block_on(user.model())?;
+/// Fetches a user, returning an error if they don't exist
+async fn fetch_user(ccx: &Context, user_id: u64) -> anyhow::Result<User> {
+    let user = ccx.http().user(UserId::new(user_id)).await?;
 
-        Ok(util::get_avatar_url(&user))
+    if user.status().get() == 404 {
+        return Err(anyhow!("User not found"));
     }
 
-    fn download(&self, url: &str) -> anyhow::Result<String> {
-        let assyst = &self.ccx.assyst;
+    Ok(user.model().await?)
+}
 
-        let content =
-            downloader::download_content(assyst, url, consts::ABSOLUTE_INPUT_FILE_SIZE_LIMIT_BYTES);
+fn cached_member_to_tag(member: CachedMember) -> tag::Member {
+    tag::Member {
+        username: member.username,
+        nickname: member.nickname,
+        roles: member
+            .roles
+            .into_iter()
+            .map(|r| tag::Role {
+                id: r.id,
+                name: r.name,
+            })
+            .collect(),
+    }
+}
 
-        let content = self.tokio.block_on(content)?;
+/// Looks up a guild member, preferring the cache over the API
+///
+/// Members fetched from the API are written back to the cache, which resolves their role names.
+async fn fetch_member(ccx: &Context, guild_id: u64, user_id: u64) -> anyhow::Result<tag::Member> {
+    let assyst = ccx.assyst.clone();
 
-        Ok(String::from_utf8_lossy(&content).into_owned())
+    // the cache being unavailable shouldn't make the subtag fail, the API can still be used
+    if let Ok(Some(member)) = get_cached_member(assyst.clone(), guild_id, user_id).await {
+        return Ok(cached_member_to_tag(member));
     }
 
-    fn channel_id(&self) -> anyhow::Result<u64> {
-        Ok(self.ccx.message.channel_id.get())
-    }
+    let member = match &ccx.message.member {
+        // the invoking member is part of the message, so it doesn't need to be fetched
+        Some(member) if user_id == ccx.message.author.id.get() => MemberSend {
+            guild_id,
+            user_id,
+            username: ccx.message.author.name.clone(),
+            nickname: member.nick.clone(),
+            roles: member.roles.iter().map(|r| r.get()).collect(),
+        },
+        _ => {
+            let member = ccx
+                .http()
+                .guild_member(Id::new(guild_id), Id::new(user_id))
+                .await
+                .context("Member not found")?
+                .model()
+                .await?;
+
+            MemberSend {
+                guild_id,
+                user_id,
+                username: member.user.name,
+                nickname: member.nick,
+                roles: member.roles.iter().map(|r| r.get()).collect(),
+            }
+        }
+    };
 
-    fn guild_id(&self) -> anyhow::Result<u64> {
-        self.ccx
-            .message
-            .guild_id
-            .context("Missing Guild ID")
-            .map(|s| s.get())
-    }
+    let (username, nickname, role_ids) = (
+        member.username.clone(),
+        member.nickname.clone(),
+        member.roles.clone(),
+    );
 
-    fn user_id(&self) -> anyhow::Result<u64> {
-        Ok(self.ccx.message.author.id.get())
+    let _ = cache_member(assyst.clone(), member).await;
+    if let Ok(Some(member)) = get_cached_member(assyst, guild_id, user_id).await {
+        return Ok(cached_member_to_tag(member));
     }
 
-    fn user_tag(&self, id: Option<u64>) -> anyhow::Result<String> {
-        if let Some(id) = id {
-            let user = self
-                .tokio
-                .block_on(self.ccx.http().user(UserId::new(id)).into_future())?;
+    // the guild is not cached, so role names have to be fetched as well
+    let roles = ccx.http().roles(Id::new(guild_id)).await?.models().await?;
+
+    Ok(tag::Member {
+        username,
+        nickname,
+        roles: roles
+            .into_iter()
+            .filter(|r| role_ids.contains(&r.id.get()))
+            .map(|r| tag::Role {
+                id: r.id.get(),
+                name: r.name,
+            })
+            .collect(),
+    })
+}
+
+/// Fulfills a request made by a running tag
+async fn handle_request(ccx: &Context, request: Request) -> anyhow::Result<Response> {
+    let guild_id = || -> anyhow::Result<i64> {
+        Ok(ccx.message.guild_id.context("Missing Guild ID")?.get() as i64)
+    };
+
+    match request {
+        Request::ExecuteJavascript { code, args } => {
+            let response = fake_eval(&ccx.assyst, &code, true, Some(&ccx.message), args).await?;
+
+            Ok(Response::Javascript(response))
+        }
+        Request::GetLastAttachment => {
+            let previous = previous_message_attachment(&ccx.assyst.http, &ccx.message)
+                .await
+                .context("Failed to extract last attachment")?;
+
+            Ok(Response::Text(previous.into_owned()))
+        }
+        Request::GetAvatar(user_id) => {
+            let user_id = user_id.unwrap_or(ccx.message.author.id.get());
+            let user = fetch_user(ccx, user_id).await?;
+
+            Ok(Response::Text(util::get_avatar_url(&user)))
+        }
+        Request::Download(url) => {
+            let content = downloader::download_content(
+                &ccx.assyst,
+                &url,
+                consts::ABSOLUTE_INPUT_FILE_SIZE_LIMIT_BYTES,
+            )
+            .await?;
+
+            Ok(Response::Text(
+                String::from_utf8_lossy(&content).into_owned(),
+            ))
+        }
+        Request::UserTag(Some(id)) => {
+            let user = fetch_user(ccx, id).await?;
+
+            Ok(Response::Text(util::format_tag(&user)))
+        }
+        Request::UserTag(None) => Ok(Response::Text(util::format_tag(&ccx.message.author))),
+        Request::GetTag(name) => {
+            let tag = find_tag(&ccx.assyst, guild_id()?, &name).await?;
+
+            match tag {
+                Some(tag) => Ok(Response::Tag(tag::Tag {
+                    name: scoped_tag_name(&name, &tag),
+                    contents: tag.data,
+                })),
+                None => Err(anyhow!("Tag not found")),
+            }
+        }
+        Request::GetGuild => {
+            let guild_id = guild_id()? as u64;
+
+            if let Ok(Some(guild)) = get_cached_guild(ccx.assyst.clone(), guild_id).await {
+                return Ok(Response::Guild(tag::Guild {
+                    name: guild.name,
+                    member_count: guild.member_count,
+                }));
+            }
+
+            let guild = ccx
+                .http()
+                .guild(Id::new(guild_id))
+                .with_counts(true)
+                .await?
+                .model()
+                .await?;
+
+            Ok(Response::Guild(tag::Guild {
+                name: guild.name,
+                member_count: guild.approximate_member_count.unwrap_or_default(),
+            }))
+        }
+        Request::GetChannelName(channel_id) => {
+            let channel_id = channel_id.unwrap_or(ccx.message.channel_id.get());
 
-            if user.status().get() == 404 {
-                return Err(anyhow!("User not found"));
+            if let Ok(Some(channel)) = get_cached_channel(ccx.assyst.clone(), channel_id).await {
+                return Ok(Response::Text(channel.name));
             }
 
-            let user = self.tokio.block_on(user.model())?;
+            let channel = ccx
+                .http()
+                .channel(Id::new(channel_id))
+                .await
+                .context("Channel not found")?
+                .model()
+                .await?;
 
-            Ok(util::format_tag(&user))
-        } else {
-            Ok(util::format_tag(&self.ccx.message.author))
+            Ok(Response::Text(channel.name.unwrap_or_default()))
         }
-    }
-    fn get_tag_contents(&self, tag: &str) -> anyhow::Result<String> {
-        let guild_id = self.ccx.message.guild_id.unwrap().get();
-        let tag = self
-            .tokio
-            .block_on(async { self.ccx.assyst.database.get_tag(guild_id as i64, tag).await });
-
-        match tag {
-            Ok(Some(Tag { data, .. })) => Ok(data),
-            Ok(None) => Err(anyhow!("Tag not found")),
-            Err(e) => Err(e.into()),
+        Request::GetMember(user_id) => {
+            let user_id = user_id.unwrap_or(ccx.message.author.id.get());
+            let member = fetch_member(ccx, guild_id()? as u64, user_id).await?;
+
+            Ok(Response::Member(member))
+        }
+        Request::GetPersistentVariable { tag, key } => {
+            let value = ccx
+                .assyst
+                .database
+                .get_tag_variable(guild_id()?, tag.as_deref().unwrap_or_default(), &key)
+                .await?;
+
+            Ok(Response::Variable(value))
+        }
+        Request::SetPersistentVariable { tag, key, value } => {
+            let success = ccx
+                .assyst
+                .database
+                .set_tag_variable(
+                    guild_id()?,
+                    tag.as_deref().unwrap_or_default(),
+                    &key,
+                    &value,
+                    consts::MAX_TAG_STORAGE_BYTES_PER_GUILD as i64,
+                )
+                .await?;
+
+            ensure!(
+                success,
+                "This server has reached its tag storage limit of {} bytes",
+                consts::MAX_TAG_STORAGE_BYTES_PER_GUILD
+            );
+
+            Ok(Response::Done)
+        }
+        Request::DeletePersistentVariable { tag, key } => {
+            ccx.assyst
+                .database
+                .delete_tag_variable(guild_id()?, tag.as_deref().unwrap_or_default(), &key)
+                .await?;
+
+            Ok(Response::Done)
         }
     }
 }
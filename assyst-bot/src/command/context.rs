@@ -195,7 +195,10 @@ impl Context {
         message_builder: MessageBuilder,
     ) -> anyhow::Result<Arc<Message>> {
         let mut c = message_builder.clone();
-        let allowed_mentions = AllowedMentions::default();
+        let allowed_mentions = AllowedMentions {
+            replied_user: c.mention_replied_user,
+            ..Default::default()
+        };
 
         let mut create_message = self
             .assyst
@@ -227,7 +230,7 @@ impl Context {
             create_message = create_message.embeds(&embeds)?;
         };
         if !self.reply.lock().await.invocation_deleted && c.should_reply {
-            create_message = create_message.reply(self.message.id);
+            create_message = create_message.reply(c.reply_to.unwrap_or(self.message.id));
         }
         let x = create_message.await;
         match x {
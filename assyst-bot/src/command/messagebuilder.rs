@@ -1,3 +1,4 @@
+use assyst_common::util::MessageId;
 use twilight_model::channel::message::embed::Embed;
 
 #[derive(Clone, Debug)]
@@ -12,6 +13,10 @@ pub struct MessageBuilder {
     pub content: Option<Box<str>>,
     pub embed: Option<Embed>,
     pub should_reply: bool,
+    /// The message to reply to, if not the invocation
+    pub reply_to: Option<MessageId>,
+    /// Whether the author of the replied message should be pinged
+    pub mention_replied_user: bool,
 }
 impl MessageBuilder {
     pub fn new() -> Self {
@@ -20,6 +25,8 @@ impl MessageBuilder {
             content: None,
             embed: None,
             should_reply: true,
+            reply_to: None,
+            mention_replied_user: false,
         }
     }
 
@@ -32,4 +39,9 @@ impl MessageBuilder {
         self.content = Some(content);
         self
     }
+
+    pub fn embed(mut self, embed: Embed) -> Self {
+        self.embed = Some(embed);
+        self
+    }
 }
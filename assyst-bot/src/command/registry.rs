@@ -4,6 +4,7 @@ use super::{
     command::CommandAvailability,
 };
 use crate::command::context::Context;
+use crate::rest::wsi;
 use std::future::Future;
 use std::sync::Arc;
 use std::{collections::HashMap, pin::Pin};
@@ -69,7 +70,13 @@ impl CommandRegistry {
         // get the appropriate handler function
         // we already validated the command exists, so unwrapping here is safe
         let command_run = self.command_runs.get(parsed_command.calling_name).unwrap();
-        let result = command_run(context, parsed_command.args, parsed_command.flags).await;
+        // WSI jobs of this command report their progress to it
+        let result = wsi::COMMAND_CONTEXT
+            .scope(
+                context.clone(),
+                command_run(context, parsed_command.args, parsed_command.flags),
+            )
+            .await;
         let mut lock = command_processed.lock().await;
         *lock = true;
 
@@ -163,6 +170,7 @@ impl CommandRegistry {
         register_command!(self, PAINT_COMMAND, run_paint_command);
         register_command!(self, PATRON_STATUS_COMMAND, run_patron_status_command);
         register_command!(self, PING_COMMAND, run_ping_command);
+        register_command!(self, PIPE_COMMAND, run_pipe_command);
         register_command!(self, PIXELATE_COMMAND, run_pixelate_command);
         register_command!(self, PREFIX_COMMAND, run_prefix_command);
         register_command!(self, PRINTER_COMMAND, run_printer_command);
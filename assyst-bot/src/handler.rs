@@ -1,6 +1,8 @@
 use crate::{
     caching::persistent_caching::{
-        get_new_guilds_from_ready, handle_guild_create_event, handle_guild_delete_event,
+        get_guild_changes_from_ready, handle_channel_delete_event, handle_channel_update,
+        handle_guild_create_event, handle_guild_delete_event, handle_guild_update_event,
+        handle_role_delete_event, handle_role_update,
     },
     handlers::*,
     logger, Assyst,
@@ -49,17 +51,57 @@ pub async fn handle_event(assyst: Arc<Assyst>, event: Event) -> anyhow::Result<(
                 logger::guild_remove(&assyst, &format!("{}", id.get())).await;
             }
         }
+        Event::GuildUpdate(event) => {
+            handle_guild_update_event(assyst, event)
+                .await
+                .context("failed to handle guild update")?;
+        }
+        Event::ChannelCreate(event) => {
+            handle_channel_update(assyst, event.channel)
+                .await
+                .context("failed to handle channel create")?;
+        }
+        Event::ChannelUpdate(event) => {
+            // only guild channels are cached
+            if let Some(channel) = event.channel.guild() {
+                handle_channel_update(assyst, channel)
+                    .await
+                    .context("failed to handle channel update")?;
+            }
+        }
+        Event::ChannelDelete(event) => {
+            handle_channel_delete_event(assyst, event)
+                .await
+                .context("failed to handle channel delete")?;
+        }
+        Event::GuildRoleCreate(event) => {
+            handle_role_update(assyst, event.role)
+                .await
+                .context("failed to handle role create")?;
+        }
+        Event::GuildRoleUpdate(event) => {
+            handle_role_update(assyst, event.role)
+                .await
+                .context("failed to handle role update")?;
+        }
+        Event::GuildRoleDelete(event) => {
+            handle_role_delete_event(assyst, event)
+                .await
+                .context("failed to handle role delete")?;
+        }
         Event::Ready(r) => {
             let shard = r.ready.shard.unwrap_or(ShardInfo {
                 id: ShardId(0),
                 total: 1,
             });
             let guilds = r.ready.guilds.len();
-            let new_guilds = get_new_guilds_from_ready(assyst.clone(), r)
+            let changes = get_guild_changes_from_ready(assyst.clone(), r)
                 .await
                 .context("failed to handle guild ready")?;
 
-            assyst.metrics.add_guilds(new_guilds as i64);
+            assyst
+                .metrics
+                .add_guilds(changes.new as i64 - changes.removed as i64);
 
             logger::info(
                 &assyst,
@@ -19,9 +19,12 @@ mod util;
 
 use anyhow::Context;
 use assyst::Assyst;
-use assyst_common::consts::{
-    gateway::{self, Latencies},
-    EVENT_PIPE,
+use assyst_common::{
+    consts::{
+        gateway::{self, Latencies},
+        EVENT_PIPE,
+    },
+    handshake::{client_handshake, Handshake},
 };
 use assyst_webserver::run as webserver_run;
 use bincode::deserialize;
@@ -31,8 +34,9 @@ use rest::get_web_download_api_urls;
 use serenity::all::Event;
 use std::sync::Arc;
 use tokio::{
-    io::{AsyncReadExt, BufReader},
-    net::UnixStream,
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{unix::OwnedWriteHalf, UnixStream},
+    sync::mpsc::{self, UnboundedReceiver},
 };
 use twilight_gateway::EventTypeFlags;
 
@@ -87,19 +91,47 @@ async fn main() -> anyhow::Result<()> {
     assyst.initialize_blacklist().await?;
     *assyst.web_download_urls.lock().await = get_web_download_api_urls(assyst.clone()).await?;
 
-    let stream = UnixStream::connect(EVENT_PIPE).await?;
-    let mut reader = BufReader::new(stream);
+    let mut stream = UnixStream::connect(EVENT_PIPE).await?;
+    let handshake = Handshake::new(
+        gateway::PROTOCOL_VERSION,
+        gateway::capabilities::LATENCIES | gateway::capabilities::REPLAY,
+    );
+    let capabilities = client_handshake(&mut stream, handshake)
+        .await
+        .context("Failed to connect to the gateway")?;
+
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let (ack_tx, ack_rx) = mpsc::unbounded_channel();
+    if capabilities & gateway::capabilities::REPLAY != 0 {
+        // this process hasn't processed any events yet,
+        // so the gateway replays everything that wasn't acknowledged
+        writer.write_u64(0).await?;
+        tokio::spawn(send_acks(writer, ack_rx));
+    }
 
     // Event loop
     loop {
         let assyst_clone = assyst.clone();
         let op = reader.read_u8().await?;
+        let seq = match op {
+            gateway::OP_SEQUENCED_EVENT => Some(reader.read_u64().await?),
+            _ => None,
+        };
         let len = reader.read_u32().await?;
         let mut data: Vec<u8> = vec![0; len as usize];
         reader.read_exact(&mut data).await?;
         match op {
-            gateway::OP_EVENT => {
+            gateway::OP_EVENT | gateway::OP_SEQUENCED_EVENT => {
                 assyst.metrics.add_event();
+
+                // events are acknowledged once they're dispatched, an event that is being handled
+                // when the bot stops isn't replayed
+                if let Some(seq) = seq {
+                    let _ = ack_tx.send(seq);
+                }
+
                 tokio::spawn(async move {
                     let event = serde_json::from_str::<Event>(&String::from_utf8_lossy(&data));
                     match event {
@@ -144,3 +176,21 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 }
+
+/// Acknowledges dispatched events to the gateway, so that they aren't replayed after a restart
+async fn send_acks(
+    mut writer: OwnedWriteHalf,
+    mut acks: UnboundedReceiver<u64>,
+) -> anyhow::Result<()> {
+    while let Some(mut seq) = acks.recv().await {
+        // only the latest sequence number matters, so acknowledgements that piled up are sent as one
+        while let Ok(next) = acks.try_recv() {
+            seq = next;
+        }
+
+        writer.write_u8(gateway::OP_ACK).await?;
+        writer.write_u64(seq).await?;
+    }
+
+    Ok(())
+}
@@ -1,8 +1,10 @@
 use std::{collections::HashMap, sync::RwLock};
 
+use crate::rest::wsi::WsiErrorKind;
+
 use prometheus::{
-    register_counter, register_int_counter, register_int_gauge, register_int_gauge_vec, Counter,
-    IntCounter, IntGauge, IntGaugeVec,
+    register_counter, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Counter, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
 };
 
 pub struct CountableMetrics {
@@ -14,6 +16,9 @@ pub struct CountableMetrics {
     pub latency: IntGaugeVec,
     pub cdn_files: IntGauge,
     pub cdn_size: IntGauge,
+    pub wsi_cache_hits: IntCounterVec,
+    pub wsi_cache_misses: IntCounter,
+    pub wsi_errors: IntCounterVec,
 }
 
 impl CountableMetrics {
@@ -30,6 +35,20 @@ impl CountableMetrics {
             latency: register_int_gauge_vec!("latency", "Gateway latency", &["shard"])?,
             cdn_files: register_int_gauge!("cdn_files", "Total files stored in the CDN")?,
             cdn_size: register_int_gauge!("cdn_size", "Size in bytes of the CDN")?,
+            wsi_cache_hits: register_int_counter_vec!(
+                "wsi_cache_hits",
+                "WSI results served from the cache",
+                &["tier"]
+            )?,
+            wsi_cache_misses: register_int_counter!(
+                "wsi_cache_misses",
+                "WSI jobs that were not found in the cache"
+            )?,
+            wsi_errors: register_int_counter_vec!(
+                "wsi_errors",
+                "Failed WSI jobs, by the kind of failure and what caused it",
+                &["kind", "cause"]
+            )?,
         })
     }
 }
@@ -137,4 +156,25 @@ impl GlobalMetrics {
     pub fn set_cdn_size(&self, size: i64) {
         self.processing.cdn_size.set(size);
     }
+
+    #[inline]
+    pub fn add_wsi_cache_hit(&self, tier: &str) {
+        self.processing
+            .wsi_cache_hits
+            .with_label_values(&[tier])
+            .inc();
+    }
+
+    #[inline]
+    pub fn add_wsi_cache_miss(&self) {
+        self.processing.wsi_cache_misses.inc();
+    }
+
+    #[inline]
+    pub fn add_wsi_error(&self, kind: WsiErrorKind) {
+        self.processing
+            .wsi_errors
+            .with_label_values(&[kind.as_str(), kind.cause()])
+            .inc();
+    }
 }
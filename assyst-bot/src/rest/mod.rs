@@ -8,7 +8,6 @@ use assyst_common::{
     consts::ABSOLUTE_INPUT_FILE_SIZE_LIMIT_BYTES,
     eval::{FakeEvalBody, FakeEvalImageResponse, FakeEvalMessageData},
     filetype,
-    util::UserId,
 };
 use bytes::Bytes;
 use futures::future::join_all;
@@ -16,10 +15,6 @@ use rand::thread_rng;
 use reqwest::{Client, ClientBuilder, Error, StatusCode};
 use serde::Deserialize;
 use serde_json::{from_str, json};
-use shared::{
-    fifo::{FifoData, FifoSend},
-    query_params::NoneQuery,
-};
 use tokio::time::{timeout, Instant};
 
 use std::error::Error as StdError;
@@ -27,7 +22,6 @@ use std::error::Error as StdError;
 use crate::{
     assyst::Assyst,
     downloader::{self, download_content_trusted},
-    rest::wsi::run_wsi_job,
     util,
 };
 
@@ -43,6 +37,7 @@ pub mod patreon;
 pub mod rust;
 pub mod wombo;
 pub mod wsi;
+pub mod wsi_pool;
 
 mod routes {
     use assyst_common::consts::BOT_ID;
@@ -376,19 +371,18 @@ impl HealthcheckResult {
 pub async fn healthcheck(assyst: Arc<Assyst>) -> Vec<HealthcheckResult> {
     let mut results = Vec::<HealthcheckResult>::new();
 
-    let timer = Instant::now();
-
-    let wsi_result = run_wsi_job(
-        assyst.clone(),
-        FifoSend::Stats(FifoData::new(vec![], NoneQuery {})),
-        UserId::new(1),
-    )
-    .await;
-    results.push(HealthcheckResult::new_from_result(
-        "WSI",
-        wsi_result,
-        timer.elapsed().as_millis() as _,
-    ));
+    // failing WSI backends are ejected, so that jobs are routed to the healthy ones
+    for (index, backend) in assyst.config.wsi_backends().iter().enumerate() {
+        let timer = Instant::now();
+        let wsi_result = wsi::stats(assyst.clone(), index).await;
+        assyst.wsi.set_ejected(index, wsi_result.is_err());
+
+        results.push(HealthcheckResult::new_from_result(
+            &format!("WSI ({})", backend.address),
+            wsi_result,
+            timer.elapsed().as_millis() as _,
+        ));
+    }
 
     let timer = Instant::now();
     let rule34_result = get_random_rule34(&*assyst, "").await;
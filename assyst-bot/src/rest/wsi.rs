@@ -1,39 +1,36 @@
+use crate::caching::wsi_cache::cache_key;
+use crate::command::context::Context;
+use crate::rest::wsi_pool::JobOutcome;
 use crate::util::get_wsi_request_tier;
 use crate::{assyst::Assyst, util::handle_job_result};
 use assyst_common::util::UserId;
 use bincode::{deserialize, serialize};
 use bytes::Bytes;
 use reqwest::Error;
-use serde::Deserialize;
 use shared::errors::ProcessingError;
 use shared::response_data::{ImageInfo, Stats};
 use shared::{
-    fifo::{FifoData, FifoSend, WsiRequest},
-    job::JobResult,
+    fifo::{FifoData, FifoSend},
     query_params::*,
 };
-use std::sync::atomic::AtomicBool;
 use std::{
-    collections::HashMap,
     future::Future,
     pin::Pin,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
-    },
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant},
 };
+use tokio::sync::{mpsc, oneshot};
 use tokio::time::timeout;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-    sync::{
-        mpsc::UnboundedReceiver,
-        oneshot::{self, Sender},
-        Mutex,
-    },
-    time::sleep,
-};
+
+/// Minimum time between edits of the status message with the progress of a job
+const PROGRESS_EDIT_INTERVAL: Duration = Duration::from_secs(3);
+
+tokio::task_local! {
+    /// The command that is running WSI jobs, if any
+    ///
+    /// Jobs report their progress in the reply of this command, and are cancelled when its invocation is deleted.
+    pub static COMMAND_CONTEXT: Arc<Context>;
+}
 
 pub type NoArgFunction = Box<
     dyn Fn(
@@ -45,175 +42,281 @@ pub type NoArgFunction = Box<
         + Sync,
 >;
 
-static CONNECTED: AtomicBool = AtomicBool::new(false);
-
-pub async fn wsi_listen(
-    job_rx: UnboundedReceiver<(Sender<JobResult>, FifoSend, usize)>,
-    socket: &str,
-) {
-    let job_rx = Arc::new(Mutex::new(job_rx));
-
-    loop {
-        let stream = match TcpStream::connect(socket).await {
-            Ok(stream) => stream,
-            Err(_) => {
-                sleep(Duration::from_secs(10)).await;
-                continue;
-            }
-        };
-
-        println!("Connected to WSI");
-
-        CONNECTED.store(true, Ordering::Relaxed);
-
-        let (mut reader, mut writer) = stream.into_split();
+pub async fn run_wsi_job(
+    assyst: Arc<Assyst>,
+    job: FifoSend,
+    user_id: UserId,
+) -> Result<Bytes, RequestError> {
+    let premium_level = get_wsi_request_tier(&assyst.clone(), user_id)
+        .await
+        .map_err(RequestError::Sqlx)?;
 
-        let jobs = Arc::new(Mutex::new(HashMap::<usize, Sender<JobResult>>::new()));
-        let jobs_clone = jobs.clone();
-        let jobs_clone_2 = jobs.clone();
+    // the same command is often run on the same popular images, so results are cached
+    let key = (is_cacheable(&job) && assyst.wsi_cache.is_enabled())
+        .then(|| cache_key(&serialize(&job).unwrap(), premium_level));
 
-        let mut r = tokio::spawn(async move {
-            loop {
-                let length = match reader.read_u32().await {
-                    Err(e) => {
-                        eprintln!("Failed to read length from WSI: {:?}", e);
-                        break;
-                    }
-                    Ok(x) => x,
-                };
-                let mut buf = vec![0; length as usize];
-                match reader.read_exact(&mut buf).await {
-                    Err(e) => {
-                        eprintln!("Failed to read buffer from WSI: {:?}", e);
-                        break;
-                    }
-                    _ => {}
-                }
+    if let Some(key) = &key {
+        if let Some((result, tier)) = assyst.wsi_cache.get(key).await {
+            assyst.metrics.add_wsi_cache_hit(tier.as_str());
+            return Ok(result);
+        }
 
-                let deserialized = match deserialize::<JobResult>(&buf) {
-                    Ok(x) => x,
-                    Err(e) => {
-                        eprintln!("Failed to deserialize WSI data: {:?}", e);
-                        continue;
-                    }
-                };
+        assyst.metrics.add_wsi_cache_miss();
+    }
 
-                let job_id = deserialized.id();
-                let tx = jobs_clone.lock().await.remove(&job_id);
+    let result = run_job(assyst.clone(), job, premium_level, None).await?;
 
-                if let Some(tx) = tx {
-                    // if this fails it means it timed out
-                    let res = tx.send(deserialized);
-                    if res.is_err() {
-                        eprintln!("Failed to send job result ID {} to job sender", job_id);
-                    }
-                }
-            }
-        });
+    if let Some(key) = key {
+        assyst.wsi_cache.insert(key, result.clone()).await;
+    }
 
-        let job_rx_clone = job_rx.clone();
+    Ok(result)
+}
+
+/// Whether the result of a job only depends on its input, operation and arguments, so that it can be cached
+///
+/// Operations are listed explicitly, so that a new one is only cached once it's known to be deterministic.
+/// Scrambling, spreading and ImageMagick scripts are random, and stats change over time.
+fn is_cacheable(job: &FifoSend) -> bool {
+    matches!(
+        job,
+        FifoSend::_3dRotate(_)
+            | FifoSend::AhShit(_)
+            | FifoSend::AprilFools(_)
+            | FifoSend::Audio(_)
+            | FifoSend::AudioPcm(_)
+            | FifoSend::Bloom(_)
+            | FifoSend::Blur(_)
+            | FifoSend::Caption(_)
+            | FifoSend::ConvertPng(_)
+            | FifoSend::DeepFry(_)
+            | FifoSend::FishEye(_)
+            | FifoSend::FixTransparency(_)
+            | FifoSend::Flash(_)
+            | FifoSend::Flip(_)
+            | FifoSend::Flop(_)
+            | FifoSend::FrameShift(_)
+            | FifoSend::Frames(_)
+            | FifoSend::Ghost(_)
+            | FifoSend::GifLoop(_)
+            | FifoSend::GifMagik(_)
+            | FifoSend::GifSpeed(_)
+            | FifoSend::Globe(_)
+            | FifoSend::Grayscale(_)
+            | FifoSend::HeartLocketText(_)
+            | FifoSend::ImageInfo(_)
+            | FifoSend::Invert(_)
+            | FifoSend::Jpeg(_)
+            | FifoSend::Magik(_)
+            | FifoSend::Makesweet(_)
+            | FifoSend::Meme(_)
+            | FifoSend::Motivate(_)
+            | FifoSend::Neon(_)
+            | FifoSend::Overlay(_)
+            | FifoSend::Paint(_)
+            | FifoSend::Pixelate(_)
+            | FifoSend::Printer(_)
+            | FifoSend::Rainbow(_)
+            | FifoSend::Resize(_)
+            | FifoSend::Reverse(_)
+            | FifoSend::Rotate(_)
+            | FifoSend::SetLoop(_)
+            | FifoSend::SpeechBubble(_)
+            | FifoSend::Spin(_)
+            | FifoSend::Swirl(_)
+            | FifoSend::Uncaption(_)
+            | FifoSend::VideoToGif(_)
+            | FifoSend::Wall(_)
+            | FifoSend::Wave(_)
+            | FifoSend::Wormhole(_)
+            | FifoSend::Zoom(_)
+            | FifoSend::ZoomBlur(_)
+    )
+}
+
+/// Rejects inputs that can't be media before they are sent to WSI
+///
+/// Links to a web page instead of the file itself are downloaded as HTML, which WSI would only fail to decode.
+fn check_input_format(assyst: &Assyst, input: &[u8]) -> Result<(), WsiError> {
+    let head = &input[..input.len().min(64)];
+    let head = String::from_utf8_lossy(head)
+        .trim_start()
+        .to_ascii_lowercase();
+
+    if input.is_empty() || head.starts_with("<!doctype html") || head.starts_with("<html") {
+        assyst.metrics.add_wsi_error(WsiErrorKind::BadFormat);
+        return Err(WsiError::from_kind(WsiErrorKind::BadFormat));
+    }
 
-        let mut w = tokio::spawn(async move {
-            let next_job_id = AtomicUsize::new(0);
+    Ok(())
+}
 
-            loop {
-                let (tx, job, premium_level) = match job_rx_clone.lock().await.recv().await {
-                    Some(x) => x,
-                    None => break,
-                };
+/// Runs a job on the given backend, or the least busy one if `backend` is `None`
+async fn run_job(
+    assyst: Arc<Assyst>,
+    job: FifoSend,
+    premium_level: usize,
+    backend: Option<usize>,
+) -> Result<Bytes, RequestError> {
+    // shortly after losing the connection, jobs are queued until WSI is back
+    if backend.is_none() && !assyst.wsi.is_reachable(premium_level) {
+        let error = WsiError::new(
+            WsiErrorKind::Unavailable,
+            "Assyst cannot establish a connection to the image server at this time. Try again in a few minutes.",
+        );
+        assyst.metrics.add_wsi_error(error.kind);
+        return Err(RequestError::Wsi(error));
+    }
 
-                let id = next_job_id.fetch_add(1, Ordering::Relaxed);
+    // 3 minute timeout
+    const MAX_TIME_LIMIT: Duration = Duration::from_secs(60 * 3);
 
-                let wsi_request = WsiRequest::new(id, premium_level, job);
-                let job = serialize(&wsi_request).unwrap();
+    let context = COMMAND_CONTEXT.try_with(|context| context.clone()).ok();
 
-                jobs.lock().await.insert(id, tx);
+    let (tx, mut rx) = oneshot::channel::<JobOutcome>();
+    let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
+    let progress_tx = context.as_ref().map(|_| progress_tx);
+    let id = assyst
+        .wsi
+        .submit(tx, progress_tx, job, premium_level, backend);
 
-                match writer.write_u32(job.len() as u32).await {
-                    Err(e) => {
-                        println!("Failed to write to WSI: {:?}", e.to_string());
-                        break;
-                    }
-                    _ => {}
-                }
+    let wait = async {
+        let Some(context) = context else {
+            return Some(rx.await);
+        };
 
-                match writer.write_all(&job).await {
-                    Err(e) => {
-                        println!("Failed to write to WSI: {:?}", e.to_string());
-                        break;
+        let mut deleted = context.reply.lock().await.watch_deleted();
+        let mut last_edit: Option<Instant> = None;
+
+        loop {
+            tokio::select! {
+                result = &mut rx => return Some(result),
+                Some(progress) = progress_rx.recv() => {
+                    if last_edit.map_or(true, |t| t.elapsed() >= PROGRESS_EDIT_INTERVAL) {
+                        last_edit = Some(Instant::now());
+                        let _ = context
+                            .reply_with_text(format!("processing... {}", progress))
+                            .await;
                     }
-                    _ => {}
                 }
+                Ok(_) = deleted.wait_for(|deleted| *deleted) => return None,
             }
-        });
-
-        let _ = tokio::select! {
-            output = &mut r => { w.abort(); output },
-            output = &mut w => { r.abort(); output },
-        };
+        }
+    };
 
-        CONNECTED.store(false, Ordering::Relaxed);
-        let mut lock = jobs_clone_2.lock().await;
-        let keys = lock.keys().map(|x| *x).collect::<Vec<_>>().clone();
-
-        for job in keys {
-            let tx = lock.remove(&job).unwrap();
-            let _ = tx.send(JobResult::new_err(
-                0,
-                ProcessingError::Other(
-                    "The image server died. Try again in a few seconds.".to_owned(),
-                ),
-            ));
+    let res = timeout(MAX_TIME_LIMIT, wait).await;
+    let result = match res {
+        Err(_) => {
+            assyst.wsi.cancel(id);
+            Err(WsiError::from_kind(WsiErrorKind::Timeout).into())
         }
+        Ok(None) => {
+            assyst.wsi.cancel(id);
+            Err(WsiError::from_kind(WsiErrorKind::Cancelled).into())
+        }
+        Ok(Some(Ok(Ok(result)))) => handle_job_result(result),
+        Ok(Some(Ok(Err(e)))) => Err(e.into()),
+        Ok(Some(Err(_))) => Err(WsiError::new(
+            WsiErrorKind::Unavailable,
+            "The image server died. Try again in a couple of minutes.",
+        )
+        .into()),
+    };
 
-        eprintln!("Lost connection to WSI server, attempting reconnection in 10 sec...");
-        sleep(Duration::from_secs(10)).await;
+    if let Err(RequestError::Wsi(e)) = &result {
+        assyst.metrics.add_wsi_error(e.kind);
     }
-}
 
-pub async fn run_wsi_job(
-    assyst: Arc<Assyst>,
-    job: FifoSend,
-    user_id: UserId,
-) -> Result<Bytes, RequestError> {
-    if !CONNECTED.load(Ordering::Relaxed) {
-        return Err(RequestError::Wsi(
-            WsiError {
-                message: "Assyst cannot establish a connection to the image server at this time. Try again in a few minutes.".to_string().into(),
-                code: 0,
-            }
-        ));
+    result
+}
+
+/// Why a WSI job failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WsiErrorKind {
+    /// The job took longer than the time limit
+    Timeout,
+    /// WSI failed to process the input, without saying whether the input or WSI itself was at fault
+    Processing,
+    /// The input isn't in a format that can be processed, so it wasn't sent to WSI
+    BadFormat,
+    /// The input is larger than WSI accepts
+    InputTooLarge,
+    /// Too many jobs are waiting for a result
+    Overloaded,
+    /// There is no connection to WSI, or it was lost while the job was running
+    Unavailable,
+    /// The job no longer needed a result, because the invocation was deleted
+    Cancelled,
+}
+impl WsiErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WsiErrorKind::Timeout => "timeout",
+            WsiErrorKind::Processing => "processing",
+            WsiErrorKind::BadFormat => "bad_format",
+            WsiErrorKind::InputTooLarge => "input_too_large",
+            WsiErrorKind::Overloaded => "overloaded",
+            WsiErrorKind::Unavailable => "unavailable",
+            WsiErrorKind::Cancelled => "cancelled",
+        }
     }
 
-    let premium_level = get_wsi_request_tier(&assyst.clone(), user_id)
-        .await
-        .map_err(RequestError::Sqlx)?;
-
-    // 3 minute timeout
-    const MAX_TIME_LIMIT: Duration = Duration::from_secs(60 * 3);
-
-    let (tx, rx) = oneshot::channel::<JobResult>();
-    assyst.send_to_wsi(tx, job, premium_level);
-
-    let res = timeout(MAX_TIME_LIMIT, rx).await;
-    let result = match res {
-        Err(_) => JobResult::new_err(0, ProcessingError::Timeout),
-        Ok(x) => x.unwrap_or(JobResult::new_err(
-            0,
-            ProcessingError::Other(
-                "The image server died. Try again in a couple of minutes.".to_string(),
-            ),
-        )),
-    };
+    /// What caused the failure: the user, the infrastructure behind WSI, or nothing that can be told apart
+    pub fn cause(&self) -> &'static str {
+        match self {
+            WsiErrorKind::BadFormat | WsiErrorKind::InputTooLarge | WsiErrorKind::Cancelled => {
+                "user"
+            }
+            WsiErrorKind::Timeout | WsiErrorKind::Overloaded | WsiErrorKind::Unavailable => {
+                "infrastructure"
+            }
+            // WSI reports every failure that isn't a timeout the same way
+            WsiErrorKind::Processing => "unknown",
+        }
+    }
 
-    handle_job_result(result)
+    fn default_message(&self) -> &'static str {
+        match self {
+            WsiErrorKind::Timeout => {
+                "The image server took too long to process this. Try again with a smaller input."
+            }
+            WsiErrorKind::Processing => "The image server failed to process the input.",
+            WsiErrorKind::BadFormat => {
+                "The input doesn't look like an image or video. Make sure the link points to the file itself."
+            }
+            WsiErrorKind::InputTooLarge => {
+                "The input is too large for the image server. Try again with a smaller input."
+            }
+            WsiErrorKind::Overloaded => {
+                "The image server is overloaded. Try again in a few minutes."
+            }
+            WsiErrorKind::Unavailable => {
+                "The image server is unavailable. Try again in a few minutes."
+            }
+            WsiErrorKind::Cancelled => {
+                "The invocation for this command was deleted, so the job was cancelled."
+            }
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Debug)]
 pub struct WsiError {
-    pub code: u16,
+    pub kind: WsiErrorKind,
     pub message: Box<str>,
 }
+impl WsiError {
+    pub fn new(kind: WsiErrorKind, message: impl Into<Box<str>>) -> Self {
+        WsiError {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn from_kind(kind: WsiErrorKind) -> Self {
+        WsiError::new(kind, kind.default_message())
+    }
+}
 
 #[derive(Debug)]
 pub enum RequestError {
@@ -222,12 +325,20 @@ pub enum RequestError {
     Wsi(WsiError),
     Sqlx(anyhow::Error),
 }
+impl From<WsiError> for RequestError {
+    fn from(e: WsiError) -> Self {
+        RequestError::Wsi(e)
+    }
+}
 impl From<ProcessingError> for RequestError {
     fn from(e: ProcessingError) -> Self {
-        RequestError::Wsi(WsiError {
-            code: 0,
-            message: e.to_string().into(),
-        })
+        // every variant is matched, so that a new kind of failure can't silently be counted as a processing error
+        let error = match e {
+            ProcessingError::Timeout => WsiError::from_kind(WsiErrorKind::Timeout),
+            // WSI describes what went wrong in its message
+            ProcessingError::Other(message) => WsiError::new(WsiErrorKind::Processing, message),
+        };
+        RequestError::Wsi(error)
     }
 }
 
@@ -269,6 +380,8 @@ pub async fn _3d_rotate(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::_3dRotate(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -279,6 +392,8 @@ pub async fn ahshit(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::AhShit(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -289,6 +404,8 @@ pub async fn aprilfools(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::AprilFools(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -300,6 +417,8 @@ pub async fn audio(
     effect: &str,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Audio(FifoData::new(
         image.to_vec(),
         AudioQueryParams {
@@ -325,6 +444,8 @@ pub async fn back_tattoo(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Makesweet(FifoData::new(
         vec![],
         MakesweetQueryParams {
@@ -341,6 +462,8 @@ pub async fn book(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Makesweet(FifoData::new(
         vec![],
         MakesweetQueryParams {
@@ -357,6 +480,8 @@ pub async fn billboard(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Makesweet(FifoData::new(
         vec![],
         MakesweetQueryParams {
@@ -374,6 +499,8 @@ pub async fn blur(
     user_id: UserId,
     power: &str,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Blur(FifoData::new(
         image.to_vec(),
         BlurQueryParams {
@@ -392,6 +519,8 @@ pub async fn bloom(
     brightness: usize,
     sharpness: usize,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Bloom(FifoData::new(
         image.to_vec(),
         BloomQueryParams {
@@ -410,6 +539,8 @@ pub async fn caption(
     user_id: UserId,
     text: &str,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Caption(FifoData::new(
         image.to_vec(),
         CaptionQueryParams {
@@ -425,6 +556,8 @@ pub async fn circuitboard(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Makesweet(FifoData::new(
         vec![],
         MakesweetQueryParams {
@@ -441,6 +574,8 @@ pub async fn convert_png(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::ConvertPng(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -451,6 +586,8 @@ pub async fn deepfry(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::DeepFry(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -461,6 +598,8 @@ pub async fn fisheye(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::FishEye(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -471,6 +610,8 @@ pub async fn fix_transparency(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::FixTransparency(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -481,6 +622,8 @@ pub async fn flash(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Flash(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -491,6 +634,8 @@ pub async fn flip(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Flip(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -501,6 +646,8 @@ pub async fn flop(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Flop(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -513,6 +660,8 @@ pub async fn motivate(
     top_text: &str,
     bottom_text: &str,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Motivate(FifoData::new(
         image.to_vec(),
         MotivateQueryParams {
@@ -529,6 +678,8 @@ pub async fn flag(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Makesweet(FifoData::new(
         vec![],
         MakesweetQueryParams {
@@ -545,6 +696,8 @@ pub async fn flag2(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Makesweet(FifoData::new(
         vec![],
         MakesweetQueryParams {
@@ -561,6 +714,8 @@ pub async fn frames(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Frames(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -571,6 +726,8 @@ pub async fn fortune_cookie(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Makesweet(FifoData::new(
         vec![],
         MakesweetQueryParams {
@@ -588,6 +745,8 @@ pub async fn ghost(
     user_id: UserId,
     depth: &str,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Ghost(FifoData::new(
         image.to_vec(),
         GhostQueryParams {
@@ -603,6 +762,8 @@ pub async fn gif_loop(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::GifLoop(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -613,6 +774,8 @@ pub async fn gif_magik(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::GifMagik(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -623,6 +786,8 @@ pub async fn gif_scramble(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::GifScramble(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -633,6 +798,8 @@ pub async fn grayscale(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Grayscale(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -644,6 +811,8 @@ pub async fn heart_locket(
     text: &str,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let text_job = FifoSend::HeartLocketText(FifoData::new(
         vec![],
         HeartLocketTextQueryParams {
@@ -680,6 +849,8 @@ pub async fn gif_speed(
     user_id: UserId,
     delay: Option<&str>,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::GifSpeed(FifoData::new(
         image.to_vec(),
         GifSpeedQueryParams {
@@ -691,6 +862,8 @@ pub async fn gif_speed(
 }
 
 pub async fn image_info(assyst: Arc<Assyst>, image: Bytes) -> Result<ImageInfo, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::ImageInfo(FifoData::new(image.to_vec(), NoneQuery {}));
 
     let result = run_wsi_job(assyst, job, UserId::new(1)).await?;
@@ -706,6 +879,8 @@ pub async fn imagemagick_eval(
     user_id: UserId,
     script: &str,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::ImageMagickEval(FifoData::new(
         image.to_vec(),
         ImageMagickEvalQueryParams {
@@ -721,6 +896,8 @@ pub async fn invert(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Invert(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -731,6 +908,8 @@ pub async fn frame_shift(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::FrameShift(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -741,6 +920,8 @@ pub async fn jpeg(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Jpeg(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -751,6 +932,8 @@ pub async fn globe(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Globe(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -761,6 +944,8 @@ pub async fn magik(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Magik(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -773,6 +958,8 @@ pub async fn meme(
     top: &str,
     bottom: &str,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Meme(FifoData::new(
         image.to_vec(),
         MemeQueryParams {
@@ -790,6 +977,8 @@ pub async fn neon(
     user_id: UserId,
     radius: usize,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Neon(FifoData::new(image.to_vec(), NeonQueryParams { radius }));
 
     run_wsi_job(assyst, job, user_id).await
@@ -800,6 +989,8 @@ pub async fn paint(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Paint(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -811,6 +1002,8 @@ pub async fn pixelate(
     user_id: UserId,
     downscaled_height: Option<usize>,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Pixelate(FifoData::new(
         image.to_vec(),
         PixelateQueryParams { downscaled_height },
@@ -824,6 +1017,8 @@ pub async fn printer(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Printer(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -834,6 +1029,8 @@ pub async fn rainbow(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Rainbow(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -845,6 +1042,8 @@ pub async fn resize(
     user_id: UserId,
     method: ResizeMethod,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Resize(FifoData::new(
         image.to_vec(),
         ResizeQueryParams {
@@ -865,6 +1064,8 @@ pub async fn resize_scale(
     scale: f32,
     method: ResizeMethod,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Resize(FifoData::new(
         image.to_vec(),
         ResizeQueryParams {
@@ -886,6 +1087,8 @@ pub async fn resize_width_height(
     height: usize,
     method: ResizeMethod,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Resize(FifoData::new(
         image.to_vec(),
         ResizeQueryParams {
@@ -905,6 +1108,8 @@ pub async fn overlay(
     user_id: UserId,
     overlay: &str,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Overlay(FifoData::new(
         image.to_vec(),
         OverlayQueryParams {
@@ -920,6 +1125,8 @@ pub async fn reverse(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Reverse(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -931,6 +1138,8 @@ pub async fn rotate(
     user_id: UserId,
     degrees: &str,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Rotate(FifoData::new(
         image.to_vec(),
         RotateQueryParams {
@@ -946,6 +1155,8 @@ pub async fn rubiks(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Makesweet(FifoData::new(
         vec![],
         MakesweetQueryParams {
@@ -970,6 +1181,8 @@ pub async fn set_loop(
     user_id: UserId,
     looping: bool,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::SetLoop(FifoData::new(
         image.to_vec(),
         SetLoopQueryParams { r#loop: looping },
@@ -983,6 +1196,8 @@ pub async fn speechbubble(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::SpeechBubble(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -993,6 +1208,8 @@ pub async fn spin(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Spin(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -1003,15 +1220,17 @@ pub async fn spread(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Spread(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
 }
 
-pub async fn stats(assyst: Arc<Assyst>) -> Result<Stats, RequestError> {
+pub async fn stats(assyst: Arc<Assyst>, backend: usize) -> Result<Stats, RequestError> {
     let job = FifoSend::Stats(FifoData::new(vec![], NoneQuery {}));
 
-    let result = run_wsi_job(assyst, job, UserId::new(1)).await?;
+    let result = run_job(assyst, job, 0, Some(backend)).await?;
     Ok(deserialize::<Stats>(&result).unwrap())
 }
 
@@ -1020,6 +1239,8 @@ pub async fn swirl(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Swirl(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -1030,6 +1251,8 @@ pub async fn toaster(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Makesweet(FifoData::new(
         vec![],
         MakesweetQueryParams {
@@ -1047,6 +1270,8 @@ pub async fn uncaption(
     user_id: UserId,
     lines: Option<String>,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Uncaption(FifoData::new(
         image.to_vec(),
         UncaptionQueryParams { lines },
@@ -1060,6 +1285,8 @@ pub async fn valentine(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Makesweet(FifoData::new(
         vec![],
         MakesweetQueryParams {
@@ -1076,6 +1303,8 @@ pub async fn videotogif(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::VideoToGif(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -1086,6 +1315,8 @@ pub async fn wall(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Wall(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -1096,6 +1327,8 @@ pub async fn wave(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Wave(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -1106,6 +1339,8 @@ pub async fn wormhole(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Wormhole(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -1116,6 +1351,8 @@ pub async fn zoom(
     image: Bytes,
     user_id: UserId,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::Zoom(FifoData::new(image.to_vec(), NoneQuery {}));
 
     run_wsi_job(assyst, job, user_id).await
@@ -1127,6 +1364,8 @@ pub async fn zoom_blur(
     user_id: UserId,
     factor: f64,
 ) -> Result<Bytes, RequestError> {
+    check_input_format(&assyst, &image)?;
+
     let job = FifoSend::ZoomBlur(FifoData::new(
         image.to_vec(),
         ZoomBlurQueryParams { factor },
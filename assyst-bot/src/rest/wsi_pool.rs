@@ -0,0 +1,848 @@
+use super::wsi::{WsiError, WsiErrorKind};
+use assyst_common::{config::WsiBackend, consts, util::get_current_millis};
+use bincode::{deserialize, serialize};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use shared::{
+    fifo::{FifoSend, WsiRequest},
+    job::JobResult,
+};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{mpsc::UnboundedSender, oneshot::Sender, Notify},
+    time::sleep,
+};
+
+/// Maximum number of jobs waiting for a result at once, including jobs waiting for WSI to reconnect
+const MAX_QUEUED_JOBS: usize = 1000;
+/// Maximum size of a serialized job, which is the largest input plus some room for its arguments
+///
+/// This also keeps the length prefix of jobs clear of [`CONTROL_FRAME`].
+const MAX_REQUEST_BYTES: usize = consts::ABSOLUTE_INPUT_FILE_SIZE_LIMIT_BYTES + 1_000_000;
+/// How many times a job is sent in total, if the connection keeps getting lost while it's running
+const MAX_JOB_ATTEMPTS: usize = 3;
+/// How long new jobs are still queued after losing the connection, before they're rejected right away
+///
+/// This is long enough for WSI to restart during a deploy.
+const RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(60);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+/// Set in the length prefix of control frames, which are exchanged alongside job requests and results
+const CONTROL_FRAME: u32 = 1 << 31;
+/// Version of the layout of [`ControlFrame`], which WSI announces in [`ControlFrame::Hello`]
+///
+/// Control frames are only exchanged with a backend that announced this exact version. Older WSI servers don't
+/// announce any, and a backend with a different layout could misread them, so both only receive job requests.
+const CONTROL_PROTOCOL_VERSION: u32 = 1;
+
+/// Frames other than job requests and results, which are bincode serialized like them
+///
+/// New variants need to be added at the end, since bincode identifies variants by their index.
+#[derive(Serialize, Deserialize)]
+enum ControlFrame {
+    /// Sent by WSI while a job is running
+    Progress {
+        id: usize,
+        stage: String,
+        percent: Option<u8>,
+    },
+    /// Sent to WSI to stop running a job that no longer needs a result
+    Cancel { id: usize },
+    /// Sent by WSI right after accepting a connection, if it understands control frames
+    Hello { version: u32 },
+}
+
+/// An intermediate progress update of a running job
+#[derive(Debug, Clone)]
+pub struct JobProgress {
+    pub stage: String,
+    pub percent: Option<u8>,
+}
+impl std::fmt::Display for JobProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.percent {
+            Some(percent) => write!(f, "{} ({}%)", self.stage, percent.min(100)),
+            None => write!(f, "{}", self.stage),
+        }
+    }
+}
+
+/// Whether a job can safely be sent again after the connection was lost while it was running
+///
+/// Image operations have no side effects, but user provided ImageMagick scripts are the most likely
+/// reason for WSI to have died in the first place, so they're not retried.
+fn is_resendable(job: &FifoSend) -> bool {
+    !matches!(job, FifoSend::ImageMagickEval(_))
+}
+
+fn died_error() -> WsiError {
+    WsiError::new(
+        WsiErrorKind::Unavailable,
+        "The image server died. Try again in a few seconds.",
+    )
+}
+
+/// The result of a job from WSI, or the reason it never got one
+pub type JobOutcome = Result<JobResult, WsiError>;
+
+struct QueuedJob {
+    tx: Sender<JobOutcome>,
+    progress: Option<UnboundedSender<JobProgress>>,
+    /// The serialized [`WsiRequest`], which is kept so it can be resent
+    request: Bytes,
+    tier: usize,
+    /// Backend that this job has to run on, rather than being balanced
+    pinned: Option<usize>,
+    resendable: bool,
+    attempts: usize,
+    /// Backend that this job is currently assigned to
+    routed_to: Option<usize>,
+    /// Whether the job has been written to the connection of its backend
+    sent: bool,
+}
+
+/// Jobs that have not received a result yet, which outlives individual connections to WSI
+#[derive(Default)]
+struct JobQueue {
+    jobs: HashMap<usize, QueuedJob>,
+    /// IDs of jobs that no backend is available for yet
+    unrouted: VecDeque<usize>,
+    /// IDs of jobs that still need to be written, for every backend
+    unsent: Vec<VecDeque<usize>>,
+    /// Number of jobs assigned to every backend that have not received a result yet
+    in_flight: Vec<usize>,
+    /// IDs of cancelled jobs that the backend needs to be told about, for every backend
+    cancelled: Vec<VecDeque<usize>>,
+    next_job_id: usize,
+}
+
+struct Backend {
+    config: WsiBackend,
+    connected: AtomicBool,
+    /// Set by the healthcheck, jobs are only routed to ejected backends if no other one is available
+    ejected: AtomicBool,
+    /// Unix timestamp (in ms) of when the connection was last lost
+    disconnected_at: AtomicU64,
+    completed: AtomicUsize,
+    /// Number of jobs that failed because the connection was lost
+    failed: AtomicUsize,
+    /// Whether the current connection announced the same [`CONTROL_PROTOCOL_VERSION`]
+    supports_control: AtomicBool,
+    /// Wakes up the writer of this backend when a job is routed to it
+    queued: Notify,
+}
+
+/// A snapshot of the state of a backend, for `wsistats`
+pub struct BackendStats {
+    pub address: Box<str>,
+    pub min_tier: usize,
+    pub connected: bool,
+    pub ejected: bool,
+    pub in_flight: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+/// Connections to all WSI backends
+///
+/// Jobs are routed to the connected backend with the least jobs in flight, out of the backends that the premium tier
+/// of the job allows. If the connection to a backend is lost, its jobs are resent to another backend (or the same one
+/// once it reconnects).
+pub struct WsiPool {
+    backends: Vec<Backend>,
+    queue: Mutex<JobQueue>,
+}
+impl WsiPool {
+    pub fn new(backends: Vec<WsiBackend>) -> Arc<Self> {
+        assert!(!backends.is_empty(), "at least one WSI backend is required");
+
+        let queue = JobQueue {
+            unsent: vec![VecDeque::new(); backends.len()],
+            in_flight: vec![0; backends.len()],
+            cancelled: vec![VecDeque::new(); backends.len()],
+            ..Default::default()
+        };
+
+        Arc::new(WsiPool {
+            backends: backends
+                .into_iter()
+                .map(|config| Backend {
+                    config,
+                    connected: AtomicBool::new(false),
+                    ejected: AtomicBool::new(false),
+                    disconnected_at: AtomicU64::new(0),
+                    completed: AtomicUsize::new(0),
+                    failed: AtomicUsize::new(0),
+                    supports_control: AtomicBool::new(false),
+                    queued: Notify::new(),
+                })
+                .collect(),
+            queue: Mutex::new(queue),
+        })
+    }
+
+    /// Spawns the connection tasks of every backend
+    pub fn start(self: &Arc<Self>) {
+        for index in 0..self.backends.len() {
+            tokio::spawn(self.clone().listen(index));
+        }
+    }
+
+    /// Whether jobs of the given tier can currently be run
+    ///
+    /// Shortly after losing the connection to a backend it still counts as reachable, since jobs are queued until it's back.
+    pub fn is_reachable(&self, tier: usize) -> bool {
+        let now = get_current_millis();
+
+        self.backends
+            .iter()
+            .filter(|b| b.config.min_tier <= tier)
+            .any(|b| {
+                b.connected.load(Ordering::Relaxed)
+                    || now.saturating_sub(b.disconnected_at.load(Ordering::Relaxed))
+                        <= RECONNECT_GRACE_PERIOD.as_millis() as u64
+            })
+    }
+
+    /// Queues a job, optionally on a specific backend, and returns its ID
+    ///
+    /// Progress updates of the job are sent to `progress`, if WSI reports any.
+    pub fn submit(
+        &self,
+        tx: Sender<JobOutcome>,
+        progress: Option<UnboundedSender<JobProgress>>,
+        job: FifoSend,
+        tier: usize,
+        pinned: Option<usize>,
+    ) -> usize {
+        let mut queue = self.queue.lock().unwrap();
+
+        let id = queue.next_job_id;
+        queue.next_job_id += 1;
+
+        if queue.jobs.len() >= MAX_QUEUED_JOBS {
+            // jobs whose caller timed out don't need a result anymore
+            let closed = queue
+                .jobs
+                .iter()
+                .filter(|(_, job)| job.tx.is_closed())
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            for closed_id in closed {
+                Self::remove(&mut queue, closed_id);
+            }
+        }
+
+        if queue.jobs.len() >= MAX_QUEUED_JOBS {
+            let _ = tx.send(Err(WsiError::from_kind(WsiErrorKind::Overloaded)));
+            return id;
+        }
+
+        if let Some(index) = pinned.filter(|&i| !self.backends[i].connected.load(Ordering::Relaxed))
+        {
+            let _ = tx.send(Err(WsiError::new(
+                WsiErrorKind::Unavailable,
+                format!(
+                    "Not connected to the image server at {}",
+                    self.backends[index].config.address
+                ),
+            )));
+            return id;
+        }
+
+        let resendable = is_resendable(&job);
+        let request = Bytes::from(serialize(&WsiRequest::new(id, tier, job)).unwrap());
+
+        if request.len() > MAX_REQUEST_BYTES {
+            let _ = tx.send(Err(WsiError::from_kind(WsiErrorKind::InputTooLarge)));
+            return id;
+        }
+
+        queue.jobs.insert(
+            id,
+            QueuedJob {
+                tx,
+                progress,
+                request,
+                tier,
+                pinned,
+                resendable,
+                attempts: 0,
+                routed_to: None,
+                sent: false,
+            },
+        );
+        self.route(&mut queue, id);
+
+        id
+    }
+
+    /// Drops a job that no longer needs a result, telling its backend to stop running it if it was already sent
+    pub fn cancel(&self, id: usize) {
+        let mut queue = self.queue.lock().unwrap();
+
+        let Some(job) = Self::remove(&mut queue, id) else {
+            return;
+        };
+
+        if let Some(index) = job.routed_to.filter(|_| job.sent) {
+            let backend = &self.backends[index];
+            if backend.supports_control.load(Ordering::Relaxed) {
+                queue.cancelled[index].push_back(id);
+                backend.queued.notify_one();
+            }
+        }
+    }
+
+    /// Marks a backend as failing (or recovered), so that jobs avoid it
+    pub fn set_ejected(&self, index: usize, ejected: bool) {
+        let was_ejected = self.backends[index]
+            .ejected
+            .swap(ejected, Ordering::Relaxed);
+
+        if was_ejected && !ejected {
+            self.route_unrouted();
+        }
+    }
+
+    pub fn stats(&self) -> Vec<BackendStats> {
+        let queue = self.queue.lock().unwrap();
+
+        self.backends
+            .iter()
+            .zip(&queue.in_flight)
+            .map(|(b, in_flight)| BackendStats {
+                address: b.config.address.clone(),
+                min_tier: b.config.min_tier,
+                connected: b.connected.load(Ordering::Relaxed),
+                ejected: b.ejected.load(Ordering::Relaxed),
+                in_flight: *in_flight,
+                completed: b.completed.load(Ordering::Relaxed),
+                failed: b.failed.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Assigns a job to a backend, or leaves it for later if none is available
+    fn route(&self, queue: &mut JobQueue, id: usize) {
+        let job = &queue.jobs[&id];
+
+        let target = match job.pinned {
+            Some(index) => Some(index),
+            None => (0..self.backends.len())
+                .filter(|&i| {
+                    let backend = &self.backends[i];
+                    backend.connected.load(Ordering::Relaxed) && backend.config.min_tier <= job.tier
+                })
+                // prefer healthy backends, then the least busy ones, then the most exclusive ones
+                .min_by_key(|&i| {
+                    let backend = &self.backends[i];
+                    (
+                        backend.ejected.load(Ordering::Relaxed),
+                        queue.in_flight[i],
+                        Reverse(backend.config.min_tier),
+                    )
+                }),
+        };
+
+        match target {
+            Some(index) => {
+                queue.jobs.get_mut(&id).unwrap().routed_to = Some(index);
+                queue.in_flight[index] += 1;
+                queue.unsent[index].push_back(id);
+                self.backends[index].queued.notify_one();
+            }
+            None => queue.unrouted.push_back(id),
+        }
+    }
+
+    fn route_unrouted(&self) {
+        let mut queue = self.queue.lock().unwrap();
+
+        let unrouted = std::mem::take(&mut queue.unrouted);
+        for id in unrouted {
+            if queue.jobs.contains_key(&id) {
+                self.route(&mut queue, id);
+            }
+        }
+    }
+
+    fn remove(queue: &mut JobQueue, id: usize) -> Option<QueuedJob> {
+        let job = queue.jobs.remove(&id)?;
+
+        if let Some(index) = job.routed_to {
+            queue.in_flight[index] -= 1;
+        }
+
+        Some(job)
+    }
+
+    /// Returns the next frame to write to a backend along with its length flags, marking jobs as sent
+    ///
+    /// Cancellations are written first, so that WSI can free up workers for the jobs after them.
+    fn next_unsent(&self, index: usize) -> Option<(u32, Bytes)> {
+        let mut queue = self.queue.lock().unwrap();
+
+        if let Some(id) = queue.cancelled[index].pop_front() {
+            let frame = serialize(&ControlFrame::Cancel { id }).unwrap();
+            return Some((CONTROL_FRAME, Bytes::from(frame)));
+        }
+
+        while let Some(id) = queue.unsent[index].pop_front() {
+            let Some(job) = queue.jobs.get_mut(&id) else {
+                continue;
+            };
+
+            if job.tx.is_closed() {
+                Self::remove(&mut queue, id);
+                continue;
+            }
+
+            job.sent = true;
+            job.attempts += 1;
+            return Some((0, job.request.clone()));
+        }
+
+        None
+    }
+
+    fn complete(&self, index: usize, result: JobResult) {
+        let job = Self::remove(&mut self.queue.lock().unwrap(), result.id());
+
+        if let Some(job) = job {
+            self.backends[index]
+                .completed
+                .fetch_add(1, Ordering::Relaxed);
+
+            // if this fails it means it timed out
+            if job.tx.send(Ok(result)).is_err() {
+                eprintln!("Failed to send WSI job result to job sender");
+            }
+        }
+    }
+
+    fn handle_control_frame(&self, index: usize, frame: ControlFrame) {
+        let backend = &self.backends[index];
+
+        match frame {
+            ControlFrame::Hello { version } => {
+                let supported = version == CONTROL_PROTOCOL_VERSION;
+                backend.supports_control.store(supported, Ordering::Relaxed);
+
+                if !supported {
+                    eprintln!(
+                        "WSI at {} speaks control protocol version {} (expected {}), progress and cancellation are disabled",
+                        backend.config.address, version, CONTROL_PROTOCOL_VERSION
+                    );
+                }
+            }
+            // the layout of the frame can't be trusted without a matching version
+            _ if !backend.supports_control.load(Ordering::Relaxed) => {
+                eprintln!("Ignoring a WSI control frame that was sent before a supported hello");
+            }
+            ControlFrame::Progress { id, stage, percent } => {
+                let queue = self.queue.lock().unwrap();
+                let progress = queue.jobs.get(&id).and_then(|job| job.progress.as_ref());
+
+                if let Some(progress) = progress {
+                    let _ = progress.send(JobProgress { stage, percent });
+                }
+            }
+            ControlFrame::Cancel { .. } => {
+                eprintln!("Received an unexpected cancel frame from WSI");
+            }
+        }
+    }
+
+    /// Called when the connection to a backend is lost: its jobs are routed again,
+    /// or failed if they were already sent and can't be resent
+    fn handle_disconnect(&self, index: usize) {
+        let mut queue = self.queue.lock().unwrap();
+
+        let mut assigned = queue
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.routed_to == Some(index))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        // route again in the original order
+        assigned.sort_unstable();
+
+        queue.unsent[index].clear();
+        queue.cancelled[index].clear();
+        queue.in_flight[index] = 0;
+
+        for id in assigned {
+            let job = queue.jobs.get_mut(&id).unwrap();
+            job.routed_to = None;
+
+            let lost = job.sent && !(job.resendable && job.attempts < MAX_JOB_ATTEMPTS);
+            job.sent = false;
+
+            if lost || job.tx.is_closed() || job.pinned.is_some() {
+                let job = queue.jobs.remove(&id).unwrap();
+                self.backends[index].failed.fetch_add(1, Ordering::Relaxed);
+                let _ = job.tx.send(Err(died_error()));
+            } else {
+                self.route(&mut queue, id);
+            }
+        }
+    }
+
+    async fn listen(self: Arc<Self>, index: usize) {
+        let address = self.backends[index].config.address.to_string();
+
+        loop {
+            let stream = match TcpStream::connect(&address).await {
+                Ok(stream) => stream,
+                Err(_) => {
+                    sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+
+            println!("Connected to WSI at {}", address);
+
+            self.backends[index]
+                .connected
+                .store(true, Ordering::Relaxed);
+            self.route_unrouted();
+
+            let (mut reader, mut writer) = stream.into_split();
+
+            let pool = self.clone();
+
+            let mut r = tokio::spawn(async move {
+                loop {
+                    let length = match reader.read_u32().await {
+                        Err(e) => {
+                            eprintln!("Failed to read length from WSI: {:?}", e);
+                            break;
+                        }
+                        Ok(x) => x,
+                    };
+                    let mut buf = vec![0; (length & !CONTROL_FRAME) as usize];
+                    match reader.read_exact(&mut buf).await {
+                        Err(e) => {
+                            eprintln!("Failed to read buffer from WSI: {:?}", e);
+                            break;
+                        }
+                        _ => {}
+                    }
+
+                    if length & CONTROL_FRAME != 0 {
+                        match deserialize::<ControlFrame>(&buf) {
+                            Ok(frame) => pool.handle_control_frame(index, frame),
+                            Err(e) => eprintln!("Failed to deserialize WSI control frame: {:?}", e),
+                        }
+                        continue;
+                    }
+
+                    let deserialized = match deserialize::<JobResult>(&buf) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            eprintln!("Failed to deserialize WSI data: {:?}", e);
+                            continue;
+                        }
+                    };
+
+                    pool.complete(index, deserialized);
+                }
+            });
+
+            let pool = self.clone();
+
+            let mut w = tokio::spawn(async move {
+                loop {
+                    let Some((flags, frame)) = pool.next_unsent(index) else {
+                        pool.backends[index].queued.notified().await;
+                        continue;
+                    };
+
+                    match writer.write_u32(frame.len() as u32 | flags).await {
+                        Err(e) => {
+                            println!("Failed to write to WSI: {:?}", e.to_string());
+                            break;
+                        }
+                        _ => {}
+                    }
+
+                    match writer.write_all(&frame).await {
+                        Err(e) => {
+                            println!("Failed to write to WSI: {:?}", e.to_string());
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            });
+
+            let _ = tokio::select! {
+                output = &mut r => { w.abort(); output },
+                output = &mut w => { r.abort(); output },
+            };
+
+            let backend = &self.backends[index];
+            backend.connected.store(false, Ordering::Relaxed);
+            backend.supports_control.store(false, Ordering::Relaxed);
+            backend
+                .disconnected_at
+                .store(get_current_millis(), Ordering::Relaxed);
+            self.handle_disconnect(index);
+
+            eprintln!(
+                "Lost connection to WSI server at {}, attempting reconnection in 10 sec...",
+                address
+            );
+            sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{fifo::FifoData, query_params::NoneQuery};
+    use tokio::{
+        net::{tcp::OwnedWriteHalf, TcpListener},
+        sync::{mpsc, oneshot},
+    };
+
+    /// Leading fields of [`WsiRequest`] on the wire
+    #[derive(Deserialize)]
+    struct FakeRequest {
+        id: usize,
+        _premium_level: usize,
+    }
+
+    /// [`JobResult`] on the wire
+    #[derive(Serialize)]
+    struct FakeResult {
+        id: usize,
+        result: Result<Vec<u8>, ()>,
+    }
+
+    /// How a fake WSI server responds to jobs
+    #[derive(Clone, Copy)]
+    enum Behaviour {
+        /// Respond with the given byte after a delay (in ms)
+        Respond(u8, u64),
+        /// Close the connection as soon as a job is received
+        Hangup,
+        /// Report progress on every job, without ever finishing them
+        ///
+        /// The control protocol version is announced after connecting, if there is one.
+        Stall(Option<u32>),
+    }
+
+    async fn write_frame(writer: &tokio::sync::Mutex<OwnedWriteHalf>, flags: u32, frame: &[u8]) {
+        let mut writer = writer.lock().await;
+        writer.write_u32(frame.len() as u32 | flags).await.unwrap();
+        writer.write_all(frame).await.unwrap();
+    }
+
+    /// Starts a WSI server on a random port, speaking the same length-prefixed bincode protocol
+    ///
+    /// IDs of jobs that were cancelled are sent to the returned channel.
+    async fn fake_wsi(behaviour: Behaviour) -> (Box<str>, mpsc::UnboundedReceiver<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string().into();
+        let (cancelled_tx, cancelled_rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (mut reader, writer) = stream.into_split();
+                let writer = Arc::new(tokio::sync::Mutex::new(writer));
+                let cancelled_tx = cancelled_tx.clone();
+
+                if let Behaviour::Stall(Some(version)) = behaviour {
+                    let hello = serialize(&ControlFrame::Hello { version }).unwrap();
+                    write_frame(&writer, CONTROL_FRAME, &hello).await;
+                }
+
+                tokio::spawn(async move {
+                    while let Ok(length) = reader.read_u32().await {
+                        let mut buf = vec![0; (length & !CONTROL_FRAME) as usize];
+                        reader.read_exact(&mut buf).await.unwrap();
+
+                        if length & CONTROL_FRAME != 0 {
+                            if let ControlFrame::Cancel { id } = deserialize(&buf).unwrap() {
+                                let _ = cancelled_tx.send(id);
+                            }
+                            continue;
+                        }
+
+                        let request = deserialize::<FakeRequest>(&buf).unwrap();
+
+                        let (marker, delay) = match behaviour {
+                            Behaviour::Respond(marker, delay) => (marker, delay),
+                            Behaviour::Hangup => return,
+                            Behaviour::Stall(_) => {
+                                let progress = serialize(&ControlFrame::Progress {
+                                    id: request.id,
+                                    stage: "stalling".to_owned(),
+                                    percent: Some(50),
+                                })
+                                .unwrap();
+                                write_frame(&writer, CONTROL_FRAME, &progress).await;
+                                continue;
+                            }
+                        };
+
+                        let writer = writer.clone();
+                        tokio::spawn(async move {
+                            sleep(Duration::from_millis(delay)).await;
+                            let result = serialize(&FakeResult {
+                                id: request.id,
+                                result: Ok(vec![marker]),
+                            })
+                            .unwrap();
+
+                            write_frame(&writer, 0, &result).await;
+                        });
+                    }
+                });
+            }
+        });
+
+        (address, cancelled_rx)
+    }
+
+    async fn start_pool(backends: &[(Behaviour, usize)]) -> Arc<WsiPool> {
+        let mut configs = Vec::new();
+        for (behaviour, min_tier) in backends {
+            configs.push(WsiBackend {
+                address: fake_wsi(*behaviour).await.0,
+                min_tier: *min_tier,
+            });
+        }
+
+        let pool = WsiPool::new(configs);
+        pool.start();
+
+        while !pool.stats().iter().all(|b| b.connected) {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        pool
+    }
+
+    fn submit(pool: &WsiPool, tier: usize) -> oneshot::Receiver<JobOutcome> {
+        let (tx, rx) = oneshot::channel();
+        let job = FifoSend::Stats(FifoData::new(vec![], NoneQuery {}));
+        pool.submit(tx, None, job, tier, None);
+        rx
+    }
+
+    async fn marker(rx: oneshot::Receiver<JobOutcome>) -> u8 {
+        rx.await.unwrap().unwrap().result.unwrap()[0]
+    }
+
+    #[tokio::test]
+    async fn routes_by_load_and_tier() {
+        let pool = start_pool(&[
+            (Behaviour::Respond(1, 200), 0),
+            (Behaviour::Respond(2, 200), 0),
+            (Behaviour::Respond(3, 0), 1),
+        ])
+        .await;
+
+        // free jobs are spread evenly over the free backends
+        let jobs = (0..4).map(|_| submit(&pool, 0)).collect::<Vec<_>>();
+        let mut markers = Vec::new();
+        for job in jobs {
+            markers.push(marker(job).await);
+        }
+        markers.sort_unstable();
+        assert_eq!(markers, [1, 1, 2, 2]);
+
+        // premium jobs prefer the premium backend when it's not busier
+        assert_eq!(marker(submit(&pool, 1)).await, 3);
+
+        pool.set_ejected(2, true);
+        assert_ne!(marker(submit(&pool, 1)).await, 3);
+    }
+
+    #[tokio::test]
+    async fn resends_jobs_of_lost_backends() {
+        let pool = start_pool(&[(Behaviour::Hangup, 0), (Behaviour::Respond(2, 50), 0)]).await;
+
+        // the first job goes to the first backend, which dies
+        let results = (0..2).map(|_| submit(&pool, 0)).collect::<Vec<_>>();
+        for result in results {
+            assert_eq!(marker(result).await, 2);
+        }
+
+        let stats = pool.stats();
+        assert!(!stats[0].connected);
+        assert_eq!(stats[1].completed, 2);
+    }
+
+    /// Starts a pool with a single stalling backend and submits a job with progress reporting
+    async fn start_stalled_job(
+        version: Option<u32>,
+    ) -> (
+        Arc<WsiPool>,
+        usize,
+        oneshot::Receiver<JobOutcome>,
+        mpsc::UnboundedReceiver<JobProgress>,
+        mpsc::UnboundedReceiver<usize>,
+    ) {
+        let (address, cancelled) = fake_wsi(Behaviour::Stall(version)).await;
+        let pool = WsiPool::new(vec![WsiBackend {
+            address,
+            min_tier: 0,
+        }]);
+        pool.start();
+
+        let (tx, rx) = oneshot::channel();
+        let (progress_tx, progress_rx) = mpsc::unbounded_channel();
+        let job = FifoSend::Stats(FifoData::new(vec![], NoneQuery {}));
+        let id = pool.submit(tx, Some(progress_tx), job, 0, None);
+
+        (pool, id, rx, progress_rx, cancelled)
+    }
+
+    #[tokio::test]
+    async fn reports_progress_and_cancels() {
+        let (pool, id, rx, mut progress_rx, mut cancelled) =
+            start_stalled_job(Some(CONTROL_PROTOCOL_VERSION)).await;
+
+        let progress = progress_rx.recv().await.unwrap();
+        assert_eq!(progress.to_string(), "stalling (50%)");
+
+        pool.cancel(id);
+        assert_eq!(cancelled.recv().await, Some(id));
+        assert_eq!(pool.stats()[0].in_flight, 0);
+        assert!(rx.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ignores_control_frames_without_matching_version() {
+        for version in [None, Some(CONTROL_PROTOCOL_VERSION + 1)] {
+            let (pool, id, rx, mut progress_rx, mut cancelled) = start_stalled_job(version).await;
+
+            // the job is routed once the backend is connected, and its progress frame would follow shortly after
+            while pool.stats()[0].in_flight == 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+            sleep(Duration::from_millis(100)).await;
+            assert!(progress_rx.try_recv().is_err());
+
+            pool.cancel(id);
+            sleep(Duration::from_millis(100)).await;
+            assert!(cancelled.try_recv().is_err());
+            assert!(rx.await.is_err());
+        }
+    }
+}
@@ -1,6 +1,15 @@
-use crate::{assyst::Assyst, command::context::Context, rest::wsi::RequestError};
+use crate::{
+    assyst::Assyst,
+    caching::persistent_caching::{
+        get_cached_channel, get_cached_guild_owner, get_cached_guild_roles,
+    },
+    command::context::Context,
+    rest::wsi::RequestError,
+};
+pub use assyst_common::util::{format_time, parse_to_millis, pluralize, ParseToMillisError};
 use assyst_common::{
     consts, filetype,
+    persistent_cache::entity_cache::{CachedChannel, CachedRole},
     util::{ChannelId, GuildId, UserId},
 };
 use bytes::Bytes;
@@ -15,7 +24,6 @@ use std::{
     sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
 };
-use twilight_http::{error::Error, Client};
 use twilight_model::{
     channel::message::Mention,
     guild::{Permissions, PremiumTier},
@@ -38,7 +46,6 @@ pub mod regexes {
         pub static ref TENOR_GIF: Regex = Regex::new(r"https://\w+\.tenor\.com/[\w\-]+/[^\.]+\.gif").unwrap();
         pub static ref URL: Regex = Regex::new(r"https?://(www\.)?[-a-zA-Z0-9@:%._\+~#=]{1,256}\.[a-zA-Z0-9()]{1,6}\b([-a-zA-Z0-9()@:%_\+.~#?&//=]*)").unwrap();
         pub static ref USER_MENTION: Regex = Regex::new(r"(?:<@!?)?(\d{16,20})>?").unwrap();
-        pub static ref TIME_STRING: Regex = Regex::new("(\\d+)([smhd])").unwrap();
         pub static ref COMMAND_FLAG: Regex = Regex::new(r#"\s+-(\w+)(?: *"([^"]+)"| *([^\-\s]+))?"#).unwrap();
     }
 }
@@ -91,7 +98,7 @@ pub async fn ensure_same_guild(
     channel_id: u64,
     guild_id: u64,
 ) -> anyhow::Result<()> {
-    let is = is_same_guild(context.http(), channel_id, guild_id).await?;
+    let is = is_same_guild(&context.assyst, channel_id, guild_id).await?;
 
     if !is {
         bail!("The provided channel is not part of this guild.");
@@ -101,20 +108,63 @@ pub async fn ensure_same_guild(
 }
 
 pub async fn is_same_guild(
-    client: &Client,
+    assyst: &Arc<Assyst>,
     channel_id: u64,
     guild_id: u64,
-) -> Result<bool, twilight_http::Error> {
-    let ch = client
+) -> anyhow::Result<bool> {
+    let channel = get_guild_channel(assyst, channel_id).await?;
+
+    Ok(channel.map_or(false, |c| c.guild_id == guild_id))
+}
+
+/// Resolves a channel, preferring the cache over the API
+///
+/// Returns `None` if the channel is not a guild channel.
+pub async fn get_guild_channel(
+    assyst: &Arc<Assyst>,
+    channel_id: u64,
+) -> anyhow::Result<Option<CachedChannel>> {
+    // the cache being unavailable shouldn't fail the lookup, the API can still be used
+    if let Ok(Some(channel)) = get_cached_channel(assyst.clone(), channel_id).await {
+        return Ok(Some(channel));
+    }
+
+    let channel = assyst
+        .http
         .channel(ChannelId::new(channel_id))
         .await?
         .model()
-        .await
-        .unwrap();
+        .await?;
 
-    let real_guild_id = ch.guild_id.map_or(0, |z| z.get());
+    Ok(channel.guild_id.map(|guild_id| CachedChannel {
+        id: channel_id,
+        guild_id: guild_id.get(),
+        name: channel.name.unwrap_or_default(),
+        nsfw: channel.nsfw.unwrap_or(false),
+    }))
+}
+
+/// Resolves the roles of a guild, preferring the cache over the API
+pub async fn get_guild_roles(
+    assyst: &Arc<Assyst>,
+    guild_id: GuildId,
+) -> anyhow::Result<Vec<CachedRole>> {
+    if let Ok(Some(roles)) = get_cached_guild_roles(assyst.clone(), guild_id.get()).await {
+        return Ok(roles);
+    }
 
-    Ok(real_guild_id == guild_id)
+    let roles = assyst.http.roles(guild_id).await?.models().await?;
+
+    Ok(roles
+        .into_iter()
+        .map(|r| CachedRole {
+            id: r.id.get(),
+            name: r.name,
+            color: r.color,
+            position: r.position,
+            permissions: r.permissions.bits(),
+        })
+        .collect())
 }
 
 /// Generates a list given a list of tuples containing strings
@@ -240,22 +290,6 @@ pub fn get_memory_usage_num() -> Option<f32> {
     Some(memory)
 }
 
-mod units {
-    pub const SECOND: u64 = 1000;
-    pub const MINUTE: u64 = SECOND * 60;
-    pub const HOUR: u64 = MINUTE * 60;
-    pub const DAY: u64 = HOUR * 24;
-}
-
-/// Pluralizes a string
-pub fn pluralize<'a>(s: &'a str, adder: &str, count: u64) -> Cow<'a, str> {
-    if count == 1 {
-        Cow::Borrowed(s)
-    } else {
-        Cow::Owned(s.to_owned() + adder)
-    }
-}
-
 /// A wrapper around uptime
 pub struct Uptime(pub u64);
 impl Uptime {
@@ -269,60 +303,6 @@ impl Uptime {
     }
 }
 
-/// Converts a unit string (s, m, h, d) to milliseconds
-fn unit_to_ms(u: &str) -> u64 {
-    match u {
-        "s" => 1000,
-        "m" => 1000 * 60,
-        "h" => 1000 * 60 * 60,
-        "d" => 1000 * 60 * 60 * 24,
-        _ => unreachable!(),
-    }
-}
-
-#[derive(Debug)]
-pub enum ParseToMillisError {
-    ParseIntError,
-    Overflow,
-}
-
-impl std::fmt::Display for ParseToMillisError {
-    #[rustfmt::skip]
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ParseToMillisError::ParseIntError => write!(f, "Input string is too large to fit in numeric type"),
-            ParseToMillisError::Overflow => write!(f, "Final time is too large to fit in numeric type")
-        }
-    }
-}
-
-impl std::error::Error for ParseToMillisError {}
-
-/// Parses a string to milliseconds
-pub fn parse_to_millis(input: &str) -> Result<u64, ParseToMillisError> {
-    let matches = regexes::TIME_STRING.captures_iter(input);
-
-    let mut total: u64 = 0;
-
-    for current in matches {
-        let amount = current[1]
-            .parse::<u64>()
-            .map_err(|_| ParseToMillisError::ParseIntError)?;
-
-        let unit: u64 = unit_to_ms(&current[2])
-            .try_into()
-            .map_err(|_| ParseToMillisError::Overflow)?;
-
-        let ms = amount
-            .checked_mul(unit)
-            .ok_or(ParseToMillisError::Overflow)?;
-
-        total = total.checked_add(ms).ok_or(ParseToMillisError::Overflow)?;
-    }
-
-    Ok(total)
-}
-
 // Ugly solution for now
 // Twilight currently doesn't support Allowed Mentions API for Webhooks
 // TODO: Use allowed_mentions once it's out
@@ -340,23 +320,6 @@ pub fn format_discord_timestamp(input: u64) -> String {
     }
 }
 
-/// Converts a timestamp to a humanly readable string
-pub fn format_time(input: u64) -> String {
-    if input >= units::DAY {
-        let amount = input / units::DAY;
-        format!("{} {}", amount, pluralize("day", "s", amount))
-    } else if input >= units::HOUR {
-        let amount = input / units::HOUR;
-        format!("{} {}", amount, pluralize("hour", "s", amount))
-    } else if input >= units::MINUTE {
-        let amount = input / units::MINUTE;
-        format!("{} {}", amount, pluralize("minute", "s", amount))
-    } else {
-        let amount = input / units::SECOND;
-        format!("{} {}", amount, pluralize("second", "s", amount))
-    }
-}
-
 /// Normalizes custom emojis by replacing them with their names
 pub fn normalize_emojis(input: &str) -> Cow<'_, str> {
     regexes::CUSTOM_EMOJI.replace_all(input, |c: &Captures| c.get(1).unwrap().as_str().to_string())
@@ -411,41 +374,47 @@ pub fn exec_sync(command: &str) -> Result<CommandOutput, std::io::Error> {
     })
 }
 
-/// Attempts to resolve the guild owner
-pub async fn get_guild_owner(http: &Client, guild_id: GuildId) -> Result<UserId, Error> {
-    Ok(http.guild(guild_id).await?.model().await.unwrap().owner_id)
+/// Attempts to resolve the guild owner, preferring the cache over the API
+pub async fn get_guild_owner(assyst: &Arc<Assyst>, guild_id: GuildId) -> anyhow::Result<UserId> {
+    if let Ok(Some(owner)) = get_cached_guild_owner(assyst.clone(), guild_id.get()).await {
+        return Ok(UserId::new(owner));
+    }
+
+    Ok(assyst.http.guild(guild_id).await?.model().await?.owner_id)
 }
 
-pub async fn is_guild_manager(
-    http: &Client,
-    guild_id: GuildId,
-    user_id: UserId,
-) -> Result<bool, Error> {
+/// Returns whether the author of the message is the owner of the guild or has the manage server
+/// or administrator permission in it
+pub async fn is_guild_manager(context: &Context, guild_id: GuildId) -> anyhow::Result<bool> {
+    let assyst = &context.assyst;
+    let user_id = context.message.author.id;
+
     // guild owner *or* manage server *or* admin
     // get owner
-    let owner = get_guild_owner(http, guild_id).await?;
-
-    // figure out permissions of the user through bitwise operations
-    let member = http
-        .guild_member(guild_id, user_id)
-        .await?
-        .model()
-        .await
-        .unwrap();
+    let owner = get_guild_owner(assyst, guild_id).await?;
+
+    // the invoking member is part of the message, so its roles are never stale
+    let role_ids = match &context.message.member {
+        Some(member) => member.roles.clone(),
+        None => {
+            assyst
+                .http
+                .guild_member(guild_id, user_id)
+                .await?
+                .model()
+                .await?
+                .roles
+        }
+    };
 
-    let roles = http
-        .roles(guild_id)
+    let member_roles = get_guild_roles(assyst, guild_id)
         .await?
-        .models()
-        .await
-        .expect("Failed to deserialize body when fetching guild roles");
-
-    let member_roles = roles
-        .iter()
-        .filter(|r| member.roles.contains(&r.id))
+        .into_iter()
+        .filter(|r| role_ids.iter().any(|id| id.get() == r.id))
         .collect::<Vec<_>>();
 
-    let member_permissions = member_roles.iter().fold(0, |a, r| a | r.permissions.bits());
+    // figure out permissions of the user through bitwise operations
+    let member_permissions = member_roles.iter().fold(0, |a, r| a | r.permissions);
     let member_is_manager = member_permissions & Permissions::ADMINISTRATOR.bits()
         == Permissions::ADMINISTRATOR.bits()
         || member_permissions & Permissions::MANAGE_GUILD.bits()
@@ -458,9 +427,7 @@ pub async fn ensure_guild_manager(
     context: &Arc<Context>,
     guild_id: impl Into<GuildId>,
 ) -> anyhow::Result<()> {
-    let user_id = context.message.author.id;
-
-    if is_guild_manager(context.http(), guild_id.into(), user_id).await? {
+    if is_guild_manager(context, guild_id.into()).await? {
         Ok(())
     } else {
         bail!("You need manage server permissions to run this command");
@@ -0,0 +1,261 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use assyst_common::{
+    cache::Cache,
+    persistent_cache::{
+        entity_cache::{CachedChannel, CachedGuild, CachedMember, CachedRole},
+        event_containers::{
+            ChannelDeleteSend, ChannelSend, GuildCreateSend, GuildDeleteSend, GuildUpdateSend,
+            MemberSend, RoleDeleteSend, RoleSend,
+        },
+        CacheResponseData, CacheResponseInner,
+    },
+};
+
+use crate::state::SharedState;
+
+/// Maximum number of members to keep at once
+const MEMBER_CACHE_LIMIT: usize = 10_000;
+/// How long a member is served from the cache before it needs to be fetched again
+///
+/// Member updates are not received over the gateway, so this is the only way they are refreshed.
+const MEMBER_TTL: Duration = Duration::from_secs(60 * 5);
+
+pub struct GuildEntry {
+    pub name: String,
+    pub owner_id: u64,
+    pub member_count: u64,
+    pub roles: HashMap<u64, CachedRole>,
+    pub channels: HashSet<u64>,
+}
+
+pub struct CachedMemberEntry {
+    pub inserted_at: Instant,
+    pub username: String,
+    pub nickname: Option<String>,
+    pub roles: Vec<u64>,
+}
+
+/// Guilds, channels, roles and members, for lookups that would otherwise need a REST request
+pub struct EntityCache {
+    pub guilds: HashMap<u64, GuildEntry>,
+    pub channels: HashMap<u64, CachedChannel>,
+    pub members: Cache<(u64, u64), CachedMemberEntry>,
+}
+impl EntityCache {
+    pub fn new() -> EntityCache {
+        EntityCache {
+            guilds: HashMap::new(),
+            channels: HashMap::new(),
+            members: Cache::new(MEMBER_CACHE_LIMIT),
+        }
+    }
+
+    pub fn add_guild(&mut self, event: &GuildCreateSend) {
+        self.remove_guild(event.id);
+
+        for channel in &event.channels {
+            self.channels
+                .insert(channel.id, channel_from_event(channel));
+        }
+
+        self.guilds.insert(
+            event.id,
+            GuildEntry {
+                name: event.name.clone(),
+                owner_id: event.owner_id,
+                member_count: event.member_count.unwrap_or(0),
+                roles: event
+                    .roles
+                    .iter()
+                    .map(|r| (r.id, role_from_event(r)))
+                    .collect(),
+                channels: event.channels.iter().map(|c| c.id).collect(),
+            },
+        );
+    }
+
+    pub fn remove_guild(&mut self, id: u64) {
+        if let Some(guild) = self.guilds.remove(&id) {
+            for channel in guild.channels {
+                self.channels.remove(&channel);
+            }
+        }
+
+        self.members
+            .cache
+            .retain(|(guild_id, _), _| *guild_id != id);
+    }
+}
+
+fn channel_from_event(channel: &ChannelSend) -> CachedChannel {
+    CachedChannel {
+        id: channel.id,
+        guild_id: channel.guild_id,
+        name: channel.name.clone(),
+        nsfw: channel.nsfw,
+    }
+}
+
+fn role_from_event(role: &RoleSend) -> CachedRole {
+    CachedRole {
+        id: role.id,
+        name: role.name.clone(),
+        color: role.color,
+        position: role.position,
+        permissions: role.permissions,
+    }
+}
+
+pub fn handle_guild_create_event(state: &SharedState, event: &GuildCreateSend) {
+    state.borrow_mut().entity_cache.add_guild(event);
+}
+
+pub fn handle_guild_delete_event(state: &SharedState, event: &GuildDeleteSend) {
+    state.borrow_mut().entity_cache.remove_guild(event.id);
+}
+
+pub fn handle_guild_update_event(state: SharedState, event: GuildUpdateSend) -> CacheResponseInner {
+    if let Some(guild) = state.borrow_mut().entity_cache.guilds.get_mut(&event.id) {
+        guild.name = event.name;
+        guild.owner_id = event.owner_id;
+    }
+
+    Ok(CacheResponseData::GenericAck)
+}
+
+pub fn handle_channel_update_event(state: SharedState, event: ChannelSend) -> CacheResponseInner {
+    let cache = &mut state.borrow_mut().entity_cache;
+
+    // channels of guilds that aren't cached would never be removed
+    if let Some(guild) = cache.guilds.get_mut(&event.guild_id) {
+        guild.channels.insert(event.id);
+        cache.channels.insert(event.id, channel_from_event(&event));
+    }
+
+    Ok(CacheResponseData::GenericAck)
+}
+
+pub fn handle_channel_delete_event(
+    state: SharedState,
+    event: ChannelDeleteSend,
+) -> CacheResponseInner {
+    let cache = &mut state.borrow_mut().entity_cache;
+
+    if let Some(guild) = cache.guilds.get_mut(&event.guild_id) {
+        guild.channels.remove(&event.id);
+    }
+    cache.channels.remove(&event.id);
+
+    Ok(CacheResponseData::GenericAck)
+}
+
+pub fn handle_role_update_event(state: SharedState, event: RoleSend) -> CacheResponseInner {
+    if let Some(guild) = state
+        .borrow_mut()
+        .entity_cache
+        .guilds
+        .get_mut(&event.guild_id)
+    {
+        guild.roles.insert(event.id, role_from_event(&event));
+    }
+
+    Ok(CacheResponseData::GenericAck)
+}
+
+pub fn handle_role_delete_event(state: SharedState, event: RoleDeleteSend) -> CacheResponseInner {
+    if let Some(guild) = state
+        .borrow_mut()
+        .entity_cache
+        .guilds
+        .get_mut(&event.guild_id)
+    {
+        guild.roles.remove(&event.id);
+    }
+
+    Ok(CacheResponseData::GenericAck)
+}
+
+pub fn handle_member(state: SharedState, member: MemberSend) -> CacheResponseInner {
+    state.borrow_mut().entity_cache.members.insert(
+        (member.guild_id, member.user_id),
+        CachedMemberEntry {
+            inserted_at: Instant::now(),
+            username: member.username,
+            nickname: member.nickname,
+            roles: member.roles,
+        },
+    );
+
+    Ok(CacheResponseData::GenericAck)
+}
+
+pub fn get_guild(state: SharedState, id: u64) -> CacheResponseInner {
+    let cache = &state.borrow().entity_cache;
+
+    Ok(CacheResponseData::Guild(cache.guilds.get(&id).map(|g| {
+        CachedGuild {
+            name: g.name.clone(),
+            member_count: g.member_count,
+        }
+    })))
+}
+
+pub fn get_channel(state: SharedState, id: u64) -> CacheResponseInner {
+    Ok(CacheResponseData::Channel(
+        state.borrow().entity_cache.channels.get(&id).cloned(),
+    ))
+}
+
+pub fn get_guild_roles(state: SharedState, id: u64) -> CacheResponseInner {
+    let cache = &state.borrow().entity_cache;
+
+    Ok(CacheResponseData::GuildRoles(
+        cache
+            .guilds
+            .get(&id)
+            .map(|g| g.roles.values().cloned().collect()),
+    ))
+}
+
+pub fn get_guild_owner(state: SharedState, id: u64) -> CacheResponseInner {
+    Ok(CacheResponseData::GuildOwner(
+        state
+            .borrow()
+            .entity_cache
+            .guilds
+            .get(&id)
+            .map(|g| g.owner_id),
+    ))
+}
+
+pub fn get_member(state: SharedState, guild_id: u64, user_id: u64) -> CacheResponseInner {
+    let cache = &state.borrow().entity_cache;
+
+    // role names can only be resolved if the guild is cached
+    let (Some(guild), Some(member)) = (
+        cache.guilds.get(&guild_id),
+        cache.members.get(&(guild_id, user_id)),
+    ) else {
+        return Ok(CacheResponseData::Member(None));
+    };
+
+    if member.inserted_at.elapsed() > MEMBER_TTL {
+        return Ok(CacheResponseData::Member(None));
+    }
+
+    let roles = member
+        .roles
+        .iter()
+        .filter_map(|id| guild.roles.get(id).cloned())
+        .collect();
+
+    Ok(CacheResponseData::Member(Some(CachedMember {
+        username: member.username.clone(),
+        nickname: member.nickname.clone(),
+        roles,
+    })))
+}
@@ -2,7 +2,7 @@ use std::collections::HashSet;
 
 use assyst_common::persistent_cache::{
     event_containers::{GuildCreateSend, GuildDeleteSend, ReadySend},
-    guild_cache::TopGuilds,
+    guild_cache::{ReadyGuildChanges, TopGuilds},
     CacheResponseData, CacheResponseInner,
 };
 
@@ -11,20 +11,33 @@ use crate::state::SharedState;
 pub struct GuildCache {
     pub top_guilds: TopGuilds,
     pub guild_ids: HashSet<u64>,
+    /// Guilds restored from a snapshot that haven't been seen in a READY or GUILD_CREATE since
+    ///
+    /// The bot may have been removed from these while the cache server was down.
+    pub unconfirmed_guild_ids: HashSet<u64>,
 }
 impl GuildCache {
     pub fn new() -> GuildCache {
         GuildCache {
             top_guilds: TopGuilds::new(),
             guild_ids: HashSet::new(),
+            unconfirmed_guild_ids: HashSet::new(),
         }
     }
 }
 
+/// Returns the shard that receives the events of a guild
+fn shard_of(guild_id: u64, shard_count: u32) -> u32 {
+    ((guild_id >> 22) % shard_count.max(1) as u64) as u32
+}
+
 pub fn handle_ready_event(state: SharedState, event: ReadySend) -> CacheResponseInner {
+    let mut state = state.borrow_mut();
+
     let mut new_guilds = 0;
     for guild in &event.guilds {
-        if state.borrow_mut().guild_cache.guild_ids.insert(guild.id) {
+        state.guild_cache.unconfirmed_guild_ids.remove(&guild.id);
+        if state.guild_cache.guild_ids.insert(guild.id) {
             // count the number of new unique guilds,
             // so we can add a number of guilds to the metrics in one call
             // (one atomic store instead of a lot of them)
@@ -32,12 +45,33 @@ pub fn handle_ready_event(state: SharedState, event: ReadySend) -> CacheResponse
         }
     }
 
-    Ok(CacheResponseData::TotalNewGuilds(new_guilds as usize))
+    // a READY contains every guild of its shard, so restored guilds of this shard that are
+    // missing from it were left while the cache server was down
+    let removed = state
+        .guild_cache
+        .unconfirmed_guild_ids
+        .iter()
+        .copied()
+        .filter(|id| shard_of(*id, event.shard_count) == event.shard_id)
+        .collect::<Vec<_>>();
+
+    for id in &removed {
+        state.guild_cache.unconfirmed_guild_ids.remove(id);
+        state.guild_cache.guild_ids.remove(id);
+        state.guild_cache.top_guilds.remove_guild(*id);
+        state.entity_cache.remove_guild(*id);
+    }
+
+    Ok(CacheResponseData::ReadyGuilds(ReadyGuildChanges {
+        new: new_guilds,
+        removed: removed.len(),
+    }))
 }
 
 pub fn handle_guild_create_event(state: SharedState, event: GuildCreateSend) -> CacheResponseInner {
     let cache = &mut state.borrow_mut().guild_cache;
 
+    cache.unconfirmed_guild_ids.remove(&event.id);
     cache.top_guilds.add_guild(
         event.id,
         event.name.clone(),
@@ -56,6 +90,7 @@ pub fn handle_guild_delete_event(state: SharedState, event: GuildDeleteSend) ->
     let cache = &mut state.borrow_mut().guild_cache;
 
     if !event.unavailable {
+        cache.unconfirmed_guild_ids.remove(&event.id);
         if cache.guild_ids.remove(&event.id) {
             Ok(CacheResponseData::ShouldLogGuildDelete(true))
         } else {
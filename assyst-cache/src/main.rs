@@ -1,47 +1,86 @@
-#![feature(never_type)]
-
+mod entity_cache;
 mod guild_cache;
 mod request_handler;
+mod server;
+mod snapshot;
 mod state;
 
 use std::{cell::RefCell, rc::Rc};
 
-use assyst_common::{
-    consts::CACHE_PIPE,
-    ok_or_break,
-    persistent_cache::{CacheRequest, CacheResponse},
-};
-use bincode::{deserialize, serialize};
+use assyst_common::consts::CACHE_PIPE;
 use request_handler::handle_request;
-use state::State;
+use state::{SharedState, State};
 use tokio::{
     fs::remove_file,
-    io::{AsyncReadExt, AsyncWriteExt},
     net::UnixListener,
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, watch},
+    time::interval,
 };
 
+/// How many requests can be waiting to be handled, across all clients
+///
+/// Clients stop being read from while this is full.
+const REQUEST_QUEUE_CAPACITY: usize = 1024;
+
 #[tokio::main]
-async fn main() -> anyhow::Result<!> {
+async fn main() -> anyhow::Result<()> {
     let _ = remove_file(CACHE_PIPE).await;
 
-    let state = Rc::new(RefCell::new(State::new()));
+    // the state is only ever touched by this task, clients hand their requests to it through the queue
+    let state = Rc::new(RefCell::new(snapshot::load().unwrap_or_else(State::new)));
+    let mut changed = false;
 
     let listener = UnixListener::bind(CACHE_PIPE)?;
+    let (requests_tx, mut requests) = mpsc::channel(REQUEST_QUEUE_CAPACITY);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let clients = tokio::spawn(server::accept_clients(listener, requests_tx, shutdown_rx));
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut snapshot_interval = interval(snapshot::SNAPSHOT_INTERVAL);
+
     loop {
-        let (mut stream, _) = listener.accept().await?;
-
-        loop {
-            let len = ok_or_break!(stream.read_u32().await);
-            let mut data: Vec<u8> = vec![0; len as usize];
-            ok_or_break!(stream.read_exact(&mut data).await);
-            let request: CacheRequest = deserialize::<CacheRequest>(&data).unwrap();
-            let id = request.id();
-            let request_data = request.data();
-            let response = handle_request(state.clone(), request_data);
-            let response = CacheResponse::new(id, response);
-            let serialized = serialize(&response).unwrap();
-            ok_or_break!(stream.write_u32(serialized.len() as u32).await);
-            ok_or_break!(stream.write_all(&serialized).await);
+        tokio::select! {
+            request = requests.recv() => {
+                // the queue only closes after shutdown, once no client can send requests anymore
+                let Some(request) = request else { break };
+
+                changed |= request.data().is_update();
+                request.respond_with(|data| handle_request(state.clone(), data));
+            }
+            _ = snapshot_interval.tick(), if changed => {
+                save_snapshot(&state);
+                changed = false;
+            }
+            _ = tokio::signal::ctrl_c(), if !*shutdown_tx.borrow() => {
+                let _ = shutdown_tx.send(true);
+            }
+            _ = terminate.recv(), if !*shutdown_tx.borrow() => {
+                let _ = shutdown_tx.send(true);
+            }
+        }
+    }
+
+    // responses to the last requests are written before the clients are closed
+    let _ = clients.await;
+
+    if changed {
+        let data = snapshot::encode(&state.borrow());
+        if let Err(e) = snapshot::save(data).await {
+            eprintln!("Failed to save cache snapshot: {:?}", e);
         }
     }
+
+    let _ = remove_file(CACHE_PIPE).await;
+    Ok(())
+}
+
+fn save_snapshot(state: &SharedState) {
+    let data = snapshot::encode(&state.borrow());
+
+    tokio::spawn(async move {
+        if let Err(e) = snapshot::save(data).await {
+            eprintln!("Failed to save cache snapshot: {:?}", e);
+        }
+    });
 }
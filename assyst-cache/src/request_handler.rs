@@ -1,6 +1,7 @@
 use assyst_common::persistent_cache::{CacheRequestData, CacheResponseData, CacheResponseInner};
 
 use crate::{
+    entity_cache::{self, get_channel, get_guild, get_guild_owner, get_guild_roles, get_member},
     guild_cache::{handle_guild_create_event, handle_guild_delete_event, handle_ready_event},
     state::SharedState,
 };
@@ -8,13 +9,40 @@ use crate::{
 pub fn handle_request(state: SharedState, request: CacheRequestData) -> CacheResponseInner {
     match request {
         CacheRequestData::SendReadyEvent(event) => handle_ready_event(state, event),
-        CacheRequestData::SendGuildCreate(event) => handle_guild_create_event(state, event),
-        CacheRequestData::SendGuildDelete(event) => handle_guild_delete_event(state, event),
+        CacheRequestData::SendGuildCreate(event) => {
+            entity_cache::handle_guild_create_event(&state, &event);
+            handle_guild_create_event(state, event)
+        }
+        CacheRequestData::SendGuildDelete(event) => {
+            entity_cache::handle_guild_delete_event(&state, &event);
+            handle_guild_delete_event(state, event)
+        }
+        CacheRequestData::SendGuildUpdate(event) => {
+            entity_cache::handle_guild_update_event(state, event)
+        }
+        CacheRequestData::SendChannelUpdate(event) => {
+            entity_cache::handle_channel_update_event(state, event)
+        }
+        CacheRequestData::SendChannelDelete(event) => {
+            entity_cache::handle_channel_delete_event(state, event)
+        }
+        CacheRequestData::SendRoleUpdate(event) => {
+            entity_cache::handle_role_update_event(state, event)
+        }
+        CacheRequestData::SendRoleDelete(event) => {
+            entity_cache::handle_role_delete_event(state, event)
+        }
+        CacheRequestData::SendMember(member) => entity_cache::handle_member(state, member),
         CacheRequestData::GetTotalGuilds => Ok(CacheResponseData::TotalGuilds(
             state.borrow().guild_cache.guild_ids.len(),
         )),
         CacheRequestData::GetTopGuilds => Ok(CacheResponseData::TopGuilds(
             state.borrow().guild_cache.top_guilds.clone(),
         )),
+        CacheRequestData::GetGuild(id) => get_guild(state, id),
+        CacheRequestData::GetChannel(id) => get_channel(state, id),
+        CacheRequestData::GetGuildRoles(id) => get_guild_roles(state, id),
+        CacheRequestData::GetGuildOwner(id) => get_guild_owner(state, id),
+        CacheRequestData::GetMember { guild_id, user_id } => get_member(state, guild_id, user_id),
     }
 }
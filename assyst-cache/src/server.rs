@@ -0,0 +1,169 @@
+use std::{sync::Arc, time::Duration};
+
+use assyst_common::{
+    handshake::{server_handshake, Handshake},
+    ok_or_break,
+    persistent_cache::{
+        capabilities, CacheError, CacheRequest, CacheRequestData, CacheResponse,
+        CacheResponseInner, FrameId, PROTOCOL_VERSION,
+    },
+};
+use bincode::{deserialize, serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+        UnixListener, UnixStream,
+    },
+    sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+    time::timeout,
+};
+
+/// How many requests a single client can have waiting for a response
+///
+/// Once a client reaches this, its connection isn't read from until responses are written,
+/// so a client sending too much fills up its own socket instead of the memory of the server.
+const MAX_IN_FLIGHT_REQUESTS: usize = 256;
+/// How long a client has to send its handshake after connecting
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A request read from a client, waiting to be handled
+pub struct QueuedRequest {
+    id: usize,
+    data: CacheRequestData,
+    client: mpsc::UnboundedSender<(CacheResponse, OwnedSemaphorePermit)>,
+    permit: OwnedSemaphorePermit,
+}
+impl QueuedRequest {
+    pub fn data(&self) -> &CacheRequestData {
+        &self.data
+    }
+
+    /// Handles the request and queues the response to be written to the client
+    pub fn respond_with(self, handler: impl FnOnce(CacheRequestData) -> CacheResponseInner) {
+        let response = CacheResponse::new(self.id, handler(self.data));
+        // the client disconnected, so there's nobody left to respond to
+        let _ = self.client.send((response, self.permit));
+    }
+}
+
+/// Accepts clients until shutdown, then waits for every client to receive the responses to its requests
+pub async fn accept_clients(
+    listener: UnixListener,
+    requests: mpsc::Sender<QueuedRequest>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut clients = JoinSet::new();
+
+    loop {
+        let client_shutdown = shutdown.clone();
+
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    clients.spawn(handle_client(stream, requests.clone(), client_shutdown));
+                }
+                Err(e) => eprintln!("Failed to accept cache client: {:?}", e),
+            },
+            // finished clients are only collected here so that they don't pile up
+            Some(_) = clients.join_next() => {}
+            _ = shutdown.wait_for(|x| *x) => break,
+        }
+    }
+
+    // the request queue closes once every client stopped reading, which lets the state loop finish
+    drop(listener);
+    drop(requests);
+    while clients.join_next().await.is_some() {}
+}
+
+async fn handle_client(
+    mut stream: UnixStream,
+    requests: mpsc::Sender<QueuedRequest>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let handshake = Handshake::new(PROTOCOL_VERSION, capabilities::ALL);
+    match timeout(HANDSHAKE_TIMEOUT, server_handshake(&mut stream, handshake)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => {
+            eprintln!("Rejected cache client: {}", e);
+            return;
+        }
+        Err(_) => {
+            eprintln!("Rejected cache client: no handshake was sent");
+            return;
+        }
+    }
+
+    let (reader, writer) = stream.into_split();
+    let (responses_tx, responses_rx) = mpsc::unbounded_channel();
+
+    let writer = tokio::spawn(write_responses(writer, responses_rx));
+    read_requests(reader, requests, responses_tx, shutdown).await;
+
+    // the writer finishes once every request that was read has been responded to
+    let _ = writer.await;
+}
+
+async fn read_requests(
+    mut reader: OwnedReadHalf,
+    requests: mpsc::Sender<QueuedRequest>,
+    responses: mpsc::UnboundedSender<(CacheResponse, OwnedSemaphorePermit)>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT_REQUESTS));
+
+    loop {
+        let permit = tokio::select! {
+            permit = in_flight.clone().acquire_owned() => permit.unwrap(),
+            _ = shutdown.wait_for(|x| *x) => break,
+        };
+
+        let len = tokio::select! {
+            len = reader.read_u32() => ok_or_break!(len),
+            _ = shutdown.wait_for(|x| *x) => break,
+        };
+        let mut data: Vec<u8> = vec![0; len as usize];
+        ok_or_break!(reader.read_exact(&mut data).await);
+
+        let request = match deserialize::<CacheRequest>(&data) {
+            Ok(request) => request,
+            // most likely a request that was added after this version,
+            // which doesn't need to be handled to tell the client that it isn't supported
+            Err(_) => match deserialize::<FrameId>(&data) {
+                Ok(FrameId { id }) => {
+                    let response = CacheResponse::new_err(id, CacheError::UnsupportedRequest);
+                    ok_or_break!(responses.send((response, permit)));
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Failed to deserialize cache request: {:?}", e);
+                    break;
+                }
+            },
+        };
+
+        let request = QueuedRequest {
+            id: request.id(),
+            data: request.data(),
+            client: responses.clone(),
+            permit,
+        };
+
+        // waits while the queue is full, which stops reading from this client until there is room
+        ok_or_break!(requests.send(request).await);
+    }
+}
+
+async fn write_responses(
+    mut writer: OwnedWriteHalf,
+    mut responses: mpsc::UnboundedReceiver<(CacheResponse, OwnedSemaphorePermit)>,
+) {
+    // the permit is held until the response is written, so that it counts as in flight until then
+    while let Some((response, _permit)) = responses.recv().await {
+        let serialized = serialize(&response).unwrap();
+        ok_or_break!(writer.write_u32(serialized.len() as u32).await);
+        ok_or_break!(writer.write_all(&serialized).await);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use assyst_common::persistent_cache::{
+    entity_cache::{CachedChannel, CachedRole},
+    guild_cache::TopGuilds,
+};
+use bincode::{deserialize, serialize};
+use serde::{Deserialize, Serialize};
+
+use crate::{entity_cache::GuildEntry, state::State};
+
+const SNAPSHOT_PATH: &str = "cache_snapshot.bin";
+const SNAPSHOT_MAGIC: &[u8; 4] = b"ASCS";
+/// Needs to be bumped whenever [`Snapshot`] changes, so that older snapshots are discarded instead of misread
+const SNAPSHOT_VERSION: u32 = 1;
+/// How often the state is written to disk, if it changed
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize)]
+struct GuildSnapshot {
+    id: u64,
+    name: String,
+    owner_id: u64,
+    member_count: u64,
+    roles: Vec<CachedRole>,
+    channels: Vec<CachedChannel>,
+}
+
+/// Everything in [`State`] that is only received when a shard identifies
+///
+/// Members aren't included, they expire quickly and are refetched as they're needed.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    guild_ids: Vec<u64>,
+    top_guilds: TopGuilds,
+    guilds: Vec<GuildSnapshot>,
+}
+
+/// Serializes the state, prefixed with a header identifying the format
+pub fn encode(state: &State) -> Vec<u8> {
+    let entities = &state.entity_cache;
+
+    let guilds = entities
+        .guilds
+        .iter()
+        .map(|(id, guild)| GuildSnapshot {
+            id: *id,
+            name: guild.name.clone(),
+            owner_id: guild.owner_id,
+            member_count: guild.member_count,
+            roles: guild.roles.values().cloned().collect(),
+            channels: guild
+                .channels
+                .iter()
+                .filter_map(|id| entities.channels.get(id).cloned())
+                .collect(),
+        })
+        .collect();
+
+    let snapshot = Snapshot {
+        guild_ids: state.guild_cache.guild_ids.iter().copied().collect(),
+        top_guilds: state.guild_cache.top_guilds.clone(),
+        guilds,
+    };
+
+    let mut data = Vec::new();
+    data.extend_from_slice(SNAPSHOT_MAGIC);
+    data.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    data.extend(serialize(&snapshot).unwrap());
+    data
+}
+
+fn decode(data: &[u8]) -> anyhow::Result<State> {
+    if data.len() < 8 || &data[..4] != SNAPSHOT_MAGIC {
+        bail!("not a cache snapshot");
+    }
+
+    let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+    if version != SNAPSHOT_VERSION {
+        bail!(
+            "snapshot version {} is not supported (expected {})",
+            version,
+            SNAPSHOT_VERSION
+        );
+    }
+
+    let snapshot = deserialize::<Snapshot>(&data[8..]).context("snapshot is corrupted")?;
+
+    let mut state = State::new();
+
+    let guild_ids = snapshot.guild_ids.into_iter().collect::<HashSet<_>>();
+    state.guild_cache.unconfirmed_guild_ids = guild_ids.clone();
+    state.guild_cache.guild_ids = guild_ids;
+    state.guild_cache.top_guilds = snapshot.top_guilds;
+
+    for guild in snapshot.guilds {
+        let channels = guild.channels.iter().map(|c| c.id).collect();
+        for channel in guild.channels {
+            state.entity_cache.channels.insert(channel.id, channel);
+        }
+
+        state.entity_cache.guilds.insert(
+            guild.id,
+            GuildEntry {
+                name: guild.name,
+                owner_id: guild.owner_id,
+                member_count: guild.member_count,
+                roles: guild
+                    .roles
+                    .into_iter()
+                    .map(|r| (r.id, r))
+                    .collect::<HashMap<_, _>>(),
+                channels,
+            },
+        );
+    }
+
+    Ok(state)
+}
+
+/// Restores the state from the last snapshot, if there is a usable one
+///
+/// Restored guilds are checked against the next READY of their shard.
+pub fn load() -> Option<State> {
+    let data = match std::fs::read(SNAPSHOT_PATH) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            eprintln!("Failed to read cache snapshot: {:?}", e);
+            return None;
+        }
+    };
+
+    match decode(&data) {
+        Ok(state) => {
+            println!(
+                "Restored {} guilds from cache snapshot",
+                state.guild_cache.guild_ids.len()
+            );
+            Some(state)
+        }
+        Err(e) => {
+            eprintln!("Discarding cache snapshot: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Writes an encoded snapshot to disk
+///
+/// The snapshot is written to a temporary file first, so that a crash never leaves a partial snapshot behind.
+pub async fn save(data: Vec<u8>) -> std::io::Result<()> {
+    let temp_path = format!("{}.tmp", SNAPSHOT_PATH);
+    tokio::fs::write(&temp_path, data).await?;
+    tokio::fs::rename(&temp_path, SNAPSHOT_PATH).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entity_cache, guild_cache, state::SharedState};
+    use assyst_common::persistent_cache::event_containers::{
+        ChannelSend, GuildCreateSend, RoleSend,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn guild(id: u64) -> GuildCreateSend {
+        GuildCreateSend {
+            id,
+            name: format!("guild {}", id),
+            owner_id: 1,
+            member_count: Some(10),
+            channels: vec![ChannelSend {
+                id: id + 1,
+                guild_id: id,
+                name: "general".to_string(),
+                nsfw: true,
+            }],
+            roles: vec![RoleSend {
+                id: id + 2,
+                guild_id: id,
+                name: "admin".to_string(),
+                color: 0,
+                position: 1,
+                permissions: 8,
+            }],
+        }
+    }
+
+    fn encoded_state() -> Vec<u8> {
+        let state: SharedState = Rc::new(RefCell::new(State::new()));
+        for id in [100, 200] {
+            entity_cache::handle_guild_create_event(&state, &guild(id));
+            guild_cache::handle_guild_create_event(state.clone(), guild(id)).unwrap();
+        }
+
+        let data = encode(&state.borrow());
+        data
+    }
+
+    #[test]
+    fn round_trips_state() {
+        let state = decode(&encoded_state()).unwrap();
+
+        assert_eq!(state.guild_cache.guild_ids, HashSet::from([100, 200]));
+        // restored guilds are unconfirmed until the next READY
+        assert_eq!(
+            state.guild_cache.unconfirmed_guild_ids,
+            HashSet::from([100, 200])
+        );
+        assert_eq!(state.guild_cache.top_guilds.0.len(), 2);
+
+        let guild = &state.entity_cache.guilds[&100];
+        assert_eq!(guild.name, "guild 100");
+        assert_eq!(guild.member_count, 10);
+        assert_eq!(guild.roles[&102].permissions, 8);
+        assert!(guild.channels.contains(&101));
+        assert!(state.entity_cache.channels[&101].nsfw);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut data = encoded_state();
+        data[0] = b'X';
+        assert!(decode(&data).is_err());
+        assert!(decode(&data[..6]).is_err());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut data = encoded_state();
+        data[4..8].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert!(decode(&data).is_err());
+    }
+}
@@ -1,14 +1,16 @@
 use std::{cell::RefCell, rc::Rc};
 
-use crate::guild_cache::GuildCache;
+use crate::{entity_cache::EntityCache, guild_cache::GuildCache};
 
 pub struct State {
     pub guild_cache: GuildCache,
+    pub entity_cache: EntityCache,
 }
 impl State {
     pub fn new() -> State {
         State {
             guild_cache: GuildCache::new(),
+            entity_cache: EntityCache::new(),
         }
     }
 }
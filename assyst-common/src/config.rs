@@ -58,6 +58,9 @@ pub struct Url {
     pub maryjane: Box<str>,
     pub rule34: Box<str>,
     pub wsi: Box<str>,
+    /// Additional WSI backends, jobs are balanced between these and `wsi`
+    #[serde(default)]
+    pub wsi_backends: Vec<WsiBackend>,
     pub codesprint: Box<str>,
     pub cdn: Box<str>,
     pub proxy: Vec<Box<str>>,
@@ -65,6 +68,37 @@ pub struct Url {
     pub wombo: Box<str>,
 }
 
+#[derive(Clone, Deserialize)]
+pub struct WsiBackend {
+    pub address: Box<str>,
+    /// Minimum premium tier of users whose jobs can be sent to this backend
+    #[serde(default)]
+    pub min_tier: usize,
+}
+
+/// Budgets of the cache of WSI results, a budget of 0 disables that part of the cache
+///
+/// The cache is disabled unless it's configured.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct WsiCache {
+    /// Maximum size in bytes of the results kept in memory
+    pub memory_bytes: usize,
+    /// Maximum size in bytes of the results kept on disk, once they no longer fit in memory
+    pub disk_bytes: usize,
+    /// Directory that results are stored in on disk
+    pub disk_path: Box<str>,
+}
+impl Default for WsiCache {
+    fn default() -> Self {
+        WsiCache {
+            memory_bytes: 0,
+            disk_bytes: 0,
+            disk_path: "wsi_cache".into(),
+        }
+    }
+}
+
 #[derive(Clone, Deserialize)]
 pub struct User {
     pub admins: HashSet<u64>,
@@ -83,10 +117,22 @@ pub struct Config {
     pub prefix: Prefix,
     pub url: Url,
     pub user: User,
+    #[serde(default)]
+    pub wsi_cache: WsiCache,
 }
 impl Config {
     pub fn new() -> Self {
         let buffer = read_to_string("../config.toml").unwrap();
         toml::from_str(&buffer).unwrap()
     }
+
+    /// Returns every configured WSI backend, with `url.wsi` being available to all users
+    pub fn wsi_backends(&self) -> Vec<WsiBackend> {
+        let mut backends = vec![WsiBackend {
+            address: self.url.wsi.clone(),
+            min_tier: 0,
+        }];
+        backends.extend(self.url.wsi_backends.iter().cloned());
+        backends
+    }
 }
@@ -10,6 +10,7 @@ pub const MESSAGE_CHARACTER_LIMIT: usize = 2000;
 pub const MESSAGE_EDIT_HANDLE_LIMIT: u32 = 60000;
 pub const IDENTIFY_ERROR_MESSAGE: &str = "I really can't describe the picture :flushed:";
 pub const MAX_TIMESTAMP: u64 = 8640000000000000;
+pub const MAX_TAG_STORAGE_BYTES_PER_GUILD: usize = 1_000_000;
 pub const DEFAULT_COLORS: &[(&str, u32)] = &[
     ("gold", 0xf1c40f),
     ("teal", 0x1abc9c),
@@ -37,8 +38,26 @@ pub mod gateway {
 
     use serde::{Deserialize, Serialize};
 
+    /// Version of the event pipe protocol, checked in the handshake
+    pub const PROTOCOL_VERSION: u32 = 1;
     pub const OP_EVENT: u8 = 0;
     pub const OP_LATENCIES: u8 = 1;
+    /// An event prefixed with its sequence number, sent instead of [`OP_EVENT`] when replay is supported
+    pub const OP_SEQUENCED_EVENT: u8 = 2;
+    /// Sent by the bot with the sequence number of the last event it processed
+    pub const OP_ACK: u8 = 3;
+
+    /// Capabilities of the gateway and the bot, exchanged in the handshake
+    pub mod capabilities {
+        /// Shard latencies are sent with [`super::OP_LATENCIES`]
+        pub const LATENCIES: u64 = 1 << 0;
+        /// Events are numbered and acknowledged, and unacknowledged events are replayed after reconnecting
+        ///
+        /// The bot sends the sequence number of the last event it processed right after the handshake,
+        /// or 0 if it doesn't know.
+        pub const REPLAY: u64 = 1 << 1;
+    }
+
     #[derive(Serialize, Deserialize)]
     pub struct Latencies(pub HashMap<u64, i64>);
 }
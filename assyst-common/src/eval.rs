@@ -14,11 +14,12 @@ pub struct FakeEvalBody<M: Serialize> {
     pub data: Option<FakeEvalMessageData<M>>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct FakeEvalResponse {
     pub message: String,
 }
 
+#[derive(Clone)]
 pub enum FakeEvalImageResponse {
     Text(FakeEvalResponse),
     Image(Bytes, filetype::Type),
@@ -1,7 +1,7 @@
 use std::cmp::min;
 use std::ops::Range;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Type {
     GIF,
     JPEG,
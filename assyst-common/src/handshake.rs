@@ -0,0 +1,106 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const HANDSHAKE_MAGIC: &[u8; 4] = b"ASHS";
+
+/// The first frame sent by both sides of the cache and event pipes
+///
+/// Unlike everything that follows it, the handshake has a fixed layout, so that processes of any
+/// version can read it and tell that they can't talk to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+    pub version: u32,
+    pub capabilities: u64,
+}
+impl Handshake {
+    pub fn new(version: u32, capabilities: u64) -> Self {
+        Handshake {
+            version,
+            capabilities,
+        }
+    }
+
+    pub async fn write<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut frame = [0; 16];
+        frame[..4].copy_from_slice(HANDSHAKE_MAGIC);
+        frame[4..8].copy_from_slice(&self.version.to_be_bytes());
+        frame[8..].copy_from_slice(&self.capabilities.to_be_bytes());
+
+        writer.write_all(&frame).await?;
+        writer.flush().await
+    }
+
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Handshake, HandshakeError> {
+        let mut frame = [0; 16];
+        reader
+            .read_exact(&mut frame)
+            .await
+            .map_err(HandshakeError::Io)?;
+
+        if &frame[..4] != HANDSHAKE_MAGIC {
+            return Err(HandshakeError::NotAHandshake);
+        }
+
+        Ok(Handshake {
+            version: u32::from_be_bytes(frame[4..8].try_into().unwrap()),
+            capabilities: u64::from_be_bytes(frame[8..].try_into().unwrap()),
+        })
+    }
+
+    /// Checks that the other side speaks the same version, returning the capabilities that both sides support
+    pub fn negotiate(&self, theirs: &Handshake) -> Result<u64, HandshakeError> {
+        if self.version != theirs.version {
+            return Err(HandshakeError::VersionMismatch {
+                ours: self.version,
+                theirs: theirs.version,
+            });
+        }
+
+        Ok(self.capabilities & theirs.capabilities)
+    }
+}
+
+/// Performs the handshake as the side that connected, returning the negotiated capabilities
+pub async fn client_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    ours: Handshake,
+) -> Result<u64, HandshakeError> {
+    ours.write(stream).await.map_err(HandshakeError::Io)?;
+    let theirs = Handshake::read(stream).await?;
+    ours.negotiate(&theirs)
+}
+
+/// Performs the handshake as the side that accepted the connection, returning the negotiated capabilities
+///
+/// The server always responds with its own handshake, even if the versions don't match,
+/// so that the client can report which versions are involved.
+pub async fn server_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    ours: Handshake,
+) -> Result<u64, HandshakeError> {
+    let theirs = Handshake::read(stream).await?;
+    ours.write(stream).await.map_err(HandshakeError::Io)?;
+    ours.negotiate(&theirs)
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    Io(std::io::Error),
+    NotAHandshake,
+    VersionMismatch { ours: u32, theirs: u32 },
+}
+
+impl std::fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandshakeError::Io(e) => write!(f, "Handshake failed: {}", e),
+            HandshakeError::NotAHandshake => write!(f, "The other side didn't send a handshake"),
+            HandshakeError::VersionMismatch { ours, theirs } => write!(
+                f,
+                "Protocol version mismatch: this side speaks version {}, the other side speaks version {}",
+                ours, theirs
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
@@ -5,5 +5,6 @@ pub mod config;
 pub mod consts;
 pub mod eval;
 pub mod filetype;
+pub mod handshake;
 pub mod persistent_cache;
 pub mod util;
@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+/// A guild as stored by the cache server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedGuild {
+    pub name: String,
+    pub member_count: u64,
+}
+
+/// A guild channel as stored by the cache server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedChannel {
+    pub id: u64,
+    pub guild_id: u64,
+    pub name: String,
+    pub nsfw: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedRole {
+    pub id: u64,
+    pub name: String,
+    pub color: u32,
+    pub position: i64,
+    pub permissions: u64,
+}
+
+/// A guild member as stored by the cache server, with its role IDs resolved to roles
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedMember {
+    pub username: String,
+    pub nickname: Option<String>,
+    pub roles: Vec<CachedRole>,
+}
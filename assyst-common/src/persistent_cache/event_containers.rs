@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
-use serenity::all::{Guild, GuildCreateEvent, GuildDeleteEvent, ReadyEvent, UnavailableGuild};
+use serenity::all::{
+    ChannelDeleteEvent, Guild, GuildChannel, GuildCreateEvent, GuildDeleteEvent,
+    GuildRoleDeleteEvent, GuildUpdateEvent, ReadyEvent, Role, UnavailableGuild,
+};
 use twilight_model::gateway::payload::incoming::{GuildCreate, GuildDelete, Ready};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -20,9 +23,16 @@ impl From<UnavailableGuild> for ReadyGuild {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ReadySend {
     pub guilds: Vec<ReadyGuild>,
+    pub shard_id: u32,
+    pub shard_count: u32,
 }
 impl From<ReadyEvent> for ReadySend {
     fn from(ready: ReadyEvent) -> Self {
+        let (shard_id, shard_count) = ready
+            .ready
+            .shard
+            .map_or((0, 1), |shard| (shard.id.0, shard.total));
+
         let g = ready
             .ready
             .guilds
@@ -30,7 +40,11 @@ impl From<ReadyEvent> for ReadySend {
             .map(|x| ReadyGuild::from(x))
             .collect::<Vec<_>>();
 
-        ReadySend { guilds: g }
+        ReadySend {
+            guilds: g,
+            shard_id,
+            shard_count,
+        }
     }
 }
 
@@ -52,14 +66,123 @@ impl From<GuildDeleteEvent> for GuildDeleteSend {
 pub struct GuildCreateSend {
     pub id: u64,
     pub name: String,
+    pub owner_id: u64,
     pub member_count: Option<u64>,
+    pub channels: Vec<ChannelSend>,
+    pub roles: Vec<RoleSend>,
 }
 impl From<GuildCreateEvent> for GuildCreateSend {
     fn from(guild_create: GuildCreateEvent) -> Self {
+        let guild = guild_create.guild;
+
         GuildCreateSend {
-            id: guild_create.guild.id.get(),
-            name: guild_create.guild.name.clone(),
-            member_count: Some(guild_create.guild.member_count),
+            id: guild.id.get(),
+            name: guild.name,
+            owner_id: guild.owner_id.get(),
+            member_count: Some(guild.member_count),
+            channels: guild
+                .channels
+                .into_values()
+                .map(ChannelSend::from)
+                .collect(),
+            roles: guild.roles.into_values().map(RoleSend::from).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GuildUpdateSend {
+    pub id: u64,
+    pub name: String,
+    pub owner_id: u64,
+}
+impl From<GuildUpdateEvent> for GuildUpdateSend {
+    fn from(guild_update: GuildUpdateEvent) -> Self {
+        GuildUpdateSend {
+            id: guild_update.guild.id.get(),
+            name: guild_update.guild.name,
+            owner_id: guild_update.guild.owner_id.get(),
         }
     }
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChannelSend {
+    pub id: u64,
+    pub guild_id: u64,
+    pub name: String,
+    pub nsfw: bool,
+}
+impl From<GuildChannel> for ChannelSend {
+    fn from(channel: GuildChannel) -> Self {
+        ChannelSend {
+            id: channel.id.get(),
+            guild_id: channel.guild_id.get(),
+            name: channel.name,
+            nsfw: channel.nsfw,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChannelDeleteSend {
+    pub id: u64,
+    pub guild_id: u64,
+}
+impl From<ChannelDeleteEvent> for ChannelDeleteSend {
+    fn from(channel_delete: ChannelDeleteEvent) -> Self {
+        ChannelDeleteSend {
+            id: channel_delete.channel.id.get(),
+            guild_id: channel_delete.channel.guild_id.get(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RoleSend {
+    pub id: u64,
+    pub guild_id: u64,
+    pub name: String,
+    pub color: u32,
+    pub position: i64,
+    pub permissions: u64,
+}
+impl From<Role> for RoleSend {
+    fn from(role: Role) -> Self {
+        RoleSend {
+            id: role.id.get(),
+            guild_id: role.guild_id.get(),
+            name: role.name,
+            color: role.colour.0,
+            position: role.position as i64,
+            permissions: role.permissions.bits(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RoleDeleteSend {
+    pub id: u64,
+    pub guild_id: u64,
+}
+impl From<GuildRoleDeleteEvent> for RoleDeleteSend {
+    fn from(role_delete: GuildRoleDeleteEvent) -> Self {
+        RoleDeleteSend {
+            id: role_delete.role_id.get(),
+            guild_id: role_delete.guild_id.get(),
+        }
+    }
+}
+
+/// A guild member, sent by the bot after it fetched or saw one
+///
+/// Members are not sent over the gateway without the privileged members intent,
+/// so the cache only knows about members that the bot has seen or fetched.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MemberSend {
+    pub guild_id: u64,
+    pub user_id: u64,
+    pub username: String,
+    pub nickname: Option<String>,
+    pub roles: Vec<u64>,
+}
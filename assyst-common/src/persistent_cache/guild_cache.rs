@@ -15,6 +15,13 @@ impl TopGuild {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TopGuilds(pub Vec<TopGuild>);
 
+/// How many guilds were added and removed by a READY
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ReadyGuildChanges {
+    pub new: usize,
+    pub removed: usize,
+}
+
 impl TopGuilds {
     pub fn new() -> Self {
         TopGuilds(Vec::new())
@@ -41,6 +48,10 @@ impl TopGuilds {
         }
     }
 
+    pub fn remove_guild(&mut self, id: u64) {
+        self.0.retain(|guild| guild.id != id);
+    }
+
     pub fn sort(&mut self) -> () {
         self.0.sort_by(|a, b| b.count.cmp(&a.count));
     }
@@ -1,13 +1,32 @@
+pub mod entity_cache;
 pub mod event_containers;
 pub mod guild_cache;
 
 use serde::{Deserialize, Serialize};
 
 use self::{
-    event_containers::{GuildCreateSend, GuildDeleteSend, ReadySend},
-    guild_cache::TopGuilds,
+    entity_cache::{CachedChannel, CachedGuild, CachedMember, CachedRole},
+    event_containers::{
+        ChannelDeleteSend, ChannelSend, GuildCreateSend, GuildDeleteSend, GuildUpdateSend,
+        MemberSend, ReadySend, RoleDeleteSend, RoleSend,
+    },
+    guild_cache::{ReadyGuildChanges, TopGuilds},
 };
 
+/// Version of the cache protocol, checked in the handshake
+///
+/// Needs to be bumped whenever a change to [`CacheRequest`] or [`CacheResponse`] can't be read by older processes.
+/// Adding a request doesn't need this, older servers respond to it with [`CacheError::UnsupportedRequest`].
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Capabilities of the cache server and its clients, exchanged in the handshake
+pub mod capabilities {
+    /// Guilds, channels, roles and members are cached, not just guild IDs
+    pub const ENTITIES: u64 = 1 << 0;
+
+    pub const ALL: u64 = ENTITIES;
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum CacheRequestData {
     GetTopGuilds,
@@ -15,6 +34,57 @@ pub enum CacheRequestData {
     SendReadyEvent(ReadySend),
     SendGuildCreate(GuildCreateSend),
     SendGuildDelete(GuildDeleteSend),
+    SendGuildUpdate(GuildUpdateSend),
+    SendChannelUpdate(ChannelSend),
+    SendChannelDelete(ChannelDeleteSend),
+    SendRoleUpdate(RoleSend),
+    SendRoleDelete(RoleDeleteSend),
+    SendMember(MemberSend),
+    GetGuild(u64),
+    GetChannel(u64),
+    GetGuildRoles(u64),
+    GetGuildOwner(u64),
+    GetMember { guild_id: u64, user_id: u64 },
+}
+
+impl CacheRequestData {
+    /// Whether this request changes the state of the cache that is kept in snapshots
+    ///
+    /// Members expire quickly and aren't part of snapshots, so caching one doesn't count.
+    pub fn is_update(&self) -> bool {
+        !matches!(
+            self,
+            CacheRequestData::SendMember(_)
+                | CacheRequestData::GetTopGuilds
+                | CacheRequestData::GetTotalGuilds
+                | CacheRequestData::GetGuild(_)
+                | CacheRequestData::GetChannel(_)
+                | CacheRequestData::GetGuildRoles(_)
+                | CacheRequestData::GetGuildOwner(_)
+                | CacheRequestData::GetMember { .. }
+        )
+    }
+
+    /// Capabilities the cache server needs to handle this request
+    pub fn required_capabilities(&self) -> u64 {
+        match self {
+            CacheRequestData::GetTopGuilds
+            | CacheRequestData::GetTotalGuilds
+            | CacheRequestData::SendReadyEvent(_)
+            | CacheRequestData::SendGuildCreate(_)
+            | CacheRequestData::SendGuildDelete(_) => 0,
+            _ => capabilities::ENTITIES,
+        }
+    }
+}
+
+/// The ID at the start of every [`CacheRequest`] and [`CacheResponse`]
+///
+/// It can still be read when the rest of a frame can't, for example when it contains a request that was added
+/// in a newer version, so that an error can be sent to whoever is waiting for it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FrameId {
+    pub id: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -39,8 +109,14 @@ pub enum CacheResponseData {
     TopGuilds(TopGuilds),
     ShouldLogGuildCreate(bool),
     ShouldLogGuildDelete(bool),
-    TotalNewGuilds(usize),
+    ReadyGuilds(ReadyGuildChanges),
     TotalGuilds(usize),
+    Guild(Option<CachedGuild>),
+    Channel(Option<CachedChannel>),
+    /// Roles of a guild, or `None` if the guild isn't cached
+    GuildRoles(Option<Vec<CachedRole>>),
+    GuildOwner(Option<u64>),
+    Member(Option<CachedMember>),
     GenericAck,
 }
 
@@ -75,6 +151,10 @@ pub enum CacheError {
     CacheServerDied,
     CacheServerDown,
     Timeout,
+    /// The request isn't known to the other side, which runs a different version
+    UnsupportedRequest,
+    /// The response couldn't be read
+    InvalidResponse,
 }
 
 impl std::fmt::Display for CacheError {
@@ -83,6 +163,10 @@ impl std::fmt::Display for CacheError {
             CacheError::CacheServerDied => write!(f, "The cache server died"),
             CacheError::CacheServerDown => write!(f, "The cache server is down"),
             CacheError::Timeout => write!(f, "A timeout occurred"),
+            CacheError::UnsupportedRequest => {
+                write!(f, "The cache server does not support this request")
+            }
+            CacheError::InvalidResponse => write!(f, "The cache server sent an invalid response"),
         }
     }
 }
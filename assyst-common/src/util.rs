@@ -1,4 +1,7 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    borrow::Cow,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use twilight_model::id::{
     marker::{ChannelMarker, GuildMarker, MessageMarker, RoleMarker, UserMarker, WebhookMarker},
@@ -22,6 +25,93 @@ pub fn get_current_millis() -> u64 {
         .expect("Couldn't fit timestamp (u128) into i64")
 }
 
+pub mod units {
+    pub const SECOND: u64 = 1000;
+    pub const MINUTE: u64 = SECOND * 60;
+    pub const HOUR: u64 = MINUTE * 60;
+    pub const DAY: u64 = HOUR * 24;
+}
+
+/// Pluralizes a string
+pub fn pluralize<'a>(s: &'a str, adder: &str, count: u64) -> Cow<'a, str> {
+    if count == 1 {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(s.to_owned() + adder)
+    }
+}
+
+/// Converts a unit string (s, m, h, d) to milliseconds
+fn unit_to_ms(u: &str) -> u64 {
+    match u {
+        "s" => 1000,
+        "m" => 1000 * 60,
+        "h" => 1000 * 60 * 60,
+        "d" => 1000 * 60 * 60 * 24,
+        _ => unreachable!(),
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseToMillisError {
+    ParseIntError,
+    Overflow,
+}
+
+impl std::fmt::Display for ParseToMillisError {
+    #[rustfmt::skip]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseToMillisError::ParseIntError => write!(f, "Input string is too large to fit in numeric type"),
+            ParseToMillisError::Overflow => write!(f, "Final time is too large to fit in numeric type")
+        }
+    }
+}
+
+impl std::error::Error for ParseToMillisError {}
+
+/// Parses a string to milliseconds
+pub fn parse_to_millis(input: &str) -> Result<u64, ParseToMillisError> {
+    let matches = regexes::TIME_STRING.captures_iter(input);
+
+    let mut total: u64 = 0;
+
+    for current in matches {
+        let amount = current[1]
+            .parse::<u64>()
+            .map_err(|_| ParseToMillisError::ParseIntError)?;
+
+        let unit: u64 = unit_to_ms(&current[2])
+            .try_into()
+            .map_err(|_| ParseToMillisError::Overflow)?;
+
+        let ms = amount
+            .checked_mul(unit)
+            .ok_or(ParseToMillisError::Overflow)?;
+
+        total = total.checked_add(ms).ok_or(ParseToMillisError::Overflow)?;
+    }
+
+    Ok(total)
+}
+
+/// Converts a timestamp to a humanly readable string
+pub fn format_time(input: u64) -> String {
+    if input >= units::DAY {
+        let amount = input / units::DAY;
+        format!("{} {}", amount, pluralize("day", "s", amount))
+    } else if input >= units::HOUR {
+        let amount = input / units::HOUR;
+        format!("{} {}", amount, pluralize("hour", "s", amount))
+    } else if input >= units::MINUTE {
+        let amount = input / units::MINUTE;
+        format!("{} {}", amount, pluralize("minute", "s", amount))
+    } else {
+        let amount = input / units::SECOND;
+        format!("{} {}", amount, pluralize("second", "s", amount))
+    }
+}
+
 /// Promotes the lifetime of a string to a static string by leaking memory
 pub fn to_static_str(s: &Box<str>) -> &'static mut str {
     Box::leak(s.clone())
@@ -63,6 +153,7 @@ pub mod regexes {
 
     lazy_static! {
         pub static ref MENTION: Regex = Regex::new(r"(?:<@!?)?(\d{16,20})>?").unwrap();
+        pub static ref TIME_STRING: Regex = Regex::new("(\\d+)([smhd])").unwrap();
     }
 }
 
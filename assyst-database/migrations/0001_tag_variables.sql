@@ -0,0 +1,8 @@
+-- Variables stored by tags, guild-wide variables have an empty tag name
+CREATE TABLE IF NOT EXISTS tag_variables (
+    guild_id BIGINT NOT NULL,
+    tag TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (guild_id, tag, key)
+);
@@ -0,0 +1,21 @@
+-- Previous contents of tags, a new revision is added whenever a tag is edited
+CREATE TABLE IF NOT EXISTS tag_history (
+    name TEXT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    revision INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    replaced_at BIGINT NOT NULL,
+    PRIMARY KEY (name, guild_id, revision)
+);
+
+-- Alternative names of tags, an alias shadows a tag with the same name
+CREATE TABLE IF NOT EXISTS tag_aliases (
+    name TEXT NOT NULL,
+    target TEXT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    author BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (name, guild_id)
+);
+
+CREATE INDEX IF NOT EXISTS tag_aliases_target_idx ON tag_aliases (target, guild_id);
@@ -0,0 +1,29 @@
+-- Used for trigram matching when searching tags and suggesting similar names
+--
+-- Creating an extension needs elevated privileges. If the bot's database user doesn't have them,
+-- an operator has to run `CREATE EXTENSION pg_trgm;` on the database as a superuser, after which
+-- the index below can be created by hand. Until then, search and suggestions don't work.
+DO $$
+BEGIN
+    CREATE EXTENSION IF NOT EXISTS pg_trgm;
+EXCEPTION WHEN insufficient_privilege THEN
+    RAISE WARNING 'pg_trgm could not be created, it must be created by a superuser';
+END
+$$;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'pg_trgm') THEN
+        CREATE INDEX IF NOT EXISTS tags_name_trgm_idx ON tags USING gin (name gin_trgm_ops);
+    END IF;
+END
+$$;
+
+-- How often each tag has been run
+CREATE TABLE IF NOT EXISTS tag_uses (
+    name TEXT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    uses INTEGER NOT NULL,
+    last_used BIGINT NOT NULL,
+    PRIMARY KEY (name, guild_id)
+);
@@ -0,0 +1,8 @@
+-- Tags that can be used from any guild under their global name, owned by whoever owns the tag
+CREATE TABLE IF NOT EXISTS published_tags (
+    name TEXT NOT NULL PRIMARY KEY,
+    guild_id BIGINT NOT NULL,
+    published_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS published_tags_tag_idx ON published_tags (name, guild_id);
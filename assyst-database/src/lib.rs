@@ -58,6 +58,23 @@ pub struct Tag {
     pub created_at: i64,
 }
 
+#[derive(sqlx::FromRow, Debug)]
+pub struct TagRevision {
+    pub name: String,
+    pub guild_id: i64,
+    pub revision: i32,
+    pub data: String,
+    pub replaced_at: i64,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct TagUsage {
+    pub name: String,
+    pub guild_id: i64,
+    pub uses: i32,
+    pub last_used: i64,
+}
+
 #[derive(sqlx::FromRow, Debug)]
 pub struct DatabaseSize {
     pub size: String,
@@ -175,6 +192,9 @@ impl Database {
             .connect(&url)
             .await?;
 
+        // creates the tables that were added after the initial schema
+        sqlx::migrate!().run(&pool).await?;
+
         Ok(Database {
             cache: RwLock::new(DatabaseCache::new()),
             pool,
@@ -834,7 +854,12 @@ impl Database {
         name: &str,
         content: &str,
     ) -> Result<bool, sqlx::Error> {
-        let query = r#"INSERT INTO tags VALUES ($1, $2, $3, $4, $5)"#;
+        // aliases shadow tags, so a tag can't be created if an alias with the same name exists
+        let query = r#"
+        INSERT INTO tags (name, data, author, guild_id, created_at)
+        SELECT $1, $2, $3, $4, $5
+        WHERE NOT EXISTS (SELECT 1 FROM tag_aliases WHERE name = $1 AND guild_id = $4)
+        "#;
 
         sqlx::query(query)
             .bind(name)
@@ -844,7 +869,7 @@ impl Database {
             .bind(get_current_millis() as i64)
             .execute(&self.pool)
             .await
-            .map(|_| true)
+            .map(|r| r.rows_affected() > 0)
             .or_else(|e| {
                 if is_unique_violation(&e) {
                     Ok(false)
@@ -855,38 +880,219 @@ impl Database {
     }
 
     pub async fn remove_tag_force(&self, guild_id: i64, name: &str) -> Result<bool, sqlx::Error> {
-        let query = r#"DELETE FROM tags WHERE name = $1 AND guild_id = $2"#;
+        self.remove_tag_with_history(None, guild_id, name).await
+    }
+
+    pub async fn remove_tag(
+        &self,
+        author: i64,
+        guild_id: i64,
+        name: &str,
+    ) -> Result<bool, sqlx::Error> {
+        self.remove_tag_with_history(Some(author), guild_id, name)
+            .await
+    }
+
+    /// Deletes a tag along with its aliases, history, usage, variables and global name, so that a tag created later under the same name starts fresh
+    ///
+    /// If `author` is `None`, the tag is deleted regardless of who owns it
+    async fn remove_tag_with_history(
+        &self,
+        author: Option<i64>,
+        guild_id: i64,
+        name: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let query = r#"DELETE FROM tags WHERE name = $1 AND guild_id = $2 AND ($3::bigint IS NULL OR author = $3)"#;
+        let aliases_query = r#"DELETE FROM tag_aliases WHERE target = $1 AND guild_id = $2"#;
+        let history_query = r#"DELETE FROM tag_history WHERE name = $1 AND guild_id = $2"#;
+        let uses_query = r#"DELETE FROM tag_uses WHERE name = $1 AND guild_id = $2"#;
+        let published_query = r#"DELETE FROM published_tags WHERE name = $1 AND guild_id = $2"#;
+        let variables_query = r#"DELETE FROM tag_variables WHERE tag = $1 AND guild_id = $2"#;
+
+        let mut tx = self.pool.begin().await?;
+
+        let success = sqlx::query(query)
+            .bind(name)
+            .bind(guild_id)
+            .bind(author)
+            .execute(&mut tx)
+            .await
+            .map(|rows| rows.rows_affected() > 0)?;
+
+        if !success {
+            return Ok(false);
+        }
+
+        for query in [
+            aliases_query,
+            history_query,
+            uses_query,
+            published_query,
+            variables_query,
+        ] {
+            sqlx::query(query)
+                .bind(name)
+                .bind(guild_id)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await.map(|_| true)
+    }
+
+    pub async fn edit_tag(
+        &self,
+        author: i64,
+        guild_id: i64,
+        name: &str,
+        new_content: &str,
+    ) -> Result<bool, sqlx::Error> {
+        self.edit_tag_with_history(Some(author), guild_id, name, new_content)
+            .await
+    }
+
+    pub async fn edit_tag_force(
+        &self,
+        guild_id: i64,
+        name: &str,
+        new_content: &str,
+    ) -> Result<bool, sqlx::Error> {
+        self.edit_tag_with_history(None, guild_id, name, new_content)
+            .await
+    }
+
+    /// Edits a tag, keeping its previous content as a new revision in the tag history
+    ///
+    /// If `author` is `None`, the tag is edited regardless of who owns it
+    async fn edit_tag_with_history(
+        &self,
+        author: Option<i64>,
+        guild_id: i64,
+        name: &str,
+        new_content: &str,
+    ) -> Result<bool, sqlx::Error> {
+        // the tag row stays locked until the transaction ends, so concurrent edits can't pick the same revision
+        let lock_query = r#"
+        SELECT name FROM tags
+        WHERE name = coalesce((SELECT target FROM tag_aliases WHERE name = $1 AND guild_id = $2), $1)
+        AND guild_id = $2 AND ($3::bigint IS NULL OR author = $3)
+        FOR UPDATE
+        "#;
+        let history_query = r#"
+        INSERT INTO tag_history (name, guild_id, revision, data, replaced_at)
+        SELECT name, guild_id, (SELECT coalesce(max(revision), 0) + 1 FROM tag_history WHERE name = $1 AND guild_id = $2), data, $3
+        FROM tags
+        WHERE name = $1 AND guild_id = $2
+        "#;
+        let update_query = r#"UPDATE tags SET data = $1 WHERE name = $2 AND guild_id = $3"#;
+
+        let mut tx = self.pool.begin().await?;
+
+        // the name might refer to an alias of the tag
+        let name = match sqlx::query_as::<_, (String,)>(lock_query)
+            .bind(name)
+            .bind(guild_id)
+            .bind(author)
+            .fetch_optional(&mut tx)
+            .await?
+        {
+            Some((name,)) => name,
+            None => return Ok(false),
+        };
+
+        sqlx::query(history_query)
+            .bind(&name)
+            .bind(guild_id)
+            .bind(get_current_millis() as i64)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query(update_query)
+            .bind(new_content)
+            .bind(&name)
+            .bind(guild_id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await.map(|_| true)
+    }
+
+    pub async fn get_tag_history(
+        &self,
+        guild_id: i64,
+        name: &str,
+    ) -> Result<Vec<TagRevision>, sqlx::Error> {
+        let query =
+            r#"SELECT * FROM tag_history WHERE name = $1 AND guild_id = $2 ORDER BY revision DESC"#;
+
+        sqlx::query_as(query)
+            .bind(name)
+            .bind(guild_id)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    pub async fn get_tag_revision(
+        &self,
+        guild_id: i64,
+        name: &str,
+        revision: i32,
+    ) -> Result<Option<TagRevision>, sqlx::Error> {
+        let query =
+            r#"SELECT * FROM tag_history WHERE name = $1 AND guild_id = $2 AND revision = $3"#;
+
+        sqlx::query_as(query)
+            .bind(name)
+            .bind(guild_id)
+            .bind(revision)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    pub async fn transfer_tag(
+        &self,
+        guild_id: i64,
+        name: &str,
+        new_author: i64,
+    ) -> Result<bool, sqlx::Error> {
+        let query = r#"UPDATE tags SET author = $1 WHERE name = $2 AND guild_id = $3"#;
 
         sqlx::query(query)
+            .bind(new_author)
             .bind(name)
             .bind(guild_id)
             .execute(&self.pool)
             .await
-            .map(|rows| rows.rows_affected() > 0)
-            .or_else(|e| {
-                if is_unique_violation(&e) {
-                    Ok(false)
-                } else {
-                    Err(e)
-                }
-            })
+            .map(|r| r.rows_affected() > 0)
     }
 
-    pub async fn remove_tag(
+    /// Creates an alias `name` for the existing tag `target`
+    ///
+    /// Returns `false` if the target does not exist, or if a tag or alias with that name already exists
+    pub async fn add_tag_alias(
         &self,
         author: i64,
         guild_id: i64,
         name: &str,
+        target: &str,
     ) -> Result<bool, sqlx::Error> {
-        let query = r#"DELETE FROM tags WHERE name = $1 AND author = $2 AND guild_id = $3"#;
+        let query = r#"
+        INSERT INTO tag_aliases (name, target, guild_id, author, created_at)
+        SELECT $1, name, guild_id, $3, $4
+        FROM tags
+        WHERE name = $2 AND guild_id = $5
+        AND NOT EXISTS (SELECT 1 FROM tags WHERE name = $1 AND guild_id = $5)
+        "#;
 
         sqlx::query(query)
             .bind(name)
+            .bind(target)
             .bind(author)
+            .bind(get_current_millis() as i64)
             .bind(guild_id)
             .execute(&self.pool)
             .await
-            .map(|rows| rows.rows_affected() > 0)
+            .map(|r| r.rows_affected() > 0)
             .or_else(|e| {
                 if is_unique_violation(&e) {
                     Ok(false)
@@ -896,28 +1102,30 @@ impl Database {
             })
     }
 
-    pub async fn edit_tag(
+    pub async fn remove_tag_alias(
         &self,
-        author: i64,
+        author: Option<i64>,
         guild_id: i64,
         name: &str,
-        new_content: &str,
     ) -> Result<bool, sqlx::Error> {
-        let query =
-            r#"UPDATE tags SET data = $1 WHERE name = $2 AND author = $3 AND guild_id = $4"#;
+        let query = r#"DELETE FROM tag_aliases WHERE name = $1 AND guild_id = $2 AND ($3::bigint IS NULL OR author = $3)"#;
 
         sqlx::query(query)
-            .bind(new_content)
             .bind(name)
-            .bind(author)
             .bind(guild_id)
+            .bind(author)
             .execute(&self.pool)
             .await
-            .map(|r| r.rows_affected() > 0)
+            .map(|rows| rows.rows_affected() > 0)
     }
 
+    /// Fetches a tag by its name, or by the name of one of its aliases
     pub async fn get_tag(&self, guild_id: i64, name: &str) -> Result<Option<Tag>, sqlx::Error> {
-        let query = r#"SELECT * FROM tags WHERE name = $1 AND guild_id = $2"#;
+        let query = r#"
+        SELECT * FROM tags
+        WHERE name = coalesce((SELECT target FROM tag_aliases WHERE name = $1 AND guild_id = $2), $1)
+        AND guild_id = $2
+        "#;
 
         let result = sqlx::query_as(query)
             .bind(name)
@@ -967,6 +1175,181 @@ impl Database {
             .await
     }
 
+    /// Publishes a tag to the global namespace, under the same name
+    ///
+    /// Returns `false` if the tag does not exist, is not owned by `author`,
+    /// or if a global tag with that name already exists
+    pub async fn publish_tag(
+        &self,
+        author: i64,
+        guild_id: i64,
+        name: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let query = r#"
+        INSERT INTO published_tags (name, guild_id, published_at)
+        SELECT name, guild_id, $4
+        FROM tags
+        WHERE name = $1 AND guild_id = $2 AND author = $3
+        "#;
+
+        sqlx::query(query)
+            .bind(name)
+            .bind(guild_id)
+            .bind(author)
+            .bind(get_current_millis() as i64)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() > 0)
+            .or_else(|e| {
+                if is_unique_violation(&e) {
+                    Ok(false)
+                } else {
+                    Err(e)
+                }
+            })
+    }
+
+    /// Removes a tag from the global namespace
+    ///
+    /// If `author` is `None`, the tag is unpublished regardless of who owns it
+    pub async fn unpublish_tag(
+        &self,
+        author: Option<i64>,
+        name: &str,
+    ) -> Result<bool, sqlx::Error> {
+        // ownership is checked against the tag itself, since it can be transferred after it was published
+        let query = r#"
+        DELETE FROM published_tags
+        WHERE name = $1
+        AND ($2::bigint IS NULL OR EXISTS (
+            SELECT 1 FROM tags
+            WHERE tags.name = published_tags.name AND tags.guild_id = published_tags.guild_id AND tags.author = $2
+        ))
+        "#;
+
+        sqlx::query(query)
+            .bind(name)
+            .bind(author)
+            .execute(&self.pool)
+            .await
+            .map(|rows| rows.rows_affected() > 0)
+    }
+
+    /// Fetches a published tag by its global name
+    pub async fn get_published_tag(&self, name: &str) -> Result<Option<Tag>, sqlx::Error> {
+        let query = r#"
+        SELECT tags.* FROM published_tags
+        INNER JOIN tags ON tags.name = published_tags.name AND tags.guild_id = published_tags.guild_id
+        WHERE published_tags.name = $1
+        "#;
+
+        sqlx::query_as(query)
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// Searches tags by name and content, using substring and trigram matching
+    ///
+    /// Trigram matching requires the `pg_trgm` extension
+    pub async fn search_tags(
+        &self,
+        guild_id: i64,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<Tag>, sqlx::Error> {
+        let sql = r#"
+        SELECT * FROM tags
+        WHERE guild_id = $1
+        AND (strpos(lower(name), lower($2)) > 0 OR strpos(lower(data), lower($2)) > 0 OR name % $2)
+        ORDER BY similarity(name, $2) DESC, name ASC
+        LIMIT $3
+        "#;
+
+        sqlx::query_as(sql)
+            .bind(guild_id)
+            .bind(query)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// Returns the names of tags that are similar to `name`, for "did you mean" suggestions
+    ///
+    /// Like [`Database::search_tags`], this requires the `pg_trgm` extension
+    pub async fn get_similar_tag_names(
+        &self,
+        guild_id: i64,
+        name: &str,
+        limit: i64,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let query = r#"
+        SELECT name FROM tags
+        WHERE guild_id = $1 AND name % $2
+        ORDER BY similarity(name, $2) DESC
+        LIMIT $3
+        "#;
+
+        sqlx::query_as::<_, (String,)>(query)
+            .bind(guild_id)
+            .bind(name)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+            .map(|rows| rows.into_iter().map(|(name,)| name).collect())
+    }
+
+    pub async fn increment_tag_uses(&self, guild_id: i64, name: &str) -> Result<(), sqlx::Error> {
+        let query = r#"
+        INSERT INTO tag_uses (name, guild_id, uses, last_used) VALUES ($1, $2, 1, $3)
+        ON CONFLICT (name, guild_id) DO UPDATE SET uses = tag_uses.uses + 1, last_used = $3
+        "#;
+
+        sqlx::query(query)
+            .bind(name)
+            .bind(guild_id)
+            .bind(get_current_millis() as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_tag_usage(
+        &self,
+        guild_id: i64,
+        name: &str,
+    ) -> Result<Option<TagUsage>, sqlx::Error> {
+        let query = r#"SELECT * FROM tag_uses WHERE name = $1 AND guild_id = $2"#;
+
+        sqlx::query_as(query)
+            .bind(name)
+            .bind(guild_id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// Returns the most used tags of a guild, ignoring tags that have since been deleted
+    pub async fn get_top_tags(
+        &self,
+        guild_id: i64,
+        limit: i64,
+    ) -> Result<Vec<TagUsage>, sqlx::Error> {
+        let query = r#"
+        SELECT tag_uses.* FROM tag_uses
+        INNER JOIN tags ON tags.name = tag_uses.name AND tags.guild_id = tag_uses.guild_id
+        WHERE tag_uses.guild_id = $1
+        ORDER BY tag_uses.uses DESC
+        LIMIT $2
+        "#;
+
+        sqlx::query_as(query)
+            .bind(guild_id)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+    }
+
     pub async fn get_tags_count(&self, guild_id: i64) -> Result<i64, sqlx::Error> {
         let query = r#"SELECT count(*) FROM tags WHERE guild_id = $1"#;
 
@@ -994,6 +1377,85 @@ impl Database {
         result.map(|c| c.count)
     }
 
+    pub async fn get_tag_variable(
+        &self,
+        guild_id: i64,
+        tag: &str,
+        key: &str,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let query =
+            r#"SELECT value FROM tag_variables WHERE guild_id = $1 AND tag = $2 AND key = $3"#;
+
+        sqlx::query_as::<_, (String,)>(query)
+            .bind(guild_id)
+            .bind(tag)
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|row| row.map(|(value,)| value))
+    }
+
+    /// Stores a tag variable, returning `false` if the guild's storage quota would be exceeded
+    ///
+    /// Guild-wide variables are stored with an empty tag name
+    pub async fn set_tag_variable(
+        &self,
+        guild_id: i64,
+        tag: &str,
+        key: &str,
+        value: &str,
+        quota: i64,
+    ) -> Result<bool, sqlx::Error> {
+        // locked per guild, so that concurrent writes can't each pass the quota check on their own
+        let lock_query = r#"SELECT pg_advisory_xact_lock($1)"#;
+        let insert_query = r#"
+        INSERT INTO tag_variables (guild_id, tag, key, value)
+        SELECT $1, $2, $3, $4
+        WHERE (
+            SELECT coalesce(sum(octet_length(key) + octet_length(value)), 0)
+            FROM tag_variables
+            WHERE guild_id = $1 AND NOT (tag = $2 AND key = $3)
+        ) + octet_length($3) + octet_length($4) <= $5
+        ON CONFLICT (guild_id, tag, key) DO UPDATE SET value = $4
+        "#;
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(lock_query)
+            .bind(guild_id)
+            .execute(&mut tx)
+            .await?;
+
+        let success = sqlx::query(insert_query)
+            .bind(guild_id)
+            .bind(tag)
+            .bind(key)
+            .bind(value)
+            .bind(quota)
+            .execute(&mut tx)
+            .await
+            .map(|r| r.rows_affected() > 0)?;
+
+        tx.commit().await.map(|_| success)
+    }
+
+    pub async fn delete_tag_variable(
+        &self,
+        guild_id: i64,
+        tag: &str,
+        key: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let query = r#"DELETE FROM tag_variables WHERE guild_id = $1 AND tag = $2 AND key = $3"#;
+
+        sqlx::query(query)
+            .bind(guild_id)
+            .bind(tag)
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .map(|rows| rows.rows_affected() > 0)
+    }
+
     pub async fn fetch_database_size(&self) -> Result<DatabaseSize, sqlx::Error> {
         let query = r#"SELECT pg_size_pretty(pg_database_size('assyst')) as size"#;
 
@@ -1,45 +1,104 @@
 #![feature(never_type)]
 
-use std::{rc::Rc, sync::Arc, time::Duration};
+mod replay;
 
+use std::{sync::Arc, time::Duration};
+
+use anyhow::bail;
 use assyst_common::{
     config::Config,
     consts::{
         gateway::{self},
         EVENT_PIPE,
     },
-    ok_or_break,
+    handshake::{server_handshake, Handshake},
 };
 use bincode::serialize;
-use futures_util::StreamExt;
+use replay::ReplayBuffer;
 use tokio::{
     fs::remove_file,
-    io::{AsyncWriteExt, BufWriter},
+    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
     net::{UnixListener, UnixStream},
     spawn,
-    sync::{mpsc::Sender, Mutex},
-    time::sleep,
-};
-use twilight_gateway::{
-    stream::{create_recommended, ShardMessageStream},
-    Config as GatewayConfig, Intents, Message,
-};
-//use twilight_http::Client;
-use twilight_model::gateway::{
-    payload::outgoing::update_presence::UpdatePresencePayload,
-    presence::{Activity, ActivityType, Status},
+    time::{sleep, timeout},
 };
 
 use serenity::model::gateway::Ready;
 use serenity::prelude::*;
 use serenity::{all::Event, async_trait, gateway::ActivityData};
 
-struct Handler(Sender<Vec<u8>>);
+/// How long a bot has to send its handshake after connecting
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Handler(Arc<ReplayBuffer>);
 #[async_trait]
 impl RawEventHandler for Handler {
     async fn raw_event(&self, _: Context, event: Event) {
         let serialised = serde_json::to_string(&event).unwrap().as_bytes().to_owned();
-        let _ = self.0.send(serialised).await;
+        self.0.push(serialised);
+    }
+}
+
+/// Sends events to a connected bot until it disconnects
+async fn serve_client(mut stream: UnixStream, events: Arc<ReplayBuffer>) -> anyhow::Result<()> {
+    // latencies aren't sent, since serenity reports them to its own handlers
+    let handshake = Handshake::new(gateway::PROTOCOL_VERSION, gateway::capabilities::REPLAY);
+    let capabilities =
+        match timeout(HANDSHAKE_TIMEOUT, server_handshake(&mut stream, handshake)).await {
+            Ok(result) => result?,
+            Err(_) => bail!("no handshake was sent"),
+        };
+    let replay = capabilities & gateway::capabilities::REPLAY != 0;
+
+    let (mut reader, writer) = stream.into_split();
+    let mut writer = BufWriter::new(writer);
+
+    // clients without replay only get the events received from now on
+    let mut last_sent = if replay {
+        reader.read_u64().await?.max(events.acked())
+    } else {
+        events.latest()
+    };
+
+    let acks = async {
+        loop {
+            let op = reader.read_u8().await?;
+            if op != gateway::OP_ACK {
+                bail!("unexpected op {} from the event pipe client", op);
+            }
+            events.ack(reader.read_u64().await?);
+        }
+    };
+
+    let sends = async {
+        // subscribed before the buffer is read, so events pushed in between aren't missed
+        let mut pushed = events.subscribe();
+        loop {
+            let (pending, missed) = events.after(last_sent);
+            if missed > 0 {
+                eprintln!("{} events expired before they could be sent", missed);
+            }
+
+            for (seq, event) in pending {
+                if replay {
+                    writer.write_u8(gateway::OP_SEQUENCED_EVENT).await?;
+                    writer.write_u64(seq).await?;
+                } else {
+                    writer.write_u8(gateway::OP_EVENT).await?;
+                }
+                writer.write_u32(event.len() as u32).await?;
+                writer.write_all(&event).await?;
+                last_sent = seq;
+            }
+            writer.flush().await?;
+
+            pushed.changed().await?;
+        }
+    };
+
+    tokio::select! {
+        result = acks => result,
+        result = sends => result,
     }
 }
 
@@ -50,51 +109,37 @@ async fn main() -> anyhow::Result<!> {
     let config = Config::new();
 
     let activity = ActivityData::playing("-help | jacher.io/assyst");
-    /*
-    let presence = UpdatePresencePayload::new(vec![activity], false, None, Status::Online)?;
-    let http_client = Client::new(config.auth.discord.to_string());
-    let gateway_config = GatewayConfig::builder(
-        config.auth.discord.to_string(),
-        Intents::GUILD_MESSAGES | Intents::GUILDS | Intents::MESSAGE_CONTENT,
-    )
-    .presence(presence)
-    .build();
-
-    let mut shards = create_recommended(&http_client, gateway_config.clone(), |_, _| {
-        gateway_config.clone()
-    })
-    .await?
-    .collect::<Vec<_>>();
-    let stream = Arc::new(Mutex::new(ShardMessageStream::new(shards.iter_mut())));
-
-    let listener = UnixListener::bind(EVENT_PIPE)?;
-    let listener = Rc::new(listener);
-
-    loop {
-        let _ = supply_connection(listener.clone(), stream.clone()).await;
-    }*/
 
     let intents =
         GatewayIntents::GUILD_MESSAGES | GatewayIntents::GUILDS | GatewayIntents::MESSAGE_CONTENT;
     let listener = UnixListener::bind(EVENT_PIPE)?;
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(10);
+    // events are buffered while no bot is connected, instead of blocking the shards
+    let events = Arc::new(ReplayBuffer::new());
+    let client_events = events.clone();
 
     spawn(async move {
         loop {
-            let (stream, _) = listener.accept().await.unwrap();
-            let writer = Arc::new(Mutex::new(BufWriter::new(stream)));
-            while let Some(x) = rx.recv().await {
-                let mut lock = writer.lock().await;
-                ok_or_break!(lock.write_u8(gateway::OP_EVENT).await);
-                ok_or_break!(lock.write_u32(x.len() as u32).await);
-                ok_or_break!(lock.write_all(&x).await);
-                ok_or_break!(lock.flush().await);
-            }
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!("Failed to accept event pipe client: {:?}", e);
+                    continue;
+                }
+            };
+
+            // clients are served on their own tasks, so one that never sends its handshake
+            // doesn't hold up the bot that connects after it
+            let events = client_events.clone();
+            spawn(async move {
+                if let Err(e) = serve_client(stream, events).await {
+                    eprintln!("Event pipe client disconnected: {}", e);
+                }
+            });
         }
     });
 
     let mut client = Client::builder(&config.auth.discord, intents)
-        .raw_event_handler(Handler(tx))
+        .raw_event_handler(Handler(events))
         .activity(activity)
         .await
         .expect("Err creating client");
@@ -103,31 +148,3 @@ async fn main() -> anyhow::Result<!> {
 
     loop {}
 }
-
-pub async fn supply_connection(
-    listener: Rc<UnixListener>,
-    gw_stream: Arc<Mutex<ShardMessageStream<'_>>>,
-) -> anyhow::Result<()> {
-    let (stream, _) = listener.accept().await?;
-    let writer = Arc::new(Mutex::new(BufWriter::new(stream)));
-    // Event loop
-    while let Some((_, event)) = gw_stream.lock().await.next().await {
-        match event {
-            Ok(Message::Text(x)) => {
-                let mut lock = writer.lock().await;
-                ok_or_break!(lock.write_u8(gateway::OP_EVENT).await);
-                ok_or_break!(lock.write_u32(x.as_bytes().len() as u32).await);
-                ok_or_break!(lock.write_all(x.as_bytes()).await);
-                ok_or_break!(lock.flush().await);
-            }
-            o => {
-                // Message::Close event or error?
-                println!("{:?}", o);
-            }
-        }
-    }
-
-    //latency_writer.abort();
-
-    Ok(())
-}
@@ -0,0 +1,266 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+
+/// Most events that are kept for the bot to catch up on
+const MAX_BUFFERED_EVENTS: usize = 10_000;
+/// How long events are kept for the bot to catch up on
+///
+/// Replaying commands that are older than this would only confuse the people who sent them.
+const MAX_EVENT_AGE: Duration = Duration::from_secs(120);
+
+/// An event along with its sequence number
+pub type SequencedEvent = (u64, Arc<[u8]>);
+
+struct BufferedEvent {
+    seq: u64,
+    received_at: Instant,
+    data: Arc<[u8]>,
+}
+
+struct Events {
+    events: VecDeque<BufferedEvent>,
+    next_seq: u64,
+    acked: u64,
+}
+impl Events {
+    fn prune(&mut self) {
+        while let Some(event) = self.events.front() {
+            if self.events.len() > MAX_BUFFERED_EVENTS
+                || event.seq <= self.acked
+                || event.received_at.elapsed() > MAX_EVENT_AGE
+            {
+                self.events.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// Events that the bot hasn't acknowledged yet, numbered in the order they were received
+///
+/// Events stay in the buffer until the bot acknowledges them, or until they're too old or there are too many,
+/// so that they can be sent again if the bot restarts before it processed them.
+pub struct ReplayBuffer {
+    events: Mutex<Events>,
+    /// Sequence number of the most recent event, which every connected client watches
+    latest: watch::Sender<u64>,
+}
+impl ReplayBuffer {
+    pub fn new() -> Self {
+        ReplayBuffer {
+            events: Mutex::new(Events {
+                events: VecDeque::new(),
+                // 0 is reserved for a client that hasn't processed any events
+                next_seq: 1,
+                acked: 0,
+            }),
+            latest: watch::Sender::new(0),
+        }
+    }
+
+    pub fn push(&self, data: Vec<u8>) {
+        let mut events = self.events.lock().unwrap();
+
+        let seq = events.next_seq;
+        events.next_seq += 1;
+        events.events.push_back(BufferedEvent {
+            seq,
+            received_at: Instant::now(),
+            data: data.into(),
+        });
+        events.prune();
+
+        // receivers stay marked as changed until they look, so a client that isn't waiting yet can't miss it
+        self.latest.send_replace(seq);
+    }
+
+    /// Marks every event up to `seq` as processed by the bot
+    pub fn ack(&self, seq: u64) {
+        let mut events = self.events.lock().unwrap();
+        events.acked = events.acked.max(seq);
+        events.prune();
+    }
+
+    pub fn acked(&self) -> u64 {
+        self.events.lock().unwrap().acked
+    }
+
+    /// Sequence number of the most recent event
+    pub fn latest(&self) -> u64 {
+        self.events.lock().unwrap().next_seq - 1
+    }
+
+    /// Returns the buffered events that come after `seq`, along with how many events after it are no longer buffered
+    pub fn after(&self, seq: u64) -> (Vec<SequencedEvent>, u64) {
+        let mut events = self.events.lock().unwrap();
+        events.prune();
+
+        let after = events
+            .events
+            .iter()
+            .filter(|e| e.seq > seq)
+            .map(|e| (e.seq, e.data.clone()))
+            .collect::<Vec<_>>();
+
+        let first = after.first().map_or(events.next_seq, |(seq, _)| *seq);
+        // events that were acknowledged weren't lost, they were already processed
+        let missed = first.saturating_sub(seq.max(events.acked) + 1);
+
+        (after, missed)
+    }
+
+    /// Returns a receiver that is marked as changed whenever an event is pushed
+    ///
+    /// Every client needs its own, since more than one can be connected at a time.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.latest.subscribe()
+    }
+}
+impl Default for ReplayBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer_with(count: usize) -> ReplayBuffer {
+        let buffer = ReplayBuffer::new();
+        for i in 0..count {
+            buffer.push(vec![i as u8]);
+        }
+        buffer
+    }
+
+    fn seqs(events: &[SequencedEvent]) -> Vec<u64> {
+        events.iter().map(|(seq, _)| *seq).collect()
+    }
+
+    /// Makes the event with the given sequence number look like it was received `age` ago
+    fn backdate(buffer: &ReplayBuffer, seq: u64, age: Duration) {
+        let mut events = buffer.events.lock().unwrap();
+        let event = events.events.iter_mut().find(|e| e.seq == seq).unwrap();
+        event.received_at = Instant::now().checked_sub(age).unwrap();
+    }
+
+    #[test]
+    fn numbers_events_from_one() {
+        let buffer = buffer_with(3);
+        assert_eq!(buffer.latest(), 3);
+
+        let (events, missed) = buffer.after(0);
+        assert_eq!(seqs(&events), vec![1, 2, 3]);
+        assert_eq!(&*events[1].1, &[1]);
+        assert_eq!(missed, 0);
+
+        assert_eq!(seqs(&buffer.after(2).0), vec![3]);
+        assert!(buffer.after(3).0.is_empty());
+    }
+
+    #[test]
+    fn prunes_acked_events() {
+        let buffer = buffer_with(5);
+
+        buffer.ack(3);
+        assert_eq!(buffer.acked(), 3);
+        assert_eq!(buffer.events.lock().unwrap().events.len(), 2);
+
+        // acks never go backwards
+        buffer.ack(1);
+        assert_eq!(buffer.acked(), 3);
+
+        buffer.ack(5);
+        assert!(buffer.events.lock().unwrap().events.is_empty());
+        assert_eq!(buffer.latest(), 5);
+    }
+
+    #[test]
+    fn replays_unacked_events_to_a_client_that_reconnects_with_zero() {
+        let buffer = buffer_with(5);
+        buffer.ack(2);
+
+        // a restarted bot hasn't processed anything itself, but acked events were handled before it restarted
+        let (events, missed) = buffer.after(0);
+        assert_eq!(seqs(&events), vec![3, 4, 5]);
+        assert_eq!(missed, 0);
+    }
+
+    #[test]
+    fn drops_expired_events() {
+        let buffer = buffer_with(3);
+        backdate(&buffer, 1, MAX_EVENT_AGE + Duration::from_secs(1));
+        backdate(&buffer, 2, MAX_EVENT_AGE - Duration::from_secs(10));
+
+        let (events, missed) = buffer.after(0);
+        assert_eq!(seqs(&events), vec![2, 3]);
+        assert_eq!(missed, 1);
+    }
+
+    #[test]
+    fn only_expires_from_the_front() {
+        let buffer = buffer_with(3);
+        backdate(&buffer, 2, MAX_EVENT_AGE + Duration::from_secs(1));
+
+        // events are received in order, so an older event behind a newer one is kept until it reaches the front
+        assert_eq!(seqs(&buffer.after(0).0), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn caps_the_number_of_events() {
+        let buffer = buffer_with(MAX_BUFFERED_EVENTS + 5);
+
+        let (events, missed) = buffer.after(0);
+        assert_eq!(events.len(), MAX_BUFFERED_EVENTS);
+        assert_eq!(events[0].0, 6);
+        assert_eq!(missed, 5);
+
+        // a client that got past the dropped events didn't miss any of them
+        let (events, missed) = buffer.after(10);
+        assert_eq!(events[0].0, 11);
+        assert_eq!(missed, 0);
+    }
+
+    #[test]
+    fn counts_missed_events_between_sent_and_buffered() {
+        let buffer = buffer_with(6);
+        buffer.ack(1);
+        backdate(&buffer, 2, MAX_EVENT_AGE + Duration::from_secs(1));
+        backdate(&buffer, 3, MAX_EVENT_AGE + Duration::from_secs(1));
+        backdate(&buffer, 4, MAX_EVENT_AGE + Duration::from_secs(1));
+
+        let (events, missed) = buffer.after(0);
+        assert_eq!(seqs(&events), vec![5, 6]);
+        assert_eq!(missed, 3);
+
+        // the client had already been sent 3, so only 4 was missed
+        assert_eq!(buffer.after(3).1, 1);
+
+        // everything expired, so all events after the client's were missed
+        backdate(&buffer, 5, MAX_EVENT_AGE + Duration::from_secs(1));
+        backdate(&buffer, 6, MAX_EVENT_AGE + Duration::from_secs(1));
+        let (events, missed) = buffer.after(3);
+        assert!(events.is_empty());
+        assert_eq!(missed, 3);
+    }
+
+    #[tokio::test]
+    async fn notifies_every_subscriber() {
+        let buffer = ReplayBuffer::new();
+        let mut first = buffer.subscribe();
+        let mut second = buffer.subscribe();
+
+        buffer.push(vec![0]);
+        first.changed().await.unwrap();
+        second.changed().await.unwrap();
+        assert_eq!(*first.borrow(), 1);
+        assert!(!second.has_changed().unwrap());
+    }
+}
@@ -1,20 +1,68 @@
 use anyhow::anyhow;
 use assyst_common::eval::FakeEvalImageResponse;
+use std::{cell::RefCell, collections::HashMap};
 
-/// A "no-op" context, which returns an error for any of the methods
+/// A "no-op" context, which returns an error for most of the methods
 ///
 /// This is useful for testing the parser, when you need to provide a Context but
 /// don't really need its functionality.
-pub struct NopContext;
+/// Persistent variables are kept in memory, so that tags using them can still be tested.
+#[derive(Default)]
+pub struct NopContext {
+    /// Persistent variables, keyed by the tag they belong to (`None` for guild variables) and their key
+    variables: RefCell<HashMap<(Option<String>, String), String>>,
+}
+
+/// A tag, as returned by [`Context::get_tag`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    /// The name that variables of the tag are scoped to, which differs from the requested name for aliases
+    pub name: String,
+    pub contents: String,
+}
+
+/// A guild, as returned by [`Context::get_guild`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Guild {
+    pub name: String,
+    pub member_count: u64,
+}
+
+/// A guild member, as returned by [`Context::get_member`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub username: String,
+    pub nickname: Option<String>,
+    pub roles: Vec<Role>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Role {
+    pub id: u64,
+    pub name: String,
+}
 
 fn not_implemented<T>() -> anyhow::Result<T> {
     Err(anyhow!("Not implemented"))
 }
 
+/// Information about where a tag is being run
+///
+/// Unlike the methods of [`Context`], these are expected to be available without doing any I/O.
+pub trait Environment {
+    /// Returns the channel ID of where this message was sent
+    fn channel_id(&self) -> anyhow::Result<u64>;
+    /// Returns the guild ID of where this message was sent
+    fn guild_id(&self) -> anyhow::Result<u64>;
+    /// Returns the user ID of the message author
+    fn user_id(&self) -> anyhow::Result<u64>;
+}
+
 /// External context for the parser
 ///
 /// It contains methods that can be provided by the caller (normally the bot crate).
-pub trait Context {
+/// Implementations of this trait block on I/O, see [`crate::Session`] for running tags without blocking.
+pub trait Context: Environment {
     /// Executes provided JavaScript code and returns the result (string or image)
     fn execute_javascript(
         &self,
@@ -27,16 +75,45 @@ pub trait Context {
     fn get_avatar(&self, user_id: Option<u64>) -> anyhow::Result<String>;
     /// Downloads the URL and returns the contents as a string
     fn download(&self, url: &str) -> anyhow::Result<String>;
-    /// Returns the channel ID of where this message was sent
-    fn channel_id(&self) -> anyhow::Result<u64>;
-    /// Returns the guild ID of where this message was sent
-    fn guild_id(&self) -> anyhow::Result<u64>;
-    /// Returns the user ID of the message author
-    fn user_id(&self) -> anyhow::Result<u64>;
     /// Returns the tag of the provided ID
     fn user_tag(&self, id: Option<u64>) -> anyhow::Result<String>;
-    /// Loads the contents of a tag
-    fn get_tag_contents(&self, tag: &str) -> anyhow::Result<String>;
+    /// Loads a tag by its name or one of its aliases
+    fn get_tag(&self, name: &str) -> anyhow::Result<Tag>;
+    /// Returns the guild of where this message was sent
+    fn get_guild(&self) -> anyhow::Result<Guild>;
+    /// Returns the name of the provided channel, or the channel this message was sent in
+    fn get_channel_name(&self, channel_id: Option<u64>) -> anyhow::Result<String>;
+    /// Returns the guild member of the provided user, or the message author
+    fn get_member(&self, user_id: Option<u64>) -> anyhow::Result<Member>;
+    /// Loads a persistent variable of the given tag, or of the guild if `tag` is `None`
+    fn get_persistent_variable(
+        &self,
+        tag: Option<&str>,
+        key: &str,
+    ) -> anyhow::Result<Option<String>>;
+    /// Stores a persistent variable for the given tag, or for the guild if `tag` is `None`
+    fn set_persistent_variable(
+        &self,
+        tag: Option<&str>,
+        key: &str,
+        value: &str,
+    ) -> anyhow::Result<()>;
+    /// Deletes a persistent variable of the given tag, or of the guild if `tag` is `None`
+    fn delete_persistent_variable(&self, tag: Option<&str>, key: &str) -> anyhow::Result<()>;
+}
+
+impl Environment for NopContext {
+    fn channel_id(&self) -> anyhow::Result<u64> {
+        not_implemented()
+    }
+
+    fn guild_id(&self) -> anyhow::Result<u64> {
+        not_implemented()
+    }
+
+    fn user_id(&self) -> anyhow::Result<u64> {
+        not_implemented()
+    }
 }
 
 impl Context for NopContext {
@@ -60,25 +137,67 @@ impl Context for NopContext {
         not_implemented()
     }
 
-    fn channel_id(&self) -> anyhow::Result<u64> {
+    fn user_tag(&self, _id: Option<u64>) -> anyhow::Result<String> {
         not_implemented()
     }
 
-    fn guild_id(&self) -> anyhow::Result<u64> {
+    fn get_tag(&self, _: &str) -> anyhow::Result<Tag> {
         not_implemented()
     }
 
-    fn user_id(&self) -> anyhow::Result<u64> {
+    fn get_guild(&self) -> anyhow::Result<Guild> {
         not_implemented()
     }
 
-    fn user_tag(&self, _id: Option<u64>) -> anyhow::Result<String> {
+    fn get_channel_name(&self, _channel_id: Option<u64>) -> anyhow::Result<String> {
         not_implemented()
     }
 
-    fn get_tag_contents(&self, _: &str) -> anyhow::Result<String> {
+    fn get_member(&self, _user_id: Option<u64>) -> anyhow::Result<Member> {
         not_implemented()
     }
+
+    fn get_persistent_variable(
+        &self,
+        tag: Option<&str>,
+        key: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let variables = self.variables.borrow();
+        Ok(variables
+            .get(&(tag.map(String::from), key.to_owned()))
+            .cloned())
+    }
+
+    fn set_persistent_variable(
+        &self,
+        tag: Option<&str>,
+        key: &str,
+        value: &str,
+    ) -> anyhow::Result<()> {
+        let mut variables = self.variables.borrow_mut();
+        variables.insert((tag.map(String::from), key.to_owned()), value.to_owned());
+        Ok(())
+    }
+
+    fn delete_persistent_variable(&self, tag: Option<&str>, key: &str) -> anyhow::Result<()> {
+        let mut variables = self.variables.borrow_mut();
+        variables.remove(&(tag.map(String::from), key.to_owned()));
+        Ok(())
+    }
+}
+
+impl Environment for &dyn Context {
+    fn channel_id(&self) -> anyhow::Result<u64> {
+        (**self).channel_id()
+    }
+
+    fn guild_id(&self) -> anyhow::Result<u64> {
+        (**self).guild_id()
+    }
+
+    fn user_id(&self) -> anyhow::Result<u64> {
+        (**self).user_id()
+    }
 }
 
 impl Context for &dyn Context {
@@ -102,23 +221,44 @@ impl Context for &dyn Context {
         (**self).download(url)
     }
 
-    fn channel_id(&self) -> anyhow::Result<u64> {
-        (**self).channel_id()
+    fn user_tag(&self, user_id: Option<u64>) -> anyhow::Result<String> {
+        (**self).user_tag(user_id)
     }
 
-    fn guild_id(&self) -> anyhow::Result<u64> {
-        (**self).guild_id()
+    fn get_tag(&self, name: &str) -> anyhow::Result<Tag> {
+        (**self).get_tag(name)
     }
 
-    fn user_id(&self) -> anyhow::Result<u64> {
-        (**self).user_id()
+    fn get_guild(&self) -> anyhow::Result<Guild> {
+        (**self).get_guild()
     }
 
-    fn user_tag(&self, user_id: Option<u64>) -> anyhow::Result<String> {
-        (**self).user_tag(user_id)
+    fn get_channel_name(&self, channel_id: Option<u64>) -> anyhow::Result<String> {
+        (**self).get_channel_name(channel_id)
+    }
+
+    fn get_member(&self, user_id: Option<u64>) -> anyhow::Result<Member> {
+        (**self).get_member(user_id)
+    }
+
+    fn get_persistent_variable(
+        &self,
+        tag: Option<&str>,
+        key: &str,
+    ) -> anyhow::Result<Option<String>> {
+        (**self).get_persistent_variable(tag, key)
+    }
+
+    fn set_persistent_variable(
+        &self,
+        tag: Option<&str>,
+        key: &str,
+        value: &str,
+    ) -> anyhow::Result<()> {
+        (**self).set_persistent_variable(tag, key, value)
     }
 
-    fn get_tag_contents(&self, tag: &str) -> anyhow::Result<String> {
-        (**self).get_tag_contents(tag)
+    fn delete_persistent_variable(&self, tag: Option<&str>, key: &str) -> anyhow::Result<()> {
+        (**self).delete_persistent_variable(tag, key)
     }
 }
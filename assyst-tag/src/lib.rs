@@ -1,34 +1,120 @@
-use assyst_common::filetype::Type;
+use assyst_common::{filetype::Type, util::get_current_millis};
 use bytes::Bytes;
 pub use context::Context;
+pub use context::Environment;
 pub use context::NopContext;
+pub use context::{Guild, Member, Role, Tag};
+pub use message::{Embed, EmbedField, MessageOptions, ReplyMode};
 use parser::Counter;
 pub use parser::Parser;
 use parser::SharedState;
+pub use parser::TraceEntry;
+use rand::{rngs::StdRng, SeedableRng};
+pub use session::{Request, Response, Session, Step};
 use std::cell::RefCell;
 use std::collections::HashMap;
+pub use validate::{validate, Diagnostic};
 
 mod context;
+mod math;
+mod message;
 mod parser;
+mod session;
 mod subtags;
+mod validate;
 
 #[derive(Debug)]
 pub struct ParseResult {
     pub output: String,
     pub attachment: Option<(Bytes, Type)>,
+    pub message: MessageOptions,
 }
 
 pub fn parse<C: Context>(input: &str, args: &[&str], cx: C) -> anyhow::Result<ParseResult> {
+    parse_inner(
+        None,
+        input,
+        args,
+        cx,
+        rand::random(),
+        get_current_millis(),
+        None,
+    )
+}
+
+/// Parses the contents of the tag `name`
+///
+/// Unlike [`parse`], this allows the tag to use tag-scoped persistent variables
+pub fn parse_tag<C: Context>(
+    name: &str,
+    input: &str,
+    args: &[&str],
+    cx: C,
+) -> anyhow::Result<ParseResult> {
+    parse_inner(
+        Some(name),
+        input,
+        args,
+        cx,
+        rand::random(),
+        get_current_millis(),
+        None,
+    )
+}
+
+/// Parses the contents of the tag `name` like [`parse_tag`], recording every subtag invocation
+///
+/// The trace is returned even if parsing fails, which makes it useful for debugging tags.
+pub fn parse_tag_traced<C: Context>(
+    name: &str,
+    input: &str,
+    args: &[&str],
+    cx: C,
+) -> (anyhow::Result<ParseResult>, Vec<TraceEntry>) {
+    let trace = RefCell::new(Vec::new());
+    let result = parse_inner(
+        Some(name),
+        input,
+        args,
+        cx,
+        rand::random(),
+        get_current_millis(),
+        Some(&trace),
+    );
+
+    (result, trace.into_inner())
+}
+
+fn parse_inner<C: Context>(
+    name: Option<&str>,
+    input: &str,
+    args: &[&str],
+    cx: C,
+    seed: u64,
+    now: u64,
+    trace: Option<&RefCell<Vec<TraceEntry>>>,
+) -> anyhow::Result<ParseResult> {
     let variables = RefCell::new(HashMap::new());
     let counter = Counter::default();
     let attachment = RefCell::new(None);
-    let state = SharedState::new(&variables, &counter, &attachment);
+    let message = RefCell::new(MessageOptions::default());
+    let rng = RefCell::new(StdRng::seed_from_u64(seed));
+    let mut state = SharedState::new(&variables, &counter, &attachment, &message, &rng, now);
+    if let Some(trace) = trace {
+        state = state.with_trace(trace);
+    }
+
+    let mut parser = Parser::new(input.as_bytes(), args, state, &cx);
+    if let Some(name) = name {
+        parser = parser.with_tag_name(name);
+    }
 
-    let output = Parser::new(input.as_bytes(), args, state, &cx).parse_segment(true)?;
+    let output = parser.parse_segment(true)?;
 
     Ok(ParseResult {
         output,
         attachment: attachment.into_inner(),
+        message: message.into_inner(),
     })
 }
 
@@ -47,7 +133,7 @@ mod tests {
     #[test]
     fn parse_test() {
         let input = "testing \\{ abc";
-        let segment = parse(input, &["h", "o"], NopContext);
+        let segment = parse(input, &["h", "o"], NopContext::default());
         match segment {
             Ok(r) => println!("{r:?}"),
             Err(e) => println!("Error: {:?}", e),
@@ -57,10 +143,294 @@ mod tests {
     #[test]
     fn tags_invoke_each_other() {
         let input = "tag content: {tag:wtf|a|b}!";
-        let segment = parse(input, &["h", "o"], NopContext);
+        let segment = parse(input, &["h", "o"], NopContext::default());
         match segment {
             Ok(r) => println!("{r:?}"),
             Err(e) => println!("Error: {:?}", e),
         }
     }
+
+    #[test]
+    fn loops() {
+        let output = |input| parse(input, &[], NopContext::default()).unwrap().output;
+
+        assert_eq!(output("{for:i|1|5|{get:i} }"), "1 2 3 4 5 ");
+        assert_eq!(output("{for:i|3|1|{get:i}}"), "321");
+        assert_eq!(output("{foreach:x|a,b,c|[{get:x}]}"), "[a][b][c]");
+        assert_eq!(output("{foreach:x||{arg:0}}done"), "done");
+        assert_eq!(
+            output(
+                "{set:n|3}{while:{get:n}|{get:n}{set:n|{if:{get:n}|=|1|0|{if:{get:n}|=|2|1|2}}}}"
+            ),
+            "321"
+        );
+        assert!(parse("{for:i|1|100000|x}", &[], NopContext::default()).is_err());
+        assert!(parse("{while:1|x}", &[], NopContext::default()).is_err());
+    }
+
+    #[test]
+    fn persistent_variables() {
+        let cx = NopContext::default();
+
+        let output = parse_tag("a", "{gset:x|1}{tagset:x|2}", &[], &cx as &dyn Context);
+        assert_eq!(output.unwrap().output, "");
+
+        let output = parse_tag("b", "{gget:x}{tagget:x}", &[], &cx as &dyn Context);
+        assert_eq!(output.unwrap().output, "1");
+
+        let output = parse_tag("a", "{gget:x}{tagget:x}", &[], &cx as &dyn Context);
+        assert_eq!(output.unwrap().output, "12");
+
+        assert!(parse("{tagget:x}", &[], &cx as &dyn Context).is_err());
+    }
+
+    #[test]
+    fn math() {
+        let output = |input| parse(input, &[], NopContext::default()).unwrap().output;
+
+        assert_eq!(output("{math:1 + 2 * 3}"), "7");
+        assert_eq!(output("{math:(1 + 2) * 3}"), "9");
+        assert_eq!(output("{math:2 ^ 3 ^ 2}"), "512");
+        assert_eq!(output("{math:7 % 4 / 2}"), "1.5");
+        assert_eq!(output("{math:-max(1, 5, 3) + abs(-2)}"), "-3");
+        assert_eq!(output("{math:1 < 2 && !(3 >= 4) || 0}"), "1");
+        assert!(parse("{math:1 / 0}", &[], NopContext::default()).is_err());
+        assert!(parse("{math:1 +}", &[], NopContext::default()).is_err());
+
+        assert_eq!(output("{if:1.5|>|1.25|yes|no}"), "yes");
+        assert_eq!(output("{if:1.0|==|1|yes|no}"), "yes");
+        assert_eq!(output("{if:hello world|contains|lo w|yes|no}"), "yes");
+        assert_eq!(output("{if:hello|startswith|he|yes|no}"), "yes");
+        assert_eq!(output("{if:abc123|regex|^[a-z]+\\d+$|yes|no}"), "yes");
+    }
+
+    #[test]
+    fn text() {
+        let output = |input| parse(input, &[], NopContext::default()).unwrap().output;
+
+        assert_eq!(output("{regex:\\d+|abc 123 456}"), "123");
+        assert_eq!(output("{regex:(\\w+)@|me@example.com|1}"), "me");
+        assert_eq!(output("{regexreplace:\\d|#|a1b2}"), "a#b#");
+        assert_eq!(output("{regexreplace:(\\w)(\\d)|$2$1|a1b2}"), "1a2b");
+        assert_eq!(output("{regexreplace:|-|ab}"), "-a-b-");
+        // an empty pattern matches between every character, so the output has to be bounded as it's built
+        let long = "x".repeat(24_000);
+        assert!(subtags::regexreplace(vec![String::new(), long.clone(), long]).is_err());
+        assert_eq!(output("{split: |a b c|1}"), "b");
+        assert_eq!(output("{foreach:x|{split:-|a-b}|{get:x}.}"), "a.b.");
+        assert_eq!(output("{substring:1|3|héllo}"), "él");
+        assert_eq!(output("{trim:  hi  }"), "hi");
+        assert_eq!(output("{urlencode:a b&c}"), "a%20b%26c");
+        assert_eq!(
+            output(r#"{jsonget:a.b.1|\{"a":\{"b":[1,"two"]\}\}}"#),
+            "two"
+        );
+        assert!(parse("{regex:(|x}", &[], NopContext::default()).is_err());
+    }
+
+    #[test]
+    fn validation() {
+        let offsets = |input| {
+            validate(input)
+                .into_iter()
+                .map(|d| d.offset)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(validate("{if:{arg:0}|=|a|{get:x}|b} \\} {note:{bogus}}").is_empty());
+        assert_eq!(offsets("hi {bogus:1}"), [3]);
+        assert_eq!(offsets("{get:a|b}"), [0]);
+        assert_eq!(offsets("{replace:a}"), [0]);
+        assert_eq!(offsets("a } b"), [2]);
+        assert_eq!(offsets("x{get:{arg:0}"), [1]);
+    }
+
+    #[test]
+    fn time_and_numbers() {
+        let run = |input| parse(input, &[], NopContext::default()).unwrap().output;
+
+        assert_eq!(run("{timestamp:%Y-%m-%d %H:%M|0}"), "1970-01-01 00:00");
+        assert_eq!(run("{dateadd:1d2h|0}"), "93600000");
+        assert_eq!(run("{dateadd:-1h|7200000}"), "3600000");
+        assert_eq!(run("{discordtime:90000|R}"), "<t:90:R>");
+        assert_eq!(run("{duration:7200000}"), "2 hours");
+        assert!(run("{now}").parse::<u64>().unwrap() > 0);
+        assert!(parse("{timestamp:%Q}", &[], NopContext::default()).is_err());
+
+        assert_eq!(run("{round:2.6} {round:3.14159|2}"), "3 3.14");
+        assert_eq!(run("{floor:2.6} {ceil:2.1}"), "2 3");
+        assert_eq!(run("{format:0,000.00|1234.5}"), "1,234.50");
+        assert_eq!(run("{format:000|-7}"), "-007");
+        assert_eq!(run("{format:0,0|1234567}"), "1,234,567");
+    }
+
+    #[test]
+    fn message_output() {
+        let input = "{embed:Title|Body}{embedcolor:#ff0000}{embedfield:a|b|true}{reply:123}text";
+        let result = parse(input, &[], NopContext::default()).unwrap();
+
+        assert_eq!(result.output, "text");
+        assert_eq!(
+            result.message.embed,
+            Some(Embed {
+                title: Some("Title".to_owned()),
+                description: Some("Body".to_owned()),
+                color: Some(0xff0000),
+                image: None,
+                fields: vec![EmbedField {
+                    name: "a".to_owned(),
+                    value: "b".to_owned(),
+                    inline: true,
+                }],
+            })
+        );
+        assert_eq!(result.message.reply, ReplyMode::Reply(Some(123)));
+
+        let result = parse("{nomention}", &[], NopContext::default()).unwrap();
+        assert_eq!(result.message.embed, None);
+        assert_eq!(result.message.reply, ReplyMode::Disabled);
+
+        assert!(parse("{embedcolor:#1000000}", &[], NopContext::default()).is_err());
+        assert!(parse(
+            "{embedimage:file:///etc/passwd}",
+            &[],
+            NopContext::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn session() {
+        fn run(session: &mut Session, responses: &[(Request, Response)]) -> ParseResult {
+            let mut responses = responses.iter();
+
+            loop {
+                match session.step(&NopContext::default()) {
+                    Step::Pending(request) => {
+                        let (expected, response) = responses.next().expect("unexpected request");
+                        assert_eq!(&request, expected);
+                        session.resume(Ok(response.clone()));
+                    }
+                    Step::Done(result) => return result.unwrap(),
+                }
+            }
+        }
+
+        let mut session = Session::new("{download:{arg:0}}-{tag:foo}", vec!["url".to_owned()]);
+        let result = run(
+            &mut session,
+            &[
+                (
+                    Request::Download("url".to_owned()),
+                    Response::Text("a".to_owned()),
+                ),
+                (
+                    Request::GetTag("foo".to_owned()),
+                    Response::Tag(Tag {
+                        name: "foo".to_owned(),
+                        contents: "{upper:b}".to_owned(),
+                    }),
+                ),
+            ],
+        );
+        assert_eq!(result.output, "a-B");
+
+        // variables of a tag invoked through an alias are scoped to the tag it resolves to
+        let mut session = Session::new("{tag:alias}", vec![]);
+        run(
+            &mut session,
+            &[
+                (
+                    Request::GetTag("alias".to_owned()),
+                    Response::Tag(Tag {
+                        name: "foo".to_owned(),
+                        contents: "{tagset:x|1}".to_owned(),
+                    }),
+                ),
+                (
+                    Request::SetPersistentVariable {
+                        tag: Some("foo".to_owned()),
+                        key: "x".to_owned(),
+                        value: "1".to_owned(),
+                    },
+                    Response::Done,
+                ),
+            ],
+        );
+
+        // random numbers need to be the same when replaying, otherwise the request would differ
+        let mut session = Session::new("{download:{range:1|1000000}}", vec![]).with_seed(42);
+        let Step::Pending(request) = session.step(&NopContext::default()) else {
+            panic!("expected a request");
+        };
+        session.resume(Ok(Response::Text("ok".to_owned())));
+        let Step::Done(result) = session.step(&NopContext::default()) else {
+            panic!("expected the tag to finish, after {request:?}");
+        };
+        assert_eq!(result.unwrap().output, "ok");
+    }
+
+    #[test]
+    fn members() {
+        let member = Member {
+            username: "alice".to_owned(),
+            nickname: None,
+            roles: vec![
+                Role {
+                    id: 1,
+                    name: "Mods".to_owned(),
+                },
+                Role {
+                    id: 2,
+                    name: "Cool".to_owned(),
+                },
+            ],
+        };
+
+        let mut session = Session::new(
+            "{nickname:5} [{roles}] {hasrole:5|mods} {hasrole:5|2} {hasrole:5|3}",
+            vec![],
+        );
+        let output = loop {
+            match session.step(&NopContext::default()) {
+                Step::Pending(Request::GetMember(_)) => {
+                    session.resume(Ok(Response::Member(member.clone())))
+                }
+                Step::Pending(request) => panic!("unexpected request {request:?}"),
+                Step::Done(result) => break result.unwrap().output,
+            }
+        };
+        assert_eq!(output, "alice [Mods, Cool] true true false");
+
+        assert!(parse("{username:abc}", &[], NopContext::default()).is_err());
+    }
+
+    #[test]
+    fn tracing() {
+        let (result, trace) =
+            parse_tag_traced("t", "{upper:{arg:0}}", &["hi"], NopContext::default());
+        assert_eq!(result.unwrap().output, "HI");
+
+        let summary = trace
+            .iter()
+            .map(|e| (e.name.as_str(), e.args.clone(), e.output.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "upper",
+                    Some(vec!["hi".to_owned()]),
+                    Some(Ok("HI".to_owned()))
+                ),
+                ("arg", Some(vec!["0".to_owned()]), Some(Ok("hi".to_owned()))),
+            ]
+        );
+
+        let (result, trace) =
+            parse_tag_traced("t", "{if:1|=|1|{arg:5}|no}", &[], NopContext::default());
+        assert!(result.is_err());
+        assert_eq!(trace[0].args, None);
+        assert!(matches!(trace[1].output, Some(Err(_))));
+    }
 }
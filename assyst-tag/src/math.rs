@@ -0,0 +1,310 @@
+use anyhow::{anyhow, bail, ensure};
+
+/// Maximum nesting of parentheses and unary operators, to avoid stack overflows
+const MAX_NESTING: u32 = 100;
+
+/// Evaluates a mathematical expression
+///
+/// Supports `+ - * / % ^`, parentheses, comparisons (`== != < <= > >=`),
+/// boolean operators (`&& || !`) and a couple of builtin functions and constants.
+/// Booleans are represented as `1` and `0`.
+pub fn eval(input: &str) -> anyhow::Result<f64> {
+    let mut parser = ExprParser {
+        input: input.as_bytes(),
+        idx: 0,
+        nesting: 0,
+    };
+
+    let value = parser.parse_or()?;
+
+    parser.skip_whitespace();
+    ensure!(
+        parser.idx == parser.input.len(),
+        "Unexpected character at position {}",
+        parser.idx
+    );
+
+    Ok(value)
+}
+
+/// Converts a boolean to its numeric representation
+fn bool_to_f64(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Calls a builtin function
+fn call_builtin(name: &str, args: &[f64]) -> anyhow::Result<f64> {
+    fn unary(name: &str, args: &[f64], f: fn(f64) -> f64) -> anyhow::Result<f64> {
+        match args {
+            [x] => Ok(f(*x)),
+            _ => bail!("{name} expects exactly 1 argument"),
+        }
+    }
+
+    match name {
+        "abs" => unary(name, args, f64::abs),
+        "cos" => unary(name, args, f64::cos),
+        "sin" => unary(name, args, f64::sin),
+        "tan" => unary(name, args, f64::tan),
+        "sqrt" => unary(name, args, f64::sqrt),
+        "max" => args
+            .iter()
+            .copied()
+            .reduce(f64::max)
+            .ok_or_else(|| anyhow!("max expects at least 1 argument")),
+        "min" => args
+            .iter()
+            .copied()
+            .reduce(f64::min)
+            .ok_or_else(|| anyhow!("min expects at least 1 argument")),
+        _ => bail!("Unknown function: {name}"),
+    }
+}
+
+/// Returns the value of a builtin constant
+fn constant(name: &str) -> anyhow::Result<f64> {
+    match name {
+        "pi" => Ok(std::f64::consts::PI),
+        "e" => Ok(std::f64::consts::E),
+        "true" => Ok(1.0),
+        "false" => Ok(0.0),
+        _ => bail!("Unknown constant: {name}"),
+    }
+}
+
+/// A recursive descent parser for math expressions, which evaluates while parsing
+struct ExprParser<'a> {
+    input: &'a [u8],
+    idx: usize,
+    nesting: u32,
+}
+
+impl<'a> ExprParser<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.input.get(self.idx) {
+            self.idx += 1;
+        }
+    }
+
+    /// Eats the given operator if it's next in the input, after skipping whitespace
+    fn eat(&mut self, op: &str) -> bool {
+        self.skip_whitespace();
+
+        if self.input[self.idx..].starts_with(op.as_bytes()) {
+            self.idx += op.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn enter(&mut self) -> anyhow::Result<()> {
+        self.nesting += 1;
+        ensure!(
+            self.nesting <= MAX_NESTING,
+            "Expression is nested too deeply"
+        );
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.nesting -= 1;
+    }
+
+    fn parse_or(&mut self) -> anyhow::Result<f64> {
+        let mut lhs = self.parse_and()?;
+
+        while self.eat("||") {
+            let rhs = self.parse_and()?;
+            lhs = bool_to_f64(lhs != 0.0 || rhs != 0.0);
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> anyhow::Result<f64> {
+        let mut lhs = self.parse_comparison()?;
+
+        while self.eat("&&") {
+            let rhs = self.parse_comparison()?;
+            lhs = bool_to_f64(lhs != 0.0 && rhs != 0.0);
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_comparison(&mut self) -> anyhow::Result<f64> {
+        let lhs = self.parse_additive()?;
+
+        // order matters here: two character operators need to be checked first
+        let result = if self.eat("==") {
+            lhs == self.parse_additive()?
+        } else if self.eat("!=") {
+            lhs != self.parse_additive()?
+        } else if self.eat("<=") {
+            lhs <= self.parse_additive()?
+        } else if self.eat(">=") {
+            lhs >= self.parse_additive()?
+        } else if self.eat("<") {
+            lhs < self.parse_additive()?
+        } else if self.eat(">") {
+            lhs > self.parse_additive()?
+        } else {
+            return Ok(lhs);
+        };
+
+        Ok(bool_to_f64(result))
+    }
+
+    fn parse_additive(&mut self) -> anyhow::Result<f64> {
+        let mut lhs = self.parse_multiplicative()?;
+
+        loop {
+            if self.eat("+") {
+                lhs += self.parse_multiplicative()?;
+            } else if self.eat("-") {
+                lhs -= self.parse_multiplicative()?;
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> anyhow::Result<f64> {
+        let mut lhs = self.parse_unary()?;
+
+        loop {
+            if self.eat("*") {
+                lhs *= self.parse_unary()?;
+            } else if self.eat("/") {
+                let rhs = self.parse_unary()?;
+                ensure!(rhs != 0.0, "Division by zero");
+                lhs /= rhs;
+            } else if self.eat("%") {
+                let rhs = self.parse_unary()?;
+                ensure!(rhs != 0.0, "Division by zero");
+                lhs %= rhs;
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> anyhow::Result<f64> {
+        self.enter()?;
+
+        let result = if self.eat("-") {
+            self.parse_unary().map(|x| -x)
+        } else if self.eat("+") {
+            self.parse_unary()
+        } else if self.eat("!") {
+            self.parse_unary().map(|x| bool_to_f64(x == 0.0))
+        } else {
+            self.parse_power()
+        };
+
+        self.leave();
+        result
+    }
+
+    fn parse_power(&mut self) -> anyhow::Result<f64> {
+        let base = self.parse_primary()?;
+
+        if self.eat("^") {
+            // right associative: 2^3^2 = 2^(3^2)
+            let exponent = self.parse_unary()?;
+            Ok(base.powf(exponent))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn parse_primary(&mut self) -> anyhow::Result<f64> {
+        self.skip_whitespace();
+
+        match self.input.get(self.idx) {
+            Some(b'(') => {
+                self.idx += 1;
+                self.enter()?;
+                let value = self.parse_or()?;
+                self.leave();
+                ensure!(self.eat(")"), "Missing closing parenthesis");
+                Ok(value)
+            }
+            Some(b'0'..=b'9' | b'.') => self.parse_number(),
+            Some(b) if b.is_ascii_alphabetic() => {
+                let name = self.read_identifier();
+
+                if self.eat("(") {
+                    let mut args = Vec::new();
+
+                    if !self.eat(")") {
+                        loop {
+                            args.push(self.parse_or()?);
+
+                            if self.eat(")") {
+                                break;
+                            }
+                            ensure!(self.eat(","), "Expected , or ) in call to {name}");
+                        }
+                    }
+
+                    call_builtin(name, &args)
+                } else {
+                    constant(name)
+                }
+            }
+            Some(&b) => bail!(
+                "Unexpected character {} at position {}",
+                b as char,
+                self.idx
+            ),
+            None => bail!("Unexpected end of expression"),
+        }
+    }
+
+    fn read_identifier(&mut self) -> &'a str {
+        let start = self.idx;
+
+        while let Some(b) = self.input.get(self.idx) {
+            if !b.is_ascii_alphanumeric() {
+                break;
+            }
+            self.idx += 1;
+        }
+
+        // identifiers are always ascii, so this can't fail
+        std::str::from_utf8(&self.input[start..self.idx]).unwrap()
+    }
+
+    fn parse_number(&mut self) -> anyhow::Result<f64> {
+        let start = self.idx;
+
+        while let Some(b'0'..=b'9' | b'.') = self.input.get(self.idx) {
+            self.idx += 1;
+        }
+
+        // exponent notation, e.g. 1e5 or 2.5e-3
+        if let Some(b'e' | b'E') = self.input.get(self.idx) {
+            let mut end = self.idx + 1;
+            if let Some(b'+' | b'-') = self.input.get(end) {
+                end += 1;
+            }
+            if let Some(b'0'..=b'9') = self.input.get(end) {
+                self.idx = end;
+                while let Some(b'0'..=b'9') = self.input.get(self.idx) {
+                    self.idx += 1;
+                }
+            }
+        }
+
+        let number = std::str::from_utf8(&self.input[start..self.idx]).unwrap();
+        number
+            .parse()
+            .map_err(|_| anyhow!("Invalid number: {number}"))
+    }
+}
@@ -0,0 +1,38 @@
+/// Structured message output of a tag, in addition to its text output
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MessageOptions {
+    /// The embed to be responded with, if any embed subtag was used
+    pub embed: Option<Embed>,
+    /// How the response should reply to other messages
+    pub reply: ReplyMode,
+}
+
+/// How the response of a tag replies to other messages
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ReplyMode {
+    /// Reply to the invocation without pinging its author
+    #[default]
+    Silent,
+    /// Reply to the message with the given ID, or the invocation if `None`
+    ///
+    /// Only the author of the invocation is pinged, so that tags can't be used to ping arbitrary users.
+    Reply(Option<u64>),
+    /// Send a regular message instead of a reply
+    Disabled,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Embed {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub color: Option<u32>,
+    pub image: Option<String>,
+    pub fields: Vec<EmbedField>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbedField {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
@@ -1,11 +1,12 @@
-use crate::{context::Context, subtags};
+use crate::{context::Context, message::MessageOptions, subtags};
 use anyhow::{anyhow, bail, ensure, Context as _};
 use assyst_common::filetype::Type;
 use bytes::Bytes;
-use rand::prelude::ThreadRng;
+use rand::rngs::StdRng;
 use std::{
     cell::{Cell, RefCell},
     collections::HashMap,
+    fmt,
 };
 
 /// Constants and helper functions for tag parser limits
@@ -19,6 +20,14 @@ pub mod limits {
     pub const MAX_ITERATIONS: u32 = 500;
     pub const MAX_DEPTH: u32 = 15;
     pub const MAX_STRING_LENGTH: usize = 25000;
+    pub const MAX_REGEX_SIZE: usize = 1 << 20;
+    pub const MAX_REGEX_PATTERN_LENGTH: usize = 1000;
+    pub const MAX_EMBED_TITLE_LENGTH: usize = 256;
+    pub const MAX_EMBED_DESCRIPTION_LENGTH: usize = 4096;
+    pub const MAX_EMBED_FIELDS: usize = 25;
+    pub const MAX_EMBED_FIELD_NAME_LENGTH: usize = 256;
+    pub const MAX_EMBED_FIELD_VALUE_LENGTH: usize = 1024;
+    pub const MAX_FORMAT_DECIMALS: usize = 20;
 
     pub fn try_increment(field_cell: &Cell<u32>, limit: u32) -> bool {
         let field = field_cell.get();
@@ -42,6 +51,14 @@ pub struct SharedState<'a> {
     counter: &'a Counter,
     /// The attachment to be responded with, if set
     attachment: &'a RefCell<Option<(Bytes, Type)>>,
+    /// Structured message output, such as embeds
+    message: &'a RefCell<MessageOptions>,
+    /// Random number generator, seeded so that parsing is deterministic
+    rng: &'a RefCell<StdRng>,
+    /// The current time in milliseconds, fixed for the whole run so that parsing is deterministic
+    now: u64,
+    /// Trace of subtag invocations, if tracing is enabled
+    trace: Option<&'a RefCell<Vec<TraceEntry>>>,
 }
 
 impl<'a> SharedState<'a> {
@@ -49,14 +66,27 @@ impl<'a> SharedState<'a> {
         variables: &'a RefCell<HashMap<String, String>>,
         counter: &'a Counter,
         attachment: &'a RefCell<Option<(Bytes, Type)>>,
+        message: &'a RefCell<MessageOptions>,
+        rng: &'a RefCell<StdRng>,
+        now: u64,
     ) -> Self {
         Self {
             variables,
             counter,
             attachment,
+            message,
+            rng,
+            now,
+            trace: None,
         }
     }
 
+    /// Enables tracing, recording every subtag invocation into `trace`
+    pub fn with_trace(mut self, trace: &'a RefCell<Vec<TraceEntry>>) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
     /// Calls `f` with a mutable reference to the user defined variables
     pub fn with_variables_mut<F, T>(&self, f: F) -> T
     where
@@ -84,6 +114,131 @@ impl<'a> SharedState<'a> {
     pub fn set_attachment(&self, buf: Bytes, ty: Type) {
         *self.attachment.borrow_mut() = Some((buf, ty));
     }
+
+    /// Returns the current time in milliseconds
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Calls `f` with a mutable reference to the random number generator
+    pub fn with_rng<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut StdRng) -> T,
+    {
+        let mut rng = self.rng.borrow_mut();
+        f(&mut rng)
+    }
+
+    /// Calls `f` with a mutable reference to the structured message output
+    pub fn with_message_mut<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut MessageOptions) -> T,
+    {
+        let mut message = self.message.borrow_mut();
+        f(&mut message)
+    }
+
+    /// Records the start of a subtag invocation, returning the index of its trace entry
+    ///
+    /// Returns `None` if tracing is disabled, in which case the other trace methods do nothing.
+    pub fn trace_begin(&self, name: &str, depth: u32) -> Option<usize> {
+        let mut trace = self.trace?.borrow_mut();
+
+        trace.push(TraceEntry {
+            name: name.to_owned(),
+            args: None,
+            output: None,
+            depth,
+            // these hold the counter values at the start of the invocation until `trace_end` is called
+            requests: self.counter.requests.get(),
+            iterations: self.counter.iterations.get(),
+        });
+
+        Some(trace.len() - 1)
+    }
+
+    /// Records the evaluated arguments of a subtag invocation
+    pub fn trace_args(&self, entry: Option<usize>, args: &[String]) {
+        if let (Some(trace), Some(entry)) = (self.trace, entry) {
+            trace.borrow_mut()[entry].args = Some(args.to_vec());
+        }
+    }
+
+    /// Records the result of a subtag invocation
+    pub fn trace_end(&self, entry: Option<usize>, result: &anyhow::Result<String>) {
+        if let (Some(trace), Some(entry)) = (self.trace, entry) {
+            let mut trace = trace.borrow_mut();
+            let entry = &mut trace[entry];
+
+            entry.requests = self.counter.requests.get() - entry.requests;
+            entry.iterations = self.counter.iterations.get() - entry.iterations;
+            entry.output = Some(match result {
+                Ok(output) => Ok(output.clone()),
+                Err(e) => Err(format!("{e:#}")),
+            });
+        }
+    }
+}
+
+/// A single recorded subtag invocation
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    /// Name of the subtag
+    pub name: String,
+    /// Evaluated arguments, or `None` for lazy subtags which evaluate their own arguments
+    pub args: Option<Vec<String>>,
+    /// Output or error message of the subtag, or `None` if it never finished
+    pub output: Option<Result<String, String>>,
+    /// Recursive depth of the parser that invoked the subtag
+    pub depth: u32,
+    /// Number of requests used by this invocation, including nested subtags
+    pub requests: u32,
+    /// Number of parser iterations used by this invocation, including nested subtags
+    pub iterations: u32,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Maximum number of characters of an argument or output to display
+        const MAX_VALUE_LENGTH: usize = 200;
+
+        fn truncate(value: &str) -> String {
+            if value.chars().count() > MAX_VALUE_LENGTH {
+                let truncated = value.chars().take(MAX_VALUE_LENGTH).collect::<String>();
+                format!("{truncated:?}...")
+            } else {
+                format!("{value:?}")
+            }
+        }
+
+        write!(
+            f,
+            "{:indent$}{{{}}}",
+            "",
+            self.name,
+            indent = self.depth as usize * 2
+        )?;
+
+        match &self.args {
+            Some(args) => {
+                let args = args.iter().map(|a| truncate(a)).collect::<Vec<_>>();
+                write!(f, " args: [{}]", args.join(", "))?;
+            }
+            None => write!(f, " (lazy)")?,
+        }
+
+        match &self.output {
+            Some(Ok(output)) => write!(f, " -> {}", truncate(output))?,
+            Some(Err(e)) => write!(f, " -> error: {e}")?,
+            None => write!(f, " -> did not finish")?,
+        }
+
+        write!(
+            f,
+            " (depth: {}, requests: {}, iterations: {})",
+            self.depth, self.requests, self.iterations
+        )
+    }
 }
 
 /// Counter for various limits
@@ -117,16 +272,16 @@ pub struct Parser<'a> {
     idx: usize,
     /// Shared parser state across multiple parsers
     state: SharedState<'a>,
-    /// A cached `ThreadRng`, used to generate random numbers
-    rng: ThreadRng,
     /// Context for this parser
     cx: &'a dyn Context,
     /// Recursive depth, to avoid stack overflow in {eval} calls
     depth: u32,
+    /// Name of the tag that is being parsed, if any
+    tag: Option<&'a str>,
 }
 
 /// Checks if a given byte is in the a..z A..Z range
-fn is_identifier(b: u8) -> bool {
+pub fn is_identifier(b: u8) -> bool {
     (b'a'..=b'z').contains(&b) || (b'A'..=b'Z').contains(&b)
 }
 
@@ -148,9 +303,9 @@ impl<'a> Parser<'a> {
             args,
             idx: 0,
             state: other.state.clone(),
-            rng: rand::thread_rng(),
             cx: other.cx,
             depth: other.depth + 1,
+            tag: other.tag,
         }
     }
 
@@ -167,11 +322,19 @@ impl<'a> Parser<'a> {
             cx,
             idx: 0,
             state,
-            rng: rand::thread_rng(),
             depth: 0,
+            tag: None,
         }
     }
 
+    /// Sets the name of the tag that is being parsed
+    ///
+    /// This is used to scope persistent tag variables to the tag they were set in
+    pub fn with_tag_name(mut self, tag: &'a str) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
     /// Reads bytes from input until the first non-identifier byte is found, increasing the internal index on
     pub fn read_identifier(&mut self) -> &'a [u8] {
         let start = self.idx;
@@ -200,6 +363,18 @@ impl<'a> Parser<'a> {
         false
     }
 
+    /// Returns the current index in the input string
+    pub fn position(&self) -> usize {
+        self.idx
+    }
+
+    /// Moves the parser back (or forward) to the given index
+    ///
+    /// This is used by loop subtags to re-evaluate the same segment multiple times
+    pub fn seek(&mut self, idx: usize) {
+        self.idx = idx;
+    }
+
     /// Eats a separator
     pub fn eat_separator(&mut self) -> bool {
         self.eat(b":|")
@@ -268,9 +443,12 @@ impl<'a> Parser<'a> {
                         bail!("Subtag name is empty.");
                     }
 
+                    let trace_entry = self.state.trace_begin(name, self.depth);
+
                     // lazy tags need to be evaluated before the args are parsed
                     // see comment in `handle_lazy_tag` for what it means for a tag to be lazy
                     if let Some(re) = self.handle_lazy_tag(name) {
+                        self.state.trace_end(trace_entry, &re);
                         output.append(&mut re?.into_bytes());
                         continue;
                     }
@@ -288,7 +466,11 @@ impl<'a> Parser<'a> {
                     self.idx += 1;
 
                     let result = if side_effects {
-                        self.handle_tag(name, args)
+                        self.state.trace_args(trace_entry, &args);
+                        let result = self.handle_tag(name, args);
+                        self.state.trace_end(trace_entry, &result);
+
+                        result
                             .with_context(|| format!("An error occurred while processing {name}"))?
                     } else {
                         String::new()
@@ -332,52 +514,16 @@ impl<'a> Parser<'a> {
     /// This is needed for special subtags like if, which needs to decide whether to parse `then` or else`
     /// only after it compared two arguments
     pub fn handle_lazy_tag(&mut self, name: &str) -> Option<anyhow::Result<String>> {
-        match name {
-            "if" => Some(subtags::r#if(self)),
-            "note" => Some(subtags::note(self)),
-            "ignore" => Some(subtags::ignore(self)),
-            _ => None,
+        match find_subtag(name)?.kind {
+            SubtagKind::Lazy(handler) | SubtagKind::Unevaluated(handler) => Some(handler(self)),
+            SubtagKind::Regular(_) => None,
         }
     }
 
     /// Handles a regular tag
     pub fn handle_tag(&mut self, name: &str, args: Vec<String>) -> anyhow::Result<String> {
-        match name {
-            "repeat" => subtags::repeat(args),
-            "range" => subtags::range(self, args),
-            "eval" => subtags::eval(self, &args),
-            "tryarg" => subtags::tryarg(self, args),
-            "arg" => subtags::arg(self, args),
-            "args" => subtags::args(self),
-            "set" => subtags::set(self, args),
-            "get" => subtags::get(self, args),
-            "delete" => subtags::delete(self, args),
-            "argslen" => subtags::argslen(self),
-            "abs" => subtags::abs(args),
-            "cos" => subtags::cos(args),
-            "sin" => subtags::sin(args),
-            "tan" => subtags::tan(args),
-            "sqrt" => subtags::sqrt(args),
-            "e" => subtags::e(),
-            "pi" => subtags::pi(),
-            "max" => subtags::max(args),
-            "min" => subtags::min(args),
-            "choose" => subtags::choose(self, args),
-            "length" => subtags::length(args),
-            "lower" => subtags::lower(args),
-            "upper" => subtags::upper(args),
-            "replace" => subtags::replace(args),
-            "reverse" => subtags::reverse(args),
-            "channelid" => subtags::channelid(self),
-            "usertag" => subtags::usertag(self, args),
-            "js" | "javascript" => subtags::javascript(self, args),
-            "lastattachment" => subtags::attachment_last(self),
-            "avatar" => subtags::avatar(self, args),
-            "download" => subtags::download(self, args),
-            "mention" => subtags::mention(self, args),
-            "idof" => subtags::idof(self, args),
-            "userid" => subtags::userid(self),
-            "tag" => subtags::tag(self, args),
+        match find_subtag(name).map(|s| &s.kind) {
+            Some(SubtagKind::Regular(handler)) => handler(self, args),
             _ => Err(anyhow!("Unknown subtag: {name}")),
         }
     }
@@ -386,10 +532,6 @@ impl<'a> Parser<'a> {
         self.args
     }
 
-    pub fn rng(&mut self) -> &mut ThreadRng {
-        &mut self.rng
-    }
-
     pub fn state(&self) -> &SharedState<'a> {
         &self.state
     }
@@ -398,7 +540,173 @@ impl<'a> Parser<'a> {
         self.depth
     }
 
+    pub fn tag_name(&self) -> Option<&'a str> {
+        self.tag
+    }
+
     pub fn context(&self) -> &dyn Context {
         &*self.cx
     }
 }
+
+/// Handler of a regular subtag, which receives its evaluated arguments
+type Handler = fn(&mut Parser, Vec<String>) -> anyhow::Result<String>;
+/// Handler of a lazy subtag, which parses its own arguments
+type LazyHandler = fn(&mut Parser) -> anyhow::Result<String>;
+
+enum SubtagKind {
+    Regular(Handler),
+    Lazy(LazyHandler),
+    /// A lazy subtag that skips its arguments without evaluating them
+    Unevaluated(LazyHandler),
+}
+
+/// A subtag known to the parser
+pub struct Subtag {
+    pub name: &'static str,
+    /// Minimum number of arguments
+    pub min_args: usize,
+    /// Maximum number of arguments, if there is one
+    pub max_args: Option<usize>,
+    kind: SubtagKind,
+}
+
+impl Subtag {
+    const fn regular(
+        name: &'static str,
+        min_args: usize,
+        max_args: Option<usize>,
+        handler: Handler,
+    ) -> Self {
+        Self {
+            name,
+            min_args,
+            max_args,
+            kind: SubtagKind::Regular(handler),
+        }
+    }
+
+    const fn lazy(
+        name: &'static str,
+        min_args: usize,
+        max_args: Option<usize>,
+        handler: LazyHandler,
+    ) -> Self {
+        Self {
+            name,
+            min_args,
+            max_args,
+            kind: SubtagKind::Lazy(handler),
+        }
+    }
+
+    const fn unevaluated(
+        name: &'static str,
+        min_args: usize,
+        max_args: Option<usize>,
+        handler: LazyHandler,
+    ) -> Self {
+        Self {
+            name,
+            min_args,
+            max_args,
+            kind: SubtagKind::Unevaluated(handler),
+        }
+    }
+
+    /// Whether the arguments of this subtag are skipped instead of evaluated, like in {note}
+    pub fn skips_args(&self) -> bool {
+        matches!(self.kind, SubtagKind::Unevaluated(_))
+    }
+}
+
+/// Every subtag, along with the number of arguments it accepts
+///
+/// The argument counts are only used to validate tag source, every handler checks its own arguments.
+#[rustfmt::skip]
+const SUBTAGS: &[Subtag] = &[
+    Subtag::lazy("if", 5, Some(5), subtags::r#if),
+    Subtag::unevaluated("note", 0, Some(1), subtags::note),
+    Subtag::unevaluated("ignore", 0, Some(1), subtags::ignore),
+    Subtag::lazy("for", 4, Some(4), subtags::r#for),
+    Subtag::lazy("foreach", 3, Some(3), subtags::foreach),
+    Subtag::lazy("while", 2, Some(2), subtags::r#while),
+    Subtag::regular("repeat", 2, Some(2), |_, args| subtags::repeat(args)),
+    Subtag::regular("range", 2, Some(2), subtags::range),
+    Subtag::regular("eval", 1, Some(1), |p, args| subtags::eval(p, &args)),
+    Subtag::regular("tryarg", 1, Some(1), |p, args| subtags::tryarg(p, args)),
+    Subtag::regular("arg", 1, Some(1), |p, args| subtags::arg(p, args)),
+    Subtag::regular("args", 0, Some(0), |p, _| subtags::args(p)),
+    Subtag::regular("set", 2, Some(2), subtags::set),
+    Subtag::regular("get", 1, Some(1), |p, args| subtags::get(p, args)),
+    Subtag::regular("delete", 1, Some(1), subtags::delete),
+    Subtag::regular("gset", 2, Some(2), |p, args| subtags::gset(p, args)),
+    Subtag::regular("gget", 1, Some(1), |p, args| subtags::gget(p, args)),
+    Subtag::regular("gdelete", 1, Some(1), |p, args| subtags::gdelete(p, args)),
+    Subtag::regular("tagset", 2, Some(2), |p, args| subtags::tagset(p, args)),
+    Subtag::regular("tagget", 1, Some(1), |p, args| subtags::tagget(p, args)),
+    Subtag::regular("tagdelete", 1, Some(1), |p, args| subtags::tagdelete(p, args)),
+    Subtag::regular("argslen", 0, Some(0), |p, _| subtags::argslen(p)),
+    Subtag::regular("abs", 1, Some(1), |_, args| subtags::abs(args)),
+    Subtag::regular("cos", 1, Some(1), |_, args| subtags::cos(args)),
+    Subtag::regular("sin", 1, Some(1), |_, args| subtags::sin(args)),
+    Subtag::regular("tan", 1, Some(1), |_, args| subtags::tan(args)),
+    Subtag::regular("sqrt", 1, Some(1), |_, args| subtags::sqrt(args)),
+    Subtag::regular("e", 0, Some(0), |_, _| subtags::e()),
+    Subtag::regular("pi", 0, Some(0), |_, _| subtags::pi()),
+    Subtag::regular("max", 1, None, |_, args| subtags::max(args)),
+    Subtag::regular("min", 1, None, |_, args| subtags::min(args)),
+    Subtag::regular("math", 1, None, |_, args| subtags::math(args)),
+    Subtag::regular("choose", 1, None, subtags::choose),
+    Subtag::regular("length", 1, Some(1), |_, args| subtags::length(args)),
+    Subtag::regular("lower", 1, Some(1), |_, args| subtags::lower(args)),
+    Subtag::regular("upper", 1, Some(1), |_, args| subtags::upper(args)),
+    Subtag::regular("replace", 3, Some(3), |_, args| subtags::replace(args)),
+    Subtag::regular("reverse", 1, Some(1), |_, args| subtags::reverse(args)),
+    Subtag::regular("regex", 2, Some(3), |_, args| subtags::regex(args)),
+    Subtag::regular("regexreplace", 3, Some(3), |_, args| subtags::regexreplace(args)),
+    Subtag::regular("split", 2, Some(3), |_, args| subtags::split(args)),
+    Subtag::regular("substring", 3, Some(3), |_, args| subtags::substring(args)),
+    Subtag::regular("trim", 1, Some(1), |_, args| subtags::trim(args)),
+    Subtag::regular("urlencode", 1, Some(1), |_, args| subtags::urlencode(args)),
+    Subtag::regular("jsonget", 2, Some(2), |_, args| subtags::jsonget(args)),
+    Subtag::regular("now", 0, Some(0), |p, _| subtags::now(p)),
+    Subtag::regular("timestamp", 1, Some(2), |p, args| subtags::timestamp(p, args)),
+    Subtag::regular("dateadd", 1, Some(2), |p, args| subtags::dateadd(p, args)),
+    Subtag::regular("discordtime", 0, Some(2), |p, args| subtags::discordtime(p, args)),
+    Subtag::regular("duration", 1, Some(1), |_, args| subtags::duration(args)),
+    Subtag::regular("round", 1, Some(2), |_, args| subtags::round(args)),
+    Subtag::regular("floor", 1, Some(1), |_, args| subtags::floor(args)),
+    Subtag::regular("ceil", 1, Some(1), |_, args| subtags::ceil(args)),
+    Subtag::regular("format", 2, Some(2), |_, args| subtags::format(args)),
+    Subtag::regular("embed", 1, Some(2), |p, args| subtags::embed(p, args)),
+    Subtag::regular("embedfield", 2, Some(3), |p, args| subtags::embedfield(p, args)),
+    Subtag::regular("embedcolor", 1, Some(1), |p, args| subtags::embedcolor(p, args)),
+    Subtag::regular("embedimage", 1, Some(1), |p, args| subtags::embedimage(p, args)),
+    Subtag::regular("reply", 0, Some(1), |p, args| subtags::reply(p, args)),
+    Subtag::regular("nomention", 0, Some(0), |p, _| subtags::nomention(p)),
+    Subtag::regular("channelid", 0, Some(0), |p, _| subtags::channelid(p)),
+    Subtag::regular("usertag", 0, Some(1), |p, args| subtags::usertag(p, args)),
+    Subtag::regular("js", 1, Some(1), subtags::javascript),
+    Subtag::regular("javascript", 1, Some(1), subtags::javascript),
+    Subtag::regular("lastattachment", 0, Some(0), |p, _| subtags::attachment_last(p)),
+    Subtag::regular("avatar", 0, Some(1), subtags::avatar),
+    Subtag::regular("download", 1, Some(1), subtags::download),
+    Subtag::regular("mention", 0, Some(1), |p, args| subtags::mention(p, args)),
+    Subtag::regular("idof", 1, Some(1), |p, args| subtags::idof(p, args)),
+    Subtag::regular("userid", 0, Some(0), |p, _| subtags::userid(p)),
+    Subtag::regular("guildid", 0, Some(0), |p, _| subtags::guildid(p)),
+    Subtag::regular("guildname", 0, Some(0), |p, _| subtags::guildname(p)),
+    Subtag::regular("membercount", 0, Some(0), |p, _| subtags::membercount(p)),
+    Subtag::regular("channelname", 0, Some(1), |p, args| subtags::channelname(p, args)),
+    Subtag::regular("username", 0, Some(1), |p, args| subtags::username(p, args)),
+    Subtag::regular("nickname", 0, Some(1), |p, args| subtags::nickname(p, args)),
+    Subtag::regular("roles", 0, Some(1), |p, args| subtags::roles(p, args)),
+    Subtag::regular("hasrole", 2, Some(2), |p, args| subtags::hasrole(p, args)),
+    Subtag::regular("tag", 1, None, subtags::tag),
+];
+
+/// Looks up a subtag by its name
+pub fn find_subtag(name: &str) -> Option<&'static Subtag> {
+    SUBTAGS.iter().find(|s| s.name == name)
+}
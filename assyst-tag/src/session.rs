@@ -0,0 +1,346 @@
+use crate::{
+    context::{Context, Environment, Guild, Member, Tag},
+    parse_inner,
+    parser::TraceEntry,
+    ParseResult,
+};
+use anyhow::{anyhow, bail, ensure};
+use assyst_common::{eval::FakeEvalImageResponse, util::get_current_millis};
+use std::cell::{Cell, RefCell};
+
+/// An I/O operation that a tag needs the result of before it can continue
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// Execute JavaScript code, see [`Context::execute_javascript`]
+    ExecuteJavascript { code: String, args: Vec<String> },
+    /// See [`Context::get_last_attachment`]
+    GetLastAttachment,
+    /// See [`Context::get_avatar`]
+    GetAvatar(Option<u64>),
+    /// See [`Context::download`]
+    Download(String),
+    /// See [`Context::user_tag`]
+    UserTag(Option<u64>),
+    /// See [`Context::get_tag`]
+    GetTag(String),
+    /// See [`Context::get_guild`]
+    GetGuild,
+    /// See [`Context::get_channel_name`]
+    GetChannelName(Option<u64>),
+    /// See [`Context::get_member`]
+    GetMember(Option<u64>),
+    /// See [`Context::get_persistent_variable`]
+    GetPersistentVariable { tag: Option<String>, key: String },
+    /// See [`Context::set_persistent_variable`]
+    SetPersistentVariable {
+        tag: Option<String>,
+        key: String,
+        value: String,
+    },
+    /// See [`Context::delete_persistent_variable`]
+    DeletePersistentVariable { tag: Option<String>, key: String },
+}
+
+/// The result of a fulfilled [`Request`]
+#[derive(Clone)]
+pub enum Response {
+    /// Response to [`Request::ExecuteJavascript`]
+    Javascript(FakeEvalImageResponse),
+    /// Response to requests that return a string
+    Text(String),
+    /// Response to [`Request::GetPersistentVariable`]
+    Variable(Option<String>),
+    /// Response to [`Request::GetTag`]
+    Tag(Tag),
+    /// Response to [`Request::GetGuild`]
+    Guild(Guild),
+    /// Response to [`Request::GetMember`]
+    Member(Member),
+    /// Response to requests that don't return anything
+    Done,
+}
+
+/// The outcome of running a [`Session`]
+pub enum Step {
+    /// The tag needs the response to this request, which is to be passed to [`Session::resume`]
+    Pending(Request),
+    /// The tag finished running
+    Done(anyhow::Result<ParseResult>),
+}
+
+/// A tag execution that can be suspended while waiting for I/O
+///
+/// Rather than blocking in [`Context`] methods, the tag is run until it needs the result of a [`Request`].
+/// The caller then fulfills the request, possibly asynchronously, and passes the result to [`Session::resume`].
+///
+/// The next call to [`Session::step`] runs the tag again from the start, replaying the responses of all previous
+/// requests, until it reaches a new request or finishes. Parsing is deterministic given the same responses
+/// (random numbers are seeded and the current time is fixed per session), so the replayed run always takes the same path.
+/// The number of requests and iterations is limited, so replaying is cheap compared to the I/O itself.
+pub struct Session {
+    name: Option<String>,
+    input: String,
+    args: Vec<String>,
+    seed: u64,
+    /// The time at which the session was created, which is used as the current time in every step
+    now: u64,
+    /// Requests made so far, along with their responses
+    journal: Vec<(Request, Result<Response, String>)>,
+    /// The request that the last step is waiting for
+    pending: Option<Request>,
+    /// Trace of the last step, if tracing is enabled
+    trace: Option<Vec<TraceEntry>>,
+}
+
+impl Session {
+    pub fn new(input: impl Into<String>, args: Vec<String>) -> Self {
+        Self {
+            name: None,
+            input: input.into(),
+            args,
+            seed: rand::random(),
+            now: get_current_millis(),
+            journal: Vec::new(),
+            pending: None,
+            trace: None,
+        }
+    }
+
+    /// Sets the name of the tag that is being run, see [`crate::parse_tag`]
+    pub fn with_tag_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Enables tracing, see [`crate::parse_tag_traced`]
+    pub fn with_trace(mut self) -> Self {
+        self.trace = Some(Vec::new());
+        self
+    }
+
+    /// Sets the seed for random number generation
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Runs the tag until it either finishes or needs the result of a new request
+    pub fn step(&mut self, env: &dyn Environment) -> Step {
+        if self.pending.is_some() {
+            return Step::Done(Err(anyhow!(
+                "Tag was resumed before its pending request was fulfilled"
+            )));
+        }
+
+        let cx = ReplayContext {
+            env,
+            journal: &self.journal,
+            position: Cell::new(0),
+            pending: RefCell::new(None),
+        };
+        let trace = self.trace.is_some().then(|| RefCell::new(Vec::new()));
+        let args = self.args.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let result = parse_inner(
+            self.name.as_deref(),
+            &self.input,
+            &args,
+            &cx as &dyn Context,
+            self.seed,
+            self.now,
+            trace.as_ref(),
+        );
+
+        if let Some(trace) = trace {
+            self.trace = Some(trace.into_inner());
+        }
+
+        // the error returned by the parser when it hits a new request is irrelevant
+        match cx.pending.into_inner() {
+            Some(request) => {
+                self.pending = Some(request.clone());
+                Step::Pending(request)
+            }
+            None => Step::Done(result),
+        }
+    }
+
+    /// Provides the result of the pending request, so that the next step can continue past it
+    ///
+    /// # Panics
+    /// Panics if there is no pending request
+    pub fn resume(&mut self, response: anyhow::Result<Response>) {
+        let request = self
+            .pending
+            .take()
+            .expect("resume called without a pending request");
+
+        self.journal
+            .push((request, response.map_err(|e| format!("{e:#}"))));
+    }
+
+    /// Returns the trace of the last step, or an empty slice if tracing is disabled
+    pub fn trace(&self) -> &[TraceEntry] {
+        self.trace.as_deref().unwrap_or_default()
+    }
+}
+
+/// A [`Context`] that answers requests from a journal of previous responses,
+/// and records the first request that it has no response for
+struct ReplayContext<'a> {
+    env: &'a dyn Environment,
+    journal: &'a [(Request, Result<Response, String>)],
+    /// Index of the next journal entry
+    position: Cell<usize>,
+    pending: RefCell<Option<Request>>,
+}
+
+impl ReplayContext<'_> {
+    fn replay(&self, request: Request) -> anyhow::Result<Response> {
+        ensure!(
+            self.pending.borrow().is_none(),
+            "Tag execution is suspended"
+        );
+
+        let position = self.position.get();
+
+        match self.journal.get(position) {
+            Some((recorded, response)) => {
+                ensure!(
+                    *recorded == request,
+                    "Tag execution diverged from a previous run"
+                );
+
+                self.position.set(position + 1);
+                response.clone().map_err(|e| anyhow!(e))
+            }
+            None => {
+                *self.pending.borrow_mut() = Some(request);
+                bail!("Tag execution is suspended")
+            }
+        }
+    }
+
+    fn replay_text(&self, request: Request) -> anyhow::Result<String> {
+        match self.replay(request)? {
+            Response::Text(text) => Ok(text),
+            _ => bail!("Unexpected response type"),
+        }
+    }
+
+    fn replay_done(&self, request: Request) -> anyhow::Result<()> {
+        match self.replay(request)? {
+            Response::Done => Ok(()),
+            _ => bail!("Unexpected response type"),
+        }
+    }
+}
+
+impl Environment for ReplayContext<'_> {
+    fn channel_id(&self) -> anyhow::Result<u64> {
+        self.env.channel_id()
+    }
+
+    fn guild_id(&self) -> anyhow::Result<u64> {
+        self.env.guild_id()
+    }
+
+    fn user_id(&self) -> anyhow::Result<u64> {
+        self.env.user_id()
+    }
+}
+
+impl Context for ReplayContext<'_> {
+    fn execute_javascript(
+        &self,
+        code: &str,
+        args: Vec<String>,
+    ) -> anyhow::Result<FakeEvalImageResponse> {
+        let request = Request::ExecuteJavascript {
+            code: code.to_owned(),
+            args,
+        };
+
+        match self.replay(request)? {
+            Response::Javascript(response) => Ok(response),
+            _ => bail!("Unexpected response type"),
+        }
+    }
+
+    fn get_last_attachment(&self) -> anyhow::Result<String> {
+        self.replay_text(Request::GetLastAttachment)
+    }
+
+    fn get_avatar(&self, user_id: Option<u64>) -> anyhow::Result<String> {
+        self.replay_text(Request::GetAvatar(user_id))
+    }
+
+    fn download(&self, url: &str) -> anyhow::Result<String> {
+        self.replay_text(Request::Download(url.to_owned()))
+    }
+
+    fn user_tag(&self, id: Option<u64>) -> anyhow::Result<String> {
+        self.replay_text(Request::UserTag(id))
+    }
+
+    fn get_tag(&self, name: &str) -> anyhow::Result<Tag> {
+        match self.replay(Request::GetTag(name.to_owned()))? {
+            Response::Tag(tag) => Ok(tag),
+            _ => bail!("Unexpected response type"),
+        }
+    }
+
+    fn get_guild(&self) -> anyhow::Result<Guild> {
+        match self.replay(Request::GetGuild)? {
+            Response::Guild(guild) => Ok(guild),
+            _ => bail!("Unexpected response type"),
+        }
+    }
+
+    fn get_channel_name(&self, channel_id: Option<u64>) -> anyhow::Result<String> {
+        self.replay_text(Request::GetChannelName(channel_id))
+    }
+
+    fn get_member(&self, user_id: Option<u64>) -> anyhow::Result<Member> {
+        match self.replay(Request::GetMember(user_id))? {
+            Response::Member(member) => Ok(member),
+            _ => bail!("Unexpected response type"),
+        }
+    }
+
+    fn get_persistent_variable(
+        &self,
+        tag: Option<&str>,
+        key: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let request = Request::GetPersistentVariable {
+            tag: tag.map(String::from),
+            key: key.to_owned(),
+        };
+
+        match self.replay(request)? {
+            Response::Variable(value) => Ok(value),
+            _ => bail!("Unexpected response type"),
+        }
+    }
+
+    fn set_persistent_variable(
+        &self,
+        tag: Option<&str>,
+        key: &str,
+        value: &str,
+    ) -> anyhow::Result<()> {
+        self.replay_done(Request::SetPersistentVariable {
+            tag: tag.map(String::from),
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+
+    fn delete_persistent_variable(&self, tag: Option<&str>, key: &str) -> anyhow::Result<()> {
+        self.replay_done(Request::DeletePersistentVariable {
+            tag: tag.map(String::from),
+            key: key.to_owned(),
+        })
+    }
+}
@@ -1,10 +1,20 @@
-use crate::parser::{
-    limits::{self, MAX_DEPTH, MAX_STRING_LENGTH},
-    Parser,
+use crate::{
+    context::Member,
+    message::{Embed, EmbedField, ReplyMode},
+    parser::{
+        limits::{self, MAX_DEPTH, MAX_STRING_LENGTH},
+        Parser,
+    },
 };
 use anyhow::{anyhow, bail, Context};
-use assyst_common::eval::FakeEvalImageResponse;
+use assyst_common::{
+    eval::FakeEvalImageResponse,
+    util::{format_time, parse_to_millis},
+};
+use chrono::DateTime;
 use rand::Rng;
+use regex::{Regex, RegexBuilder};
+use std::fmt::Write;
 
 use anyhow::ensure;
 
@@ -21,6 +31,24 @@ macro_rules! ensure_request_limit {
     };
 }
 
+/// Compiles a user provided regex, with limits on how large the compiled program can get
+///
+/// The regex engine does not backtrack, so matching is linear in the input length,
+/// which is itself bounded by `MAX_STRING_LENGTH`.
+fn build_regex(pattern: &str) -> anyhow::Result<Regex> {
+    ensure!(
+        pattern.len() <= limits::MAX_REGEX_PATTERN_LENGTH,
+        "Regex pattern exceeds maximum length of {}",
+        limits::MAX_REGEX_PATTERN_LENGTH
+    );
+
+    RegexBuilder::new(pattern)
+        .size_limit(limits::MAX_REGEX_SIZE)
+        .dfa_size_limit(limits::MAX_REGEX_SIZE)
+        .build()
+        .map_err(Into::into)
+}
+
 fn try_eat_closing_brace(parser: &mut Parser) -> anyhow::Result<()> {
     ensure!(parser.eat(&[b'}']), "Missing closing brace");
     Ok(())
@@ -39,7 +67,7 @@ pub fn repeat(args: Vec<String>) -> anyhow::Result<String> {
 pub fn range(parser: &mut Parser, args: Vec<String>) -> anyhow::Result<String> {
     let lower = args.first().context("Missing lower bound")?.parse()?;
     let upper = args.get(1).context("Missing upper bound")?.parse()?;
-    let out: usize = parser.rng().gen_range(lower..=upper);
+    let out: usize = parser.state().with_rng(|rng| rng.gen_range(lower..=upper));
 
     Ok(out.to_string())
 }
@@ -84,20 +112,26 @@ pub fn args(parser: &Parser) -> anyhow::Result<String> {
     Ok(args.join(" "))
 }
 
+/// Sets a user defined variable, respecting the variable limits
 #[rustfmt::skip]
+fn set_variable(parser: &Parser, key: String, value: String) -> anyhow::Result<()> {
+    parser.state().with_variables_mut(move |variables| -> anyhow::Result<()> {
+        ensure!(variables.len() < limits::MAX_VARIABLES || variables.contains_key(&key), "Maximum number of variables reached");
+        ensure!(key.len() < limits::MAX_VARIABLE_KEY_LENGTH, "Key exceeds maximum length of {}", limits::MAX_VARIABLE_KEY_LENGTH);
+        ensure!(value.len() < limits::MAX_VARIABLE_VALUE_LENGTH, "Value exceeds maximum length of {}", limits::MAX_VARIABLE_VALUE_LENGTH);
+
+        variables.insert(key, value);
+        Ok(())
+    })
+}
+
 pub fn set(parser: &mut Parser, args: Vec<String>) -> anyhow::Result<String> {
     let mut iter = args.into_iter();
     let key = iter.next().context("Missing key argument")?;
     let value = iter.next().context("Missing value argument")?;
 
-    parser.state().with_variables_mut(move |variables| -> anyhow::Result<String> {
-        ensure!(variables.len() < limits::MAX_VARIABLES, "Maximum number of variables reached");
-        ensure!(key.len() < limits::MAX_VARIABLE_KEY_LENGTH, "Key exceeds maximum length of {}", limits::MAX_VARIABLE_KEY_LENGTH);
-        ensure!(value.len() < limits::MAX_VARIABLE_VALUE_LENGTH, "Value exceeds maximum length of {}", limits::MAX_VARIABLE_VALUE_LENGTH);
-        
-        variables.insert(key.into(), value.into());
-        Ok(String::new())
-    })
+    set_variable(parser, key, value)?;
+    Ok(String::new())
 }
 
 pub fn get(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
@@ -121,6 +155,79 @@ pub fn delete(parser: &mut Parser, args: Vec<String>) -> anyhow::Result<String>
     Ok(String::new())
 }
 
+/// Returns the name of the current tag, which is needed for tag-scoped persistent variables
+fn current_tag<'a>(parser: &Parser<'a>) -> anyhow::Result<&'a str> {
+    parser
+        .tag_name()
+        .context("Tag variables can only be used inside of a tag")
+}
+
+#[rustfmt::skip]
+fn set_persistent_variable(parser: &Parser, tag: Option<&str>, args: Vec<String>) -> anyhow::Result<String> {
+    ensure_request_limit!(parser);
+
+    let key = args.first().context("Missing key argument")?;
+    let value = args.get(1).context("Missing value argument")?;
+
+    ensure!(key.len() < limits::MAX_VARIABLE_KEY_LENGTH, "Key exceeds maximum length of {}", limits::MAX_VARIABLE_KEY_LENGTH);
+    ensure!(value.len() < limits::MAX_VARIABLE_VALUE_LENGTH, "Value exceeds maximum length of {}", limits::MAX_VARIABLE_VALUE_LENGTH);
+
+    parser.context().set_persistent_variable(tag, key, value)?;
+    Ok(String::new())
+}
+
+fn get_persistent_variable(
+    parser: &Parser,
+    tag: Option<&str>,
+    args: Vec<String>,
+) -> anyhow::Result<String> {
+    ensure_request_limit!(parser);
+
+    let key = args.first().context("Missing key argument")?;
+
+    Ok(parser
+        .context()
+        .get_persistent_variable(tag, key)?
+        .unwrap_or_default())
+}
+
+fn delete_persistent_variable(
+    parser: &Parser,
+    tag: Option<&str>,
+    args: Vec<String>,
+) -> anyhow::Result<String> {
+    ensure_request_limit!(parser);
+
+    let key = args.first().context("Missing key argument")?;
+
+    parser.context().delete_persistent_variable(tag, key)?;
+    Ok(String::new())
+}
+
+pub fn gset(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    set_persistent_variable(parser, None, args)
+}
+
+pub fn gget(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    get_persistent_variable(parser, None, args)
+}
+
+pub fn gdelete(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    delete_persistent_variable(parser, None, args)
+}
+
+pub fn tagset(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    set_persistent_variable(parser, Some(current_tag(parser)?), args)
+}
+
+pub fn tagget(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    get_persistent_variable(parser, Some(current_tag(parser)?), args)
+}
+
+pub fn tagdelete(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    delete_persistent_variable(parser, Some(current_tag(parser)?), args)
+}
+
 pub fn argslen(parser: &Parser) -> anyhow::Result<String> {
     Ok(parser.args().len().to_string())
 }
@@ -186,12 +293,24 @@ pub fn min(args: Vec<String>) -> anyhow::Result<String> {
     Ok(i32::to_string(&min))
 }
 
+/// Evaluates a math expression
+///
+/// Arguments are joined back together, so that `||` can be used without escaping it
+pub fn math(args: Vec<String>) -> anyhow::Result<String> {
+    ensure!(!args.is_empty(), "Missing expression argument");
+
+    let result = crate::math::eval(&args.join("|"))?;
+    ensure!(result.is_finite(), "Result is not a finite number");
+
+    Ok(result.to_string())
+}
+
 pub fn choose(parser: &mut Parser, args: Vec<String>) -> anyhow::Result<String> {
     if args.is_empty() {
         bail!("Nothing to choose from!");
     }
 
-    let idx = parser.rng().gen_range(0..args.len());
+    let idx = parser.state().with_rng(|rng| rng.gen_range(0..args.len()));
     args.get(idx).cloned().context("No arguments present")
 }
 
@@ -228,6 +347,408 @@ pub fn reverse(args: Vec<String>) -> anyhow::Result<String> {
     Ok(String::from_utf8_lossy(&text).into_owned())
 }
 
+pub fn regex(args: Vec<String>) -> anyhow::Result<String> {
+    let pattern = args.first().context("Missing pattern argument")?;
+    let input = args.get(1).context("Missing input argument")?;
+    let group = args.get(2).map(|g| g.parse::<usize>()).transpose()?;
+
+    let regex = build_regex(pattern)?;
+
+    Ok(regex
+        .captures(input)
+        .and_then(|c| c.get(group.unwrap_or(0)))
+        .map(|m| m.as_str().to_owned())
+        .unwrap_or_default())
+}
+
+pub fn regexreplace(args: Vec<String>) -> anyhow::Result<String> {
+    let pattern = args.first().context("Missing pattern argument")?;
+    let with = args.get(1).context("Missing replacer")?;
+    let input = args.get(2).context("Missing input argument")?;
+
+    let regex = build_regex(pattern)?;
+
+    // the output is checked as it grows, since an empty pattern can multiply the input many times over
+    let mut output = String::new();
+    let mut last_end = 0;
+    for captures in regex.captures_iter(input) {
+        let matched = captures.get(0).unwrap();
+        output.push_str(&input[last_end..matched.start()]);
+        captures.expand(with, &mut output);
+        last_end = matched.end();
+
+        ensure!(
+            output.len() < MAX_STRING_LENGTH,
+            "String exceeds maximum length of {MAX_STRING_LENGTH} bytes"
+        );
+    }
+    output.push_str(&input[last_end..]);
+
+    ensure!(
+        output.len() < MAX_STRING_LENGTH,
+        "String exceeds maximum length of {MAX_STRING_LENGTH} bytes"
+    );
+
+    Ok(output)
+}
+
+pub fn split(args: Vec<String>) -> anyhow::Result<String> {
+    let separator = args.first().context("Missing separator argument")?;
+    let input = args.get(1).context("Missing input argument")?;
+
+    ensure!(!separator.is_empty(), "Separator cannot be empty");
+
+    match args.get(2) {
+        Some(index) => {
+            let index = index.parse::<usize>()?;
+            input
+                .split(separator.as_str())
+                .nth(index)
+                .map(String::from)
+                .with_context(|| format!("Index {index} is out of bounds"))
+        }
+        // without an index, return a comma separated list that {foreach} can iterate over
+        None => Ok(input
+            .split(separator.as_str())
+            .collect::<Vec<_>>()
+            .join(",")),
+    }
+}
+
+pub fn substring(args: Vec<String>) -> anyhow::Result<String> {
+    let start = args
+        .first()
+        .context("Missing start argument")?
+        .parse::<usize>()?;
+    let end = args
+        .get(1)
+        .context("Missing end argument")?
+        .parse::<usize>()?;
+    let input = args.get(2).context("Missing input argument")?;
+
+    ensure!(
+        start <= end,
+        "Start index must not be greater than end index"
+    );
+
+    Ok(input.chars().skip(start).take(end - start).collect())
+}
+
+pub fn trim(args: Vec<String>) -> anyhow::Result<String> {
+    let text = args.first().context("Missing text argument")?;
+    Ok(text.trim().to_owned())
+}
+
+pub fn urlencode(args: Vec<String>) -> anyhow::Result<String> {
+    let text = args.first().context("Missing text argument")?;
+    Ok(urlencoding::encode(text).into_owned())
+}
+
+pub fn jsonget(args: Vec<String>) -> anyhow::Result<String> {
+    let path = args.first().context("Missing path argument")?;
+    let json = args.get(1).context("Missing JSON argument")?;
+
+    let mut value = &serde_json::from_str::<serde_json::Value>(json)?;
+
+    for key in path.split('.').filter(|k| !k.is_empty()) {
+        value = match value {
+            serde_json::Value::Array(array) => key.parse::<usize>().ok().and_then(|i| array.get(i)),
+            serde_json::Value::Object(object) => object.get(key),
+            _ => None,
+        }
+        .with_context(|| format!("Path segment {key} not found"))?;
+    }
+
+    match value {
+        serde_json::Value::String(s) => Ok(s.clone()),
+        other => Ok(other.to_string()),
+    }
+}
+
+/// Parses an optional timestamp argument in milliseconds, defaulting to the current time
+fn timestamp_or_now(parser: &Parser, timestamp: Option<&String>) -> anyhow::Result<u64> {
+    match timestamp {
+        Some(timestamp) => timestamp
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid timestamp: {timestamp}")),
+        None => Ok(parser.state().now()),
+    }
+}
+
+/// Parses a number argument
+fn parse_number(number: Option<&String>) -> anyhow::Result<f64> {
+    let number = number.context("Missing number argument")?;
+
+    number
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid number: {number}"))
+}
+
+pub fn now(parser: &Parser) -> anyhow::Result<String> {
+    Ok(parser.state().now().to_string())
+}
+
+pub fn timestamp(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let format = args.first().context("Missing format argument")?;
+    let timestamp = timestamp_or_now(parser, args.get(1))?;
+
+    let datetime = i64::try_from(timestamp)
+        .ok()
+        .and_then(DateTime::from_timestamp_millis)
+        .context("Timestamp is out of range")?;
+
+    // chrono returns an error when formatting with an invalid format string, which would panic in `to_string`
+    let mut output = String::new();
+    write!(output, "{}", datetime.format(format))
+        .map_err(|_| anyhow!("Invalid format string: {format}"))?;
+
+    ensure!(
+        output.len() < MAX_STRING_LENGTH,
+        "Output string exceeds maximum string length of {MAX_STRING_LENGTH} bytes"
+    );
+
+    Ok(output)
+}
+
+pub fn dateadd(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let duration = args.first().context("Missing duration argument")?.trim();
+    let timestamp = timestamp_or_now(parser, args.get(1))?;
+
+    // a leading - subtracts the duration instead
+    let (subtract, duration) = match duration.strip_prefix('-') {
+        Some(duration) => (true, duration),
+        None => (false, duration),
+    };
+    let millis = parse_to_millis(duration)?;
+
+    let result = if subtract {
+        timestamp.checked_sub(millis)
+    } else {
+        timestamp.checked_add(millis)
+    };
+
+    result
+        .map(|t| t.to_string())
+        .context("Resulting timestamp is out of range")
+}
+
+pub fn discordtime(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let timestamp = timestamp_or_now(parser, args.first())?;
+    let style = args.get(1).map_or("f", |s| s.trim());
+
+    ensure!(
+        matches!(style, "t" | "T" | "d" | "D" | "f" | "F" | "R"),
+        "Invalid timestamp style: {style} (expected one of t, T, d, D, f, F, R)"
+    );
+
+    Ok(format!("<t:{}:{style}>", timestamp / 1000))
+}
+
+pub fn duration(args: Vec<String>) -> anyhow::Result<String> {
+    let millis = parse_number(args.first())?;
+    ensure!(
+        millis.is_finite() && millis >= 0.0,
+        "Duration must be a positive number of milliseconds"
+    );
+
+    Ok(format_time(millis as u64))
+}
+
+pub fn round(args: Vec<String>) -> anyhow::Result<String> {
+    let number = parse_number(args.first())?;
+
+    match args.get(1) {
+        Some(decimals) => {
+            let decimals = decimals.trim().parse::<usize>()?;
+            ensure!(
+                decimals <= limits::MAX_FORMAT_DECIMALS,
+                "Cannot round to more than {} decimals",
+                limits::MAX_FORMAT_DECIMALS
+            );
+
+            Ok(format!("{number:.decimals$}"))
+        }
+        None => Ok(number.round().to_string()),
+    }
+}
+
+pub fn floor(args: Vec<String>) -> anyhow::Result<String> {
+    parse_number(args.first()).map(|n| n.floor().to_string())
+}
+
+pub fn ceil(args: Vec<String>) -> anyhow::Result<String> {
+    parse_number(args.first()).map(|n| n.ceil().to_string())
+}
+
+/// Formats a number according to a pattern like `0,000.00`
+///
+/// The number of zeros before the decimal point is the minimum number of integer digits,
+/// the number of zeros after it is the number of decimals, and a comma enables thousands separators.
+pub fn format(args: Vec<String>) -> anyhow::Result<String> {
+    let pattern = args.first().context("Missing pattern argument")?.trim();
+    let number = parse_number(args.get(1))?;
+
+    ensure!(number.is_finite(), "Cannot format {number}");
+
+    let (integer_pattern, decimal_pattern) = pattern.split_once('.').unwrap_or((pattern, ""));
+    ensure!(
+        integer_pattern.chars().all(|c| c == '0' || c == ',')
+            && decimal_pattern.chars().all(|c| c == '0'),
+        "Invalid pattern: {pattern} (expected something like 0,000.00)"
+    );
+
+    let decimals = decimal_pattern.len();
+    let min_digits = integer_pattern.chars().filter(|&c| c == '0').count();
+    ensure!(
+        decimals <= limits::MAX_FORMAT_DECIMALS && min_digits <= limits::MAX_FORMAT_DECIMALS,
+        "Pattern cannot have more than {} digits on either side",
+        limits::MAX_FORMAT_DECIMALS
+    );
+
+    let formatted = format!("{:.decimals$}", number.abs());
+    let (integer, fraction) = formatted.split_once('.').unwrap_or((&formatted, ""));
+    let integer = format!("{integer:0>min_digits$}");
+
+    let mut output = String::new();
+    if number < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        output.push('-');
+    }
+
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && integer_pattern.contains(',') && (integer.len() - i) % 3 == 0 {
+            output.push(',');
+        }
+        output.push(digit);
+    }
+
+    if !fraction.is_empty() {
+        output.push('.');
+        output.push_str(fraction);
+    }
+
+    Ok(output)
+}
+
+/// Calls `f` with the embed of the response, creating it if it doesn't exist yet
+fn with_embed<T>(parser: &Parser, f: impl FnOnce(&mut Embed) -> T) -> T {
+    parser
+        .state()
+        .with_message_mut(|message| f(message.embed.get_or_insert_with(Embed::default)))
+}
+
+/// Ensures that a user provided embed text is not longer than `max` characters
+fn ensure_embed_text_length(what: &str, text: &str, max: usize) -> anyhow::Result<()> {
+    ensure!(
+        text.chars().count() <= max,
+        "Embed {what} cannot be longer than {max} characters"
+    );
+    Ok(())
+}
+
+pub fn embed(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let mut iter = args.into_iter();
+    let title = iter.next().context("Missing title argument")?;
+    let description = iter.next();
+
+    ensure_embed_text_length("title", &title, limits::MAX_EMBED_TITLE_LENGTH)?;
+    if let Some(description) = &description {
+        ensure_embed_text_length(
+            "description",
+            description,
+            limits::MAX_EMBED_DESCRIPTION_LENGTH,
+        )?;
+    }
+
+    with_embed(parser, |embed| {
+        embed.title = (!title.is_empty()).then_some(title);
+        if description.is_some() {
+            embed.description = description;
+        }
+    });
+
+    Ok(String::new())
+}
+
+pub fn embedfield(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let mut iter = args.into_iter();
+    let name = iter.next().context("Missing name argument")?;
+    let value = iter.next().context("Missing value argument")?;
+    let inline = iter.next().is_some_and(|inline| is_truthy(&inline));
+
+    ensure_embed_text_length("field name", &name, limits::MAX_EMBED_FIELD_NAME_LENGTH)?;
+    ensure_embed_text_length("field value", &value, limits::MAX_EMBED_FIELD_VALUE_LENGTH)?;
+
+    with_embed(parser, |embed| {
+        ensure!(
+            embed.fields.len() < limits::MAX_EMBED_FIELDS,
+            "Embeds cannot have more than {} fields",
+            limits::MAX_EMBED_FIELDS
+        );
+
+        embed.fields.push(EmbedField {
+            name,
+            value,
+            inline,
+        });
+        Ok(())
+    })?;
+
+    Ok(String::new())
+}
+
+pub fn embedcolor(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let color = args.first().context("Missing color argument")?.trim();
+
+    // accept both #rrggbb and plain decimal numbers
+    let parsed = match color.strip_prefix('#') {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => color.parse(),
+    };
+    let color = parsed
+        .ok()
+        .filter(|c| *c <= 0xffffff)
+        .with_context(|| format!("Invalid color: {color}"))?;
+
+    with_embed(parser, |embed| embed.color = Some(color));
+    Ok(String::new())
+}
+
+pub fn embedimage(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let url = args.first().context("Missing URL argument")?.trim();
+
+    ensure!(
+        url.starts_with("http://") || url.starts_with("https://"),
+        "Embed image must be an http or https URL"
+    );
+
+    with_embed(parser, |embed| embed.image = Some(url.to_owned()));
+    Ok(String::new())
+}
+
+pub fn reply(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let message_id = args
+        .first()
+        .map(|id| id.trim().parse::<u64>())
+        .transpose()
+        .context("Invalid message ID")?;
+
+    parser
+        .state()
+        .with_message_mut(|message| message.reply = ReplyMode::Reply(message_id));
+
+    Ok(String::new())
+}
+
+pub fn nomention(parser: &Parser) -> anyhow::Result<String> {
+    parser
+        .state()
+        .with_message_mut(|message| message.reply = ReplyMode::Disabled);
+
+    Ok(String::new())
+}
+
 pub fn r#if(parser: &mut Parser) -> anyhow::Result<String> {
     ensure!(parser.eat_separator(), "Missing statement argument");
     let mut stmt = parser.parse_segment(true)?;
@@ -253,30 +774,47 @@ pub fn r#if(parser: &mut Parser) -> anyhow::Result<String> {
         }
     }
 
-    fn eval_branch_with_i32s<F>(
+    fn eval_branch_with_f64s<F>(
         parser: &mut Parser,
         a: &str,
         b: &str,
         f: F,
     ) -> anyhow::Result<String>
     where
-        F: FnOnce(i32, i32) -> bool,
+        F: FnOnce(f64, f64) -> bool,
     {
-        let (a, b) = (a.parse()?, b.parse()?);
+        let (a, b) = (a.trim().parse()?, b.trim().parse()?);
         eval_branch(parser, f(a, b))
     }
 
+    /// Compares numerically if both sides are numbers, and as strings otherwise
+    fn loosely_equal(a: &str, b: &str) -> bool {
+        match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => a == b,
+        }
+    }
+
     let result = match comparison.as_str() {
         "=" => eval_branch(parser, stmt == value),
-        ">" => eval_branch_with_i32s(parser, &stmt, &value, |a, b| a > b),
-        ">=" => eval_branch_with_i32s(parser, &stmt, &value, |a, b| a >= b),
-        "<" => eval_branch_with_i32s(parser, &stmt, &value, |a, b| a < b),
-        "<=" => eval_branch_with_i32s(parser, &stmt, &value, |a, b| a <= b),
+        "==" => eval_branch(parser, loosely_equal(&stmt, &value)),
+        "!=" => eval_branch(parser, !loosely_equal(&stmt, &value)),
+        ">" => eval_branch_with_f64s(parser, &stmt, &value, |a, b| a > b),
+        ">=" => eval_branch_with_f64s(parser, &stmt, &value, |a, b| a >= b),
+        "<" => eval_branch_with_f64s(parser, &stmt, &value, |a, b| a < b),
+        "<=" => eval_branch_with_f64s(parser, &stmt, &value, |a, b| a <= b),
         "~" => {
             stmt.make_ascii_lowercase();
             value.make_ascii_lowercase();
             eval_branch(parser, stmt == value)
         }
+        "contains" => eval_branch(parser, stmt.contains(&value)),
+        "startswith" => eval_branch(parser, stmt.starts_with(&value)),
+        "endswith" => eval_branch(parser, stmt.ends_with(&value)),
+        "regex" => {
+            let regex = build_regex(&value)?;
+            eval_branch(parser, regex.is_match(&stmt))
+        }
         _ => bail!("Invalid comparison operator {comparison}"),
     };
 
@@ -284,6 +822,119 @@ pub fn r#if(parser: &mut Parser) -> anyhow::Result<String> {
     result
 }
 
+/// Returns whether a loop condition is "truthy"
+///
+/// Anything other than an empty string, `0` or `false` is considered true
+fn is_truthy(value: &str) -> bool {
+    !matches!(value.trim(), "" | "0" | "false")
+}
+
+/// Evaluates the loop body starting at `body_start` once and appends it to `output`
+///
+/// The parser is left at the end of the body, so callers can check for the closing brace
+fn eval_loop_body(
+    parser: &mut Parser,
+    body_start: usize,
+    output: &mut String,
+) -> anyhow::Result<()> {
+    parser.seek(body_start);
+    let result = parser.parse_segment(true)?;
+
+    ensure!(
+        output.len() + result.len() < MAX_STRING_LENGTH,
+        "Output string exceeds maximum string length of {MAX_STRING_LENGTH} bytes"
+    );
+
+    output.push_str(&result);
+    Ok(())
+}
+
+pub fn r#for(parser: &mut Parser) -> anyhow::Result<String> {
+    ensure!(parser.eat_separator(), "Missing variable argument");
+    let variable = parser.parse_segment(true)?;
+
+    ensure!(parser.eat_separator(), "Missing start argument");
+    let start = parser.parse_segment(true)?.trim().parse::<i64>()?;
+
+    ensure!(parser.eat_separator(), "Missing end argument");
+    let end = parser.parse_segment(true)?.trim().parse::<i64>()?;
+
+    ensure!(parser.eat_separator(), "Missing body argument");
+    let body_start = parser.position();
+
+    let iterations = start.abs_diff(end) + 1;
+    ensure!(
+        iterations <= limits::MAX_ITERATIONS as u64,
+        "Loop exceeds maximum number of iterations ({})",
+        limits::MAX_ITERATIONS
+    );
+
+    let step = if start <= end { 1 } else { -1 };
+    let mut output = String::new();
+    let mut i = start;
+
+    for _ in 0..iterations {
+        set_variable(parser, variable.clone(), i.to_string())?;
+        eval_loop_body(parser, body_start, &mut output)?;
+        i += step;
+    }
+
+    try_eat_closing_brace(parser)?;
+    Ok(output)
+}
+
+pub fn foreach(parser: &mut Parser) -> anyhow::Result<String> {
+    ensure!(parser.eat_separator(), "Missing variable argument");
+    let variable = parser.parse_segment(true)?;
+
+    ensure!(parser.eat_separator(), "Missing list argument");
+    let list = parser.parse_segment(true)?;
+
+    ensure!(parser.eat_separator(), "Missing body argument");
+    let body_start = parser.position();
+
+    let mut output = String::new();
+    let mut empty = true;
+
+    for item in list.split(',').filter(|item| !item.is_empty()) {
+        empty = false;
+        set_variable(parser, variable.clone(), item.to_owned())?;
+        eval_loop_body(parser, body_start, &mut output)?;
+    }
+
+    if empty {
+        // the body was never evaluated, so it needs to be skipped
+        parser.parse_segment(false)?;
+    }
+
+    try_eat_closing_brace(parser)?;
+    Ok(output)
+}
+
+pub fn r#while(parser: &mut Parser) -> anyhow::Result<String> {
+    ensure!(parser.eat_separator(), "Missing condition argument");
+    let condition_start = parser.position();
+
+    let mut output = String::new();
+
+    loop {
+        parser.seek(condition_start);
+        let condition = parser.parse_segment(true)?;
+        ensure!(parser.eat_separator(), "Missing body argument");
+
+        if !is_truthy(&condition) {
+            parser.parse_segment(false)?;
+            break;
+        }
+
+        let body_start = parser.position();
+        eval_loop_body(parser, body_start, &mut output)?;
+    }
+
+    try_eat_closing_brace(parser)?;
+    Ok(output)
+}
+
 pub fn note(parser: &mut Parser) -> anyhow::Result<String> {
     if parser.eat_separator() {
         parser.parse_segment(false)?;
@@ -372,6 +1023,78 @@ pub fn userid(parser: &Parser) -> anyhow::Result<String> {
     parser.context().user_id().map(|id| id.to_string())
 }
 
+pub fn guildid(parser: &Parser) -> anyhow::Result<String> {
+    parser.context().guild_id().map(|id| id.to_string())
+}
+
+pub fn guildname(parser: &Parser) -> anyhow::Result<String> {
+    ensure_request_limit!(parser);
+
+    parser.context().get_guild().map(|g| g.name)
+}
+
+pub fn membercount(parser: &Parser) -> anyhow::Result<String> {
+    ensure_request_limit!(parser);
+
+    parser
+        .context()
+        .get_guild()
+        .map(|g| g.member_count.to_string())
+}
+
+pub fn channelname(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    ensure_request_limit!(parser);
+
+    let channel_id = args.first().map(|s| s.trim().parse()).transpose()?;
+    parser.context().get_channel_name(channel_id)
+}
+
+/// Looks up the member with the ID in the given argument, or the message author if there is none
+fn member_arg(parser: &Parser, args: &[String], index: usize) -> anyhow::Result<Member> {
+    ensure_request_limit!(parser);
+
+    let user_id = args
+        .get(index)
+        .map(|s| s.trim().parse().context("Invalid user ID"))
+        .transpose()?;
+
+    parser.context().get_member(user_id)
+}
+
+pub fn username(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    member_arg(parser, &args, 0).map(|m| m.username)
+}
+
+pub fn nickname(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let member = member_arg(parser, &args, 0)?;
+    Ok(member.nickname.unwrap_or(member.username))
+}
+
+pub fn roles(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let member = member_arg(parser, &args, 0)?;
+
+    Ok(member
+        .roles
+        .into_iter()
+        .map(|r| r.name)
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+pub fn hasrole(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
+    let role = args.get(1).context("Missing role argument")?.trim();
+    let member = member_arg(parser, &args, 0)?;
+
+    // the role can be given by ID or by name
+    let role_id = role.parse::<u64>().ok();
+    let has_role = member
+        .roles
+        .iter()
+        .any(|r| Some(r.id) == role_id || r.name.eq_ignore_ascii_case(role));
+
+    Ok(has_role.to_string())
+}
+
 pub fn idof(parser: &Parser, args: Vec<String>) -> anyhow::Result<String> {
     let mention = args.first().context("Missing mention argument")?;
     match assyst_common::util::mention_to_id(mention) {
@@ -389,9 +1112,12 @@ pub fn tag(parser: &mut Parser, args: Vec<String>) -> anyhow::Result<String> {
         "Maximum recursion depth reached ({MAX_DEPTH})"
     );
 
-    let tag_content = parser.context().get_tag_contents(name)?;
+    // variables are scoped to the name the tag resolves to, so an alias shares them with its tag
+    let tag = parser.context().get_tag(name)?;
 
     let args = args.iter().map(|s| s.as_str()).collect::<Vec<_>>();
 
-    Parser::from_parent_with_args(tag_content.as_bytes(), parser, &args).parse_segment(true)
+    Parser::from_parent_with_args(tag.contents.as_bytes(), parser, &args)
+        .with_tag_name(&tag.name)
+        .parse_segment(true)
 }
@@ -0,0 +1,136 @@
+use std::fmt;
+
+use crate::parser::{find_subtag, is_identifier, Subtag};
+
+/// A problem found in the source of a tag
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    /// Byte offset in the tag source where the problem was found
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "byte {}: {}", self.offset, self.message)
+    }
+}
+
+/// Checks the source of a tag for problems, without evaluating anything
+///
+/// This reports unknown subtags, unbalanced braces and wrong argument counts.
+/// Subtags and their argument counts are looked up in the same table that the parser dispatches from.
+pub fn validate(input: &str) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        input: input.as_bytes(),
+        idx: 0,
+        diagnostics: Vec::new(),
+    };
+
+    validator.validate_segment(true, true);
+    validator.diagnostics
+}
+
+struct Validator<'a> {
+    input: &'a [u8],
+    idx: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, offset: usize, message: String) {
+        self.diagnostics.push(Diagnostic { offset, message });
+    }
+
+    /// Walks a single segment, stopping before the `|` or `}` that ends it
+    ///
+    /// If `check` is false, subtags in this segment are not checked, because the parser will not evaluate them.
+    fn validate_segment(&mut self, top_level: bool, check: bool) {
+        while let Some(&byte) = self.input.get(self.idx) {
+            match byte {
+                b'\\' if matches!(self.input.get(self.idx + 1), Some(b'|' | b'}' | b'{')) => {
+                    self.idx += 2;
+                }
+                b'{' => self.validate_subtag(check),
+                b'|' | b'}' if top_level => {
+                    if check {
+                        self.report(
+                            self.idx,
+                            format!(
+                                "Unescaped `{}` ends the tag output early, use `\\{}` to escape it",
+                                byte as char, byte as char
+                            ),
+                        );
+                    }
+                    self.idx += 1;
+                }
+                b'|' | b'}' => return,
+                _ => self.idx += 1,
+            }
+        }
+    }
+
+    fn validate_subtag(&mut self, check: bool) {
+        let start = self.idx;
+        self.idx += 1;
+
+        let name_start = self.idx;
+        while matches!(self.input.get(self.idx), Some(&b) if is_identifier(b)) {
+            self.idx += 1;
+        }
+        // identifiers are always ascii
+        let name = std::str::from_utf8(&self.input[name_start..self.idx]).unwrap();
+
+        let subtag = find_subtag(name);
+        let check_args = check && !subtag.is_some_and(Subtag::skips_args);
+
+        if check {
+            if name.is_empty() {
+                self.report(start, "Subtag name is empty".to_owned());
+            } else if subtag.is_none() {
+                self.report(start, format!("Unknown subtag `{name}`"));
+            }
+        }
+
+        let mut argc = 0;
+        while let Some(b'|' | b':') = self.input.get(self.idx) {
+            self.idx += 1;
+            argc += 1;
+            self.validate_segment(false, check_args);
+        }
+
+        match self.input.get(self.idx) {
+            Some(b'}') => self.idx += 1,
+            Some(_) => {
+                if check {
+                    self.report(
+                        self.idx,
+                        format!("Expected `:`, `|` or `}}` after subtag name `{name}`"),
+                    );
+                }
+                self.idx += 1;
+            }
+            None => {
+                if check {
+                    self.report(start, format!("Subtag `{name}` is missing a closing brace"));
+                }
+                return;
+            }
+        }
+
+        if let (true, Some(subtag)) = (check, subtag) {
+            let (min, max) = (subtag.min_args, subtag.max_args);
+            if argc < min {
+                self.report(
+                    start,
+                    format!("Subtag `{name}` expects at least {min} arguments, but got {argc}"),
+                );
+            } else if let Some(max) = max.filter(|max| argc > *max) {
+                self.report(
+                    start,
+                    format!("Subtag `{name}` expects at most {max} arguments, but got {argc}"),
+                );
+            }
+        }
+    }
+}